@@ -0,0 +1,76 @@
+//! Compare the allocations made while streaming files with and without a
+//! `BufferPool`. Run with `cargo run --release --example file_buffer_pool
+//! --features full`.
+
+use futures::StreamExt;
+use saphir::file::{buffer_pool::BufferPool, FileCursor, FileStream, MAX_BUFFER};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const REQUESTS: usize = 10_000;
+
+async fn serve_files(content: &[u8], pool: Option<&BufferPool>) -> (usize, usize, u128) {
+    let (allocations, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed));
+    let start = Instant::now();
+
+    for _ in 0..REQUESTS {
+        let file = FileCursor::new(content.to_vec(), None, PathBuf::from("asset.js"));
+        let mut stream = FileStream::new(file);
+        if let Some(pool) = pool {
+            stream = stream.with_buffer_pool(pool.clone());
+        }
+        while let Some(chunk) = stream.next().await {
+            chunk.unwrap();
+        }
+    }
+
+    (
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes,
+        start.elapsed().as_millis(),
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    // A small static asset, the read buffer dominates the allocations
+    let content = vec![b'a'; 4096];
+    let pool = BufferPool::new(64);
+
+    for (name, pool) in &[("without pool", None), ("with pool", Some(&pool))] {
+        let (allocations, bytes, elapsed) = serve_files(&content, *pool).await;
+        println!(
+            "{:>12}: {} allocations, {} KiB allocated per request, {} ms for {} requests (buffer of {} bytes)",
+            name,
+            allocations / REQUESTS,
+            bytes / REQUESTS / 1024,
+            elapsed,
+            REQUESTS,
+            MAX_BUFFER
+        );
+    }
+}
@@ -78,6 +78,12 @@ impl UserController {
             Err(_) => (500, None),
         }
     }
+
+    #[get("/agent")]
+    async fn user_agent(&self, headers: Headers) -> (u16, String) {
+        let agent = headers.get("user-agent").and_then(|h| h.to_str().ok()).unwrap_or("unknown");
+        (200, format!("Requested with {}", agent))
+    }
 }
 
 struct ApiKeyMiddleware(String);
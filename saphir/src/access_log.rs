@@ -0,0 +1,444 @@
+//! Logging of the requests handled, one line per request.
+//!
+//! The `AccessLogMiddleware` writes a line through the `log` crate for every
+//! request once it is answered, in the `LogFormat` fitting the pipeline that
+//! collects the logs.
+
+use crate::{
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+};
+use futures::{future::BoxFuture, FutureExt};
+use http::{Method, Version};
+use log::Level;
+use std::{
+    fmt::Write,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_TARGET: &str = "saphir::access";
+
+/// What is logged about a request
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry {
+    /// When the request was received
+    pub time: SystemTime,
+    /// Address of the client, see `HttpContext::client_addr`
+    pub client_addr: Option<IpAddr>,
+    pub method: Method,
+    /// Path and query of the request
+    pub uri: String,
+    pub version: Version,
+    pub status: u16,
+    /// `Content-Length` of the response, unknown for streamed bodies
+    pub response_size: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// Time taken to produce the response, not including sending its body
+    pub duration: Duration,
+}
+
+/// Renders an `AccessLogEntry` as a line of log
+pub type LogFormatter = Arc<dyn Fn(&AccessLogEntry) -> String + Send + Sync>;
+
+/// Format of the access log lines, `AccessLogMiddleware` uses `Human` by
+/// default
+#[derive(Clone)]
+pub enum LogFormat {
+    /// `GET /users?page=2 HTTP/1.1 200 1534B 12.5ms 127.0.0.1`
+    Human,
+    /// One JSON object per line, with the fields of `AccessLogEntry`
+    Json,
+    /// `key=value` pairs, values with spaces or quotes being quoted
+    Logfmt,
+    /// The Apache and nginx combined log format
+    ApacheCombined,
+    /// Lines rendered by the closure
+    Custom(LogFormatter),
+}
+
+impl LogFormat {
+    pub fn custom<F: 'static + Fn(&AccessLogEntry) -> String + Send + Sync>(formatter: F) -> Self {
+        LogFormat::Custom(Arc::new(formatter))
+    }
+
+    /// Render `entry` as a line of log
+    pub fn format(&self, entry: &AccessLogEntry) -> String {
+        let client_addr = entry.client_addr.map(|addr| addr.to_string());
+        match self {
+            LogFormat::Human => {
+                let size = entry.response_size.map(|size| format!("{}B", size)).unwrap_or_else(|| "-".to_string());
+                format!(
+                    "{} {} {:?} {} {} {} {}",
+                    entry.method,
+                    entry.uri,
+                    entry.version,
+                    entry.status,
+                    size,
+                    millis(entry.duration),
+                    client_addr.as_deref().unwrap_or("-")
+                )
+            }
+            LogFormat::Json => {
+                let mut line = String::from("{");
+                let _ = write!(line, "\"time\":{},", json_string(Some(&rfc3339(entry.time))));
+                let _ = write!(line, "\"client_addr\":{},", json_string(client_addr.as_deref()));
+                let _ = write!(line, "\"method\":{},", json_string(Some(entry.method.as_str())));
+                let _ = write!(line, "\"uri\":{},", json_string(Some(&entry.uri)));
+                let _ = write!(line, "\"version\":{},", json_string(Some(&format!("{:?}", entry.version))));
+                let _ = write!(line, "\"status\":{},", entry.status);
+                let _ = match entry.response_size {
+                    Some(size) => write!(line, "\"response_size\":{},", size),
+                    None => write!(line, "\"response_size\":null,"),
+                };
+                let _ = write!(line, "\"referer\":{},", json_string(entry.referer.as_deref()));
+                let _ = write!(line, "\"user_agent\":{},", json_string(entry.user_agent.as_deref()));
+                let _ = write!(line, "\"duration_ms\":{}}}", entry.duration.as_secs_f64() * 1000.0);
+                line
+            }
+            LogFormat::Logfmt => {
+                let fields = [
+                    ("time", Some(rfc3339(entry.time))),
+                    ("client_addr", client_addr),
+                    ("method", Some(entry.method.to_string())),
+                    ("uri", Some(entry.uri.clone())),
+                    ("version", Some(format!("{:?}", entry.version))),
+                    ("status", Some(entry.status.to_string())),
+                    ("response_size", entry.response_size.map(|size| size.to_string())),
+                    ("referer", entry.referer.clone()),
+                    ("user_agent", entry.user_agent.clone()),
+                    ("duration", Some(millis(entry.duration))),
+                ];
+                let fields: Vec<String> = fields
+                    .iter()
+                    .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}={}", key, logfmt_value(value))))
+                    .collect();
+                fields.join(" ")
+            }
+            LogFormat::ApacheCombined => format!(
+                "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\"",
+                client_addr.as_deref().unwrap_or("-"),
+                apache_time(entry.time),
+                entry.method,
+                entry.uri,
+                entry.version,
+                entry.status,
+                entry.response_size.map(|size| size.to_string()).unwrap_or_else(|| "-".to_string()),
+                apache_escape(entry.referer.as_deref().unwrap_or("-")),
+                apache_escape(entry.user_agent.as_deref().unwrap_or("-")),
+            ),
+            LogFormat::Custom(formatter) => formatter(entry),
+        }
+    }
+}
+
+/// Middleware logging every request it is applied to once answered, at the
+/// `info` level and under the `saphir::access` target by default.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::access_log::{AccessLogMiddleware, LogFormat};
+/// let access_log = AccessLogMiddleware::new().format(LogFormat::ApacheCombined);
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(access_log, vec!["/**"], None))
+///     .build();
+/// ```
+pub struct AccessLogMiddleware {
+    format: LogFormat,
+    level: Level,
+    target: String,
+}
+
+impl Default for AccessLogMiddleware {
+    fn default() -> Self {
+        AccessLogMiddleware {
+            format: LogFormat::Human,
+            level: Level::Info,
+            target: DEFAULT_TARGET.to_string(),
+        }
+    }
+}
+
+impl AccessLogMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Target of the log records, so the access log can be routed apart
+    /// from the other logs
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = target.to_string();
+        self
+    }
+
+    async fn next_inner(&self, ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let (ctx, entry) = self.handle(ctx, chain).await?;
+        log!(target: &self.target, self.level, "{}", self.format.format(&entry));
+        ctx
+    }
+
+    /// Pass the request down the chain, gathering its entry. The requests the
+    /// chain fails are logged with a `500`
+    async fn handle(&self, ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<(Result<HttpContext, SaphirError>, AccessLogEntry), SaphirError> {
+        let started = Instant::now();
+        let time = SystemTime::now();
+        let req = ctx.state.request().ok_or(SaphirError::RequestMovedBeforeHandler)?;
+        let header = |name: http::header::HeaderName| req.headers().get(name).and_then(|h| h.to_str().ok()).map(|h| h.to_string());
+        let mut entry = AccessLogEntry {
+            time,
+            client_addr: ctx.client_addr(),
+            method: req.method().clone(),
+            uri: req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/").to_string(),
+            version: req.version(),
+            status: 0,
+            response_size: None,
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+            duration: Duration::default(),
+        };
+
+        let ctx = chain.next(ctx).await;
+        entry.duration = started.elapsed();
+        let res = match ctx.as_ref() {
+            Ok(ctx) => ctx.state.response(),
+            Err(_) => {
+                entry.status = 500;
+                None
+            }
+        };
+        if let Some(res) = res {
+            entry.status = res.status().as_u16();
+            entry.response_size = res
+                .headers()
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse().ok());
+        }
+        Ok((ctx, entry))
+    }
+}
+
+impl Middleware for AccessLogMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+/// Milliseconds of `duration` with a `ms` unit, at most 3 decimals
+fn millis(duration: Duration) -> String {
+    let millis = format!("{:.3}", duration.as_secs_f64() * 1000.0);
+    format!("{}ms", millis.trim_end_matches('0').trim_end_matches('.'))
+}
+
+fn json_string(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "null".to_string(),
+    };
+
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty() && !value.chars().any(|c| c == ' ' || c == '"' || c == '=' || c.is_control()) {
+        return value.to_string();
+    }
+    json_string(Some(value))
+}
+
+/// Escape the quotes and control characters of a quoted field of the
+/// combined log format
+fn apache_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// UTC calendar date and time of `time`: year, month from 1, day, hour,
+/// minute, second
+fn civil(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400) as u32);
+
+    // Days since the epoch to a proleptic Gregorian date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(time);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+fn apache_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, request::Request, router::Router};
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            client_addr: Some("127.0.0.1".parse().unwrap()),
+            method: Method::GET,
+            uri: "/apache_pb.gif?size=2".to_string(),
+            version: Version::HTTP_11,
+            status: 200,
+            response_size: Some(2326),
+            referer: Some("http://www.example.com/start.html".to_string()),
+            user_agent: Some("Mozilla/4.08 [en] (Win98; \"I\")".to_string()),
+            duration: Duration::from_micros(12_500),
+        }
+    }
+
+    #[test]
+    fn entry_renders_in_each_format() {
+        assert_eq!(
+            LogFormat::Human.format(&entry()),
+            "GET /apache_pb.gif?size=2 HTTP/1.1 200 2326B 12.5ms 127.0.0.1"
+        );
+        assert_eq!(
+            LogFormat::Json.format(&entry()),
+            "{\"time\":\"2000-10-10T13:55:36Z\",\"client_addr\":\"127.0.0.1\",\"method\":\"GET\",\"uri\":\"/apache_pb.gif?size=2\",\
+             \"version\":\"HTTP/1.1\",\"status\":200,\"response_size\":2326,\"referer\":\"http://www.example.com/start.html\",\
+             \"user_agent\":\"Mozilla/4.08 [en] (Win98; \\\"I\\\")\",\"duration_ms\":12.5}"
+        );
+        assert_eq!(
+            LogFormat::Logfmt.format(&entry()),
+            "time=2000-10-10T13:55:36Z client_addr=127.0.0.1 method=GET uri=\"/apache_pb.gif?size=2\" version=HTTP/1.1 status=200 \
+             response_size=2326 referer=http://www.example.com/start.html user_agent=\"Mozilla/4.08 [en] (Win98; \\\"I\\\")\" duration=12.5ms"
+        );
+        assert_eq!(
+            LogFormat::ApacheCombined.format(&entry()),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?size=2 HTTP/1.1\" 200 2326 \
+             \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; \\\"I\\\")\""
+        );
+        assert_eq!(
+            LogFormat::custom(|e| format!("{} {}", e.status, e.uri)).format(&entry()),
+            "200 /apache_pb.gif?size=2"
+        );
+
+        let unknown = AccessLogEntry {
+            client_addr: None,
+            response_size: None,
+            referer: None,
+            user_agent: None,
+            ..entry()
+        };
+        assert_eq!(
+            LogFormat::ApacheCombined.format(&unknown),
+            "- - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?size=2 HTTP/1.1\" 200 - \"-\" \"-\""
+        );
+        assert_eq!(
+            LogFormat::Logfmt.format(&unknown),
+            "time=2000-10-10T13:55:36Z method=GET uri=\"/apache_pb.gif?size=2\" version=HTTP/1.1 status=200 duration=12.5ms"
+        );
+    }
+
+    #[test]
+    fn dates_are_rendered_in_utc() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(apache_time(UNIX_EPOCH + Duration::from_secs(951_782_400)), "29/Feb/2000:00:00:00 +0000");
+    }
+
+    #[tokio::test]
+    async fn answered_requests_are_logged() {
+        let router = Router::builder().route("/users", Method::POST, |_req: Request<Body>| async { 201 }).build();
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .uri("/users?page=2")
+            .header(http::header::USER_AGENT, "curl/7.68.0")
+            .body(Body::empty())
+            .unwrap();
+        let ctx = HttpContext::new(Request::new(req, None), router);
+
+        let (ctx, entry) = AccessLogMiddleware::new().handle(ctx, &MiddleChainEnd).await.unwrap();
+        assert_eq!(ctx.unwrap().state.take_response().unwrap().status(), 201);
+        assert_eq!(entry.method, Method::POST);
+        assert_eq!(entry.uri, "/users?page=2");
+        assert_eq!(entry.status, 201);
+        assert_eq!(entry.user_agent.as_deref(), Some("curl/7.68.0"));
+        assert_eq!(entry.referer, None);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_logged() {
+        struct Failing;
+
+        impl MiddlewareChain for Failing {
+            fn next(&self, _ctx: HttpContext) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+                async { Err(SaphirError::Other("unreachable upstream".to_string())) }.boxed()
+            }
+        }
+
+        let req = http::Request::builder().uri("/users").body(Body::empty()).unwrap();
+        let ctx = HttpContext::new(Request::new(req, None), Router::builder().build());
+
+        let (ctx, entry) = AccessLogMiddleware::new().handle(ctx, &Failing).await.unwrap();
+        assert!(matches!(ctx, Err(SaphirError::Other(_))));
+        assert_eq!(entry.uri, "/users");
+        assert_eq!(entry.status, 500);
+        assert_eq!(entry.response_size, None);
+    }
+}
@@ -1,6 +1,6 @@
 #![allow(clippy::type_complexity)]
 
-use crate::error::SaphirError;
+use crate::error::{ExtractError, SaphirError};
 use futures::{
     task::{Context, Poll},
     Future,
@@ -15,15 +15,31 @@ pub use hyper::body::Bytes;
 #[cfg(feature = "form")]
 pub use form::Form;
 #[cfg(feature = "json")]
-pub use json::Json;
-use std::ops::DerefMut;
-use tokio::stream::StreamExt;
+pub use json::{Json, JsonConfig};
+use parking_lot::Mutex;
+use std::{
+    ops::DerefMut,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 #[doc(hidden)]
 pub(crate) static mut REQUEST_BODY_BYTES_LIMIT: Option<usize> = None;
 
+/// Most memory allocated up front for a body from its declared length. Past
+/// it, the buffer grows as the data arrives, so a client can't make the
+/// server allocate memory by merely announcing a large body.
+const MAX_BODY_PREALLOCATION: usize = 1024 * 1024;
+
+/// A body streamed through a wrapper, e.g. the request body counted and
+/// tracked by the server, whose size hint and trailers stay visible
+pub(crate) type WrappedBody = Pin<Box<dyn HttpBody<Data = Bytes, Error = SaphirError> + Send + Sync>>;
+
 pub(crate) enum BodyInner {
     Raw(RawBody),
+    Wrapped(WrappedBody),
     Memory(Bytes),
 }
 
@@ -41,23 +57,36 @@ impl BodyInner {
     pub(crate) fn into_raw(self) -> RawBody {
         match self {
             BodyInner::Raw(r) => r,
+            // Hyper bodies don't carry trailers, only the data makes it through
+            BodyInner::Wrapped(mut w) => RawBody::wrap_stream(futures::stream::poll_fn(move |cx| w.as_mut().poll_data(cx))),
             BodyInner::Memory(b) => RawBody::from(b),
         }
     }
 
-    pub async fn load(self) -> Result<Bytes, SaphirError> {
+    /// Buffer the whole body, counting what is buffered against `budget`.
+    /// With the length the body declared, see `read_body_limited`
+    pub async fn load(self, budget: Option<MemoryBudget>, length_hint: Option<u64>) -> Result<Bytes, SaphirError> {
+        let reserve = |chunk: &Bytes| match budget.as_ref() {
+            Some(budget) => budget.reserve(chunk.len()),
+            None => Ok(()),
+        };
         unsafe {
             if let Some(0) = REQUEST_BODY_BYTES_LIMIT {
                 return Ok(Bytes::new());
             }
         }
-        match self {
-            BodyInner::Raw(mut r) => {
-                let first = if let Some(buf) = r.next().await.transpose().map_err(SaphirError::from)? {
+        match (self, length_hint) {
+            (BodyInner::Memory(b), _) => Ok(b),
+            (mut r, Some(length)) => read_body_limited(&mut r, length, unsafe { REQUEST_BODY_BYTES_LIMIT }, budget.as_ref())
+                .await
+                .map(Bytes::from),
+            (mut r, None) => {
+                let first = if let Some(buf) = r.data().await.transpose()? {
                     buf
                 } else {
                     return Ok(Bytes::new());
                 };
+                reserve(&first)?;
 
                 unsafe {
                     if REQUEST_BODY_BYTES_LIMIT.as_ref().filter(|p| first.len() >= **p).is_some() {
@@ -65,11 +94,12 @@ impl BodyInner {
                     }
                 }
 
-                let second = if let Some(buf) = r.next().await.transpose().map_err(SaphirError::from)? {
+                let second = if let Some(buf) = r.data().await.transpose()? {
                     buf
                 } else {
                     return Ok(first);
                 };
+                reserve(&second)?;
 
                 let cap = first.remaining() + second.remaining() + r.size_hint().lower() as usize;
                 let mut vec = Vec::with_capacity(cap);
@@ -82,7 +112,8 @@ impl BodyInner {
                     }
                 }
 
-                while let Some(buf) = r.next().await.transpose().map_err(SaphirError::from)? {
+                while let Some(buf) = r.data().await.transpose()? {
+                    reserve(&buf)?;
                     vec.extend_from_slice(buf.as_ref());
                     unsafe {
                         if REQUEST_BODY_BYTES_LIMIT.as_ref().filter(|p| vec.len() >= **p).is_some() {
@@ -93,11 +124,49 @@ impl BodyInner {
 
                 Ok(vec.into())
             }
-            BodyInner::Memory(b) => Ok(b),
         }
     }
 }
 
+/// Buffer `body`, of a declared length of `length` bytes, in a single
+/// allocation up to `MAX_BODY_PREALLOCATION`. Before reading anything, the
+/// declared length is checked against `limit`, the body limit of the server,
+/// and counted against `budget`, failing right away with
+/// `ExtractError::TooLarge` when it doesn't fit. The bytes sent past it are
+/// counted as they arrive, and like the body of an unknown length, it stops
+/// once going past `limit`.
+pub(crate) async fn read_body_limited<B>(body: &mut B, length: u64, limit: Option<usize>, budget: Option<&MemoryBudget>) -> Result<Vec<u8>, SaphirError>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    SaphirError: From<B::Error>,
+{
+    let length = std::convert::TryFrom::try_from(length).unwrap_or(usize::MAX);
+    if let Some(limit) = limit.filter(|limit| length > *limit) {
+        return Err(ExtractError::TooLarge { limit }.into());
+    }
+    let mut reserved = length;
+    if let Some(budget) = budget {
+        budget.reserve(reserved)?;
+    }
+
+    let mut vec = Vec::with_capacity(reserved.min(MAX_BODY_PREALLOCATION));
+    while let Some(buf) = body.data().await.transpose().map_err(SaphirError::from)? {
+        let len = vec.len() + buf.len();
+        if len > reserved {
+            if let Some(budget) = budget {
+                budget.reserve(len - reserved)?;
+            }
+            reserved = len;
+        }
+        vec.extend_from_slice(buf.as_ref());
+        if limit.filter(|limit| vec.len() >= *limit).is_some() {
+            break;
+        }
+    }
+
+    Ok(vec)
+}
+
 impl<T> From<T> for BodyInner
 where
     T: Into<RawBody>,
@@ -113,6 +182,73 @@ where
 {
     inner: Option<BodyInner>,
     fut: Option<Pin<Box<dyn Future<Output = Result<(T::Out, Bytes), SaphirError>> + Send + Sync + 'static>>>,
+    budget: Option<MemoryBudget>,
+    length_hint: Option<u64>,
+}
+
+/// Memory a request may buffer, see
+/// `ListenerBuilder::request_memory_limit`. The extractors buffering the
+/// request body count it against the budget of the request, and so does the
+/// response of the handler when it is held in memory, unless it carries the
+/// `SharedBody` extension. Handlers buffering data of their own can count it
+/// with `reserve`.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    inner: Arc<MemoryBudgetInner>,
+}
+
+/// Response extension marking a body shared with other responses, like the
+/// ones replayed by a cache. It is held in memory once for all of them, out of
+/// the `MemoryBudget` of the requests
+#[derive(Clone, Copy, Debug)]
+pub struct SharedBody;
+
+#[derive(Debug)]
+struct MemoryBudgetInner {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            inner: Arc::new(MemoryBudgetInner {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Bytes counted so far
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::SeqCst)
+    }
+
+    /// Count `bytes` more buffered bytes, failing with
+    /// `ExtractError::TooLarge` when they don't fit in the budget.
+    /// Nothing is counted on failure
+    pub fn reserve(&self, bytes: usize) -> Result<(), SaphirError> {
+        let limit = self.inner.limit;
+        self.inner
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|total| *total <= limit)
+            })
+            .map(|_| ())
+            .map_err(|_| SaphirError::Extract(ExtractError::TooLarge { limit }))
+    }
+
+    /// Give back `bytes` counted by `reserve`, once they are freed
+    pub fn release(&self, bytes: usize) {
+        let _ = self
+            .inner
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+    }
 }
 
 impl Body<Bytes> {
@@ -120,8 +256,22 @@ impl Body<Bytes> {
         Body {
             inner: Some(BodyInner::empty()),
             fut: None,
+            budget: None,
+            length_hint: None,
         }
     }
+
+    /// Stream this body through the body `wrap` makes of it, keeping the
+    /// memory budget and length hint of this one for the extractors
+    pub(crate) fn wrapped<B, F>(mut self, wrap: F) -> Self
+    where
+        B: 'static + HttpBody<Data = Bytes, Error = SaphirError> + Send + Sync,
+        F: FnOnce(Self) -> B,
+    {
+        let budget = self.budget.take();
+        let length_hint = self.length_hint;
+        Body::from_http_body(wrap(self)).with_memory_budget(budget).with_length_hint(length_hint)
+    }
 }
 
 impl<T: 'static> Body<T>
@@ -129,8 +279,8 @@ where
     T: FromBytes,
 {
     #[inline]
-    pub(crate) async fn generate(inner: BodyInner) -> Result<(T::Out, Bytes), SaphirError> {
-        T::from_bytes(inner.load().await?)
+    pub(crate) async fn generate(inner: BodyInner, budget: Option<MemoryBudget>, length_hint: Option<u64>) -> Result<(T::Out, Bytes), SaphirError> {
+        T::from_bytes(inner.load(budget, length_hint).await?)
     }
 
     #[inline]
@@ -138,9 +288,38 @@ where
         Body {
             inner: Some(BodyInner::from_raw(raw)),
             fut: None,
+            budget: None,
+            length_hint: None,
         }
     }
 
+    /// Stream `body`, forwarding its size hint and trailers, which a
+    /// `RawBody` wrapping it would lose
+    #[inline]
+    pub(crate) fn from_http_body<B: 'static + HttpBody<Data = Bytes, Error = SaphirError> + Send + Sync>(body: B) -> Self {
+        Body {
+            inner: Some(BodyInner::Wrapped(Box::pin(body))),
+            fut: None,
+            budget: None,
+            length_hint: None,
+        }
+    }
+
+    /// Count what the extractors buffer from this body against `budget`
+    #[inline]
+    pub(crate) fn with_memory_budget(mut self, budget: Option<MemoryBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Let the extractors buffer this body in a single allocation of the
+    /// `Content-Length` the request declared
+    #[inline]
+    pub(crate) fn with_length_hint(mut self, length_hint: Option<u64>) -> Self {
+        self.length_hint = length_hint;
+        self
+    }
+
     #[inline]
     pub(crate) fn into_raw(self) -> RawBody {
         self.inner.unwrap_or_else(BodyInner::empty).into_raw()
@@ -153,6 +332,8 @@ where
         Body {
             inner: self.inner.take(),
             fut: None,
+            budget: self.budget.clone(),
+            length_hint: self.length_hint,
         }
     }
 
@@ -163,13 +344,20 @@ where
         Body {
             inner: self.inner.take(),
             fut: None,
+            budget: self.budget.clone(),
+            length_hint: self.length_hint,
         }
     }
 }
 
 impl<T: FromBytes> Default for Body<T> {
     fn default() -> Self {
-        Body { inner: None, fut: None }
+        Body {
+            inner: None,
+            fut: None,
+            budget: None,
+            length_hint: None,
+        }
     }
 }
 
@@ -196,7 +384,7 @@ where
                 Poll::Pending => Poll::Pending,
             }
         } else if let Some(body) = self.inner.take() {
-            self.fut = Some(Box::pin(Self::generate(body)));
+            self.fut = Some(Box::pin(Self::generate(body, self.budget.clone(), self.length_hint)));
 
             match self
                 .fut
@@ -257,9 +445,13 @@ impl FromBytes for Vec<u8> {
 
 #[cfg(feature = "json")]
 pub mod json {
-    use crate::{body::FromBytes, error::SaphirError};
+    use crate::{
+        body::FromBytes,
+        error::{ExtractError, SaphirError},
+    };
     use hyper::body::Bytes;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
     use std::{
         borrow::{Borrow, BorrowMut},
         ops::{Deref, DerefMut},
@@ -276,6 +468,152 @@ pub mod json {
         pub fn unwrap(self) -> T {
             self.0
         }
+
+        /// Respond with `config` instead of the one of the router
+        ///
+        /// ```rust
+        /// # use saphir::prelude::*;
+        /// # use saphir::body::json::{ConfiguredJson, JsonConfig};
+        /// #[derive(serde_derive::Serialize)]
+        /// struct Status {
+        ///     healthy: bool,
+        /// }
+        ///
+        /// async fn status(_req: Request) -> ConfiguredJson<Status> {
+        ///     Json(Status { healthy: true }).with_config(JsonConfig::new().pretty(true))
+        /// }
+        /// ```
+        pub fn with_config(self, config: JsonConfig) -> ConfiguredJson<T> {
+            ConfiguredJson { value: self.0, config }
+        }
+    }
+
+    /// A `Json` responder serialized with its own `JsonConfig`, see
+    /// `Json::with_config`
+    pub struct ConfiguredJson<T> {
+        pub(crate) value: T,
+        pub(crate) config: JsonConfig,
+    }
+
+    /// Casing applied to the field names of serialized objects
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum FieldCasing {
+        /// `userId`
+        CamelCase,
+        /// `UserId`
+        PascalCase,
+        /// `user_id`
+        SnakeCase,
+        /// `user-id`
+        KebabCase,
+    }
+
+    /// How `Json` responders serialize their value. The router holds the
+    /// one used by default, see `router::Builder::json_config`.
+    ///
+    /// The defaults produce the same output as `serde_json::to_vec`. The other
+    /// options go through a `serde_json::Value`, so the keys of every object
+    /// are affected, including the ones of serialized maps.
+    #[derive(Clone, Debug, Default)]
+    pub struct JsonConfig {
+        pretty: bool,
+        field_casing: Option<FieldCasing>,
+        skip_nulls: bool,
+    }
+
+    impl JsonConfig {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Indent the output, handy during development. Compact by default
+        pub fn pretty(mut self, pretty: bool) -> Self {
+            self.pretty = pretty;
+            self
+        }
+
+        /// Rename the fields of objects to `casing`. They are kept as
+        /// serialized by default
+        pub fn field_casing(mut self, casing: FieldCasing) -> Self {
+            self.field_casing = Some(casing);
+            self
+        }
+
+        /// Leave out the fields of objects whose value is `null`. Nulls
+        /// inside arrays are kept. Disabled by default
+        pub fn skip_nulls(mut self, skip: bool) -> Self {
+            self.skip_nulls = skip;
+            self
+        }
+
+        /// Serialize `value` according to this config
+        pub fn to_vec<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, serde_json::Error> {
+            if self.field_casing.is_none() && !self.skip_nulls {
+                return if self.pretty {
+                    serde_json::to_vec_pretty(value)
+                } else {
+                    serde_json::to_vec(value)
+                };
+            }
+
+            let value = self.transform(serde_json::to_value(value)?);
+            if self.pretty {
+                serde_json::to_vec_pretty(&value)
+            } else {
+                serde_json::to_vec(&value)
+            }
+        }
+
+        fn transform(&self, value: Value) -> Value {
+            match value {
+                Value::Object(fields) => Value::Object(
+                    fields
+                        .into_iter()
+                        .filter(|(_, v)| !(self.skip_nulls && v.is_null()))
+                        .map(|(k, v)| {
+                            let k = match self.field_casing {
+                                Some(casing) => rename(&k, casing),
+                                None => k,
+                            };
+                            (k, self.transform(v))
+                        })
+                        .collect(),
+                ),
+                Value::Array(items) => Value::Array(items.into_iter().map(|v| self.transform(v)).collect()),
+                v => v,
+            }
+        }
+    }
+
+    /// Rename `field`, split into words on `_`, `-` and lowercase to
+    /// uppercase boundaries
+    fn rename(field: &str, casing: FieldCasing) -> String {
+        let mut words: Vec<String> = Vec::new();
+        let mut previous_lowercase = false;
+        for c in field.chars() {
+            if c == '_' || c == '-' {
+                words.push(String::new());
+                previous_lowercase = false;
+                continue;
+            }
+            if words.is_empty() || (c.is_uppercase() && previous_lowercase) {
+                words.push(String::new());
+            }
+            previous_lowercase = c.is_lowercase() || c.is_ascii_digit();
+            words.last_mut().expect("Pushed above").extend(c.to_lowercase());
+        }
+        let words = words.into_iter().filter(|w| !w.is_empty());
+
+        let capitalize = |w: String| {
+            let mut chars = w.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        };
+        match casing {
+            FieldCasing::SnakeCase => words.collect::<Vec<_>>().join("_"),
+            FieldCasing::KebabCase => words.collect::<Vec<_>>().join("-"),
+            FieldCasing::PascalCase => words.map(capitalize).collect(),
+            FieldCasing::CamelCase => words.enumerate().map(|(i, w)| if i == 0 { w } else { capitalize(w) }).collect(),
+        }
     }
 
     impl<T> Deref for Json<T> {
@@ -327,14 +665,17 @@ pub mod json {
         where
             Self: Sized,
         {
-            Ok((serde_json::from_slice(bytes.as_ref())?, bytes))
+            Ok((serde_json::from_slice(bytes.as_ref()).map_err(ExtractError::InvalidJson)?, bytes))
         }
     }
 }
 
 #[cfg(feature = "form")]
 pub mod form {
-    use crate::{body::FromBytes, error::SaphirError};
+    use crate::{
+        body::FromBytes,
+        error::{ExtractError, SaphirError},
+    };
     use hyper::body::Bytes;
     use serde::Deserialize;
     use std::{
@@ -404,7 +745,7 @@ pub mod form {
         where
             Self: Sized,
         {
-            Ok((serde_urlencoded::from_bytes(bytes.as_ref())?, bytes))
+            Ok((serde_urlencoded::from_bytes(bytes.as_ref()).map_err(ExtractError::InvalidForm)?, bytes))
         }
     }
 }
@@ -456,52 +797,36 @@ impl HttpBody for BodyInner {
     type Data = Bytes;
     type Error = SaphirError;
 
-    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, SaphirError>>> {
-        if let BodyInner::Memory(b) = self.deref_mut() {
-            if !b.is_empty() {
-                Poll::Ready(Some(Ok(b.to_bytes())))
-            } else {
-                Poll::Ready(None)
-            }
-        } else {
-            let p = unsafe {
-                self.map_unchecked_mut(|s| match s {
-                    BodyInner::Raw(r) => r,
-                    BodyInner::Memory(_) => unreachable!("This is unreachable since checked above"),
-                })
-                .poll_data(cx)
-            };
-
-            match p {
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, SaphirError>>> {
+        match self.get_mut() {
+            BodyInner::Raw(r) => match Pin::new(r).poll_data(cx) {
                 Poll::Ready(Some(res)) => Poll::Ready(Some(res.map_err(SaphirError::from))),
                 Poll::Ready(None) => Poll::Ready(None),
                 Poll::Pending => Poll::Pending,
+            },
+            BodyInner::Wrapped(w) => w.as_mut().poll_data(cx),
+            BodyInner::Memory(b) => {
+                if !b.is_empty() {
+                    Poll::Ready(Some(Ok(b.to_bytes())))
+                } else {
+                    Poll::Ready(None)
+                }
             }
         }
     }
 
-    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
-        if let BodyInner::Memory(_b) = self.deref_mut() {
-            Poll::Ready(Ok(None))
-        } else {
-            let p = unsafe {
-                self.map_unchecked_mut(|s| match s {
-                    BodyInner::Raw(r) => r,
-                    BodyInner::Memory(_) => unreachable!("This is unreachable since checked above"),
-                })
-                .poll_trailers(cx)
-            };
-
-            match p {
-                Poll::Ready(res) => Poll::Ready(res.map_err(SaphirError::from)),
-                Poll::Pending => Poll::Pending,
-            }
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match self.get_mut() {
+            BodyInner::Raw(r) => Pin::new(r).poll_trailers(cx).map_err(SaphirError::from),
+            BodyInner::Wrapped(w) => w.as_mut().poll_trailers(cx),
+            BodyInner::Memory(_b) => Poll::Ready(Ok(None)),
         }
     }
 
     fn is_end_stream(&self) -> bool {
         match self {
             BodyInner::Raw(r) => r.is_end_stream(),
+            BodyInner::Wrapped(w) => w.is_end_stream(),
             BodyInner::Memory(b) => b.remaining() > 0,
         }
     }
@@ -509,6 +834,7 @@ impl HttpBody for BodyInner {
     fn size_hint(&self) -> SizeHint {
         match self {
             BodyInner::Raw(r) => r.size_hint(),
+            BodyInner::Wrapped(w) => w.size_hint(),
             BodyInner::Memory(b) => SizeHint::with_exact(b.remaining() as u64),
         }
     }
@@ -537,3 +863,344 @@ impl<T: FromBytes> Into<RawBody> for Body<T> {
         inner.unwrap_or_else(BodyInner::empty).into_raw()
     }
 }
+
+type MetricsCallback = Box<dyn FnOnce(&BodyMetrics) + Send>;
+
+#[derive(Default)]
+struct BodyMetricsInner {
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+    completed: AtomicBool,
+    on_complete: Mutex<Vec<MetricsCallback>>,
+}
+
+/// Amount of body bytes received and sent for a request.
+///
+/// The response body is streamed after the middlewares are done, so the
+/// final counts are only known once `on_complete` callbacks run.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// async fn metrics_middleware(ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+///     let metrics = ctx.body_metrics().clone();
+///     let ctx = chain.next(ctx).await?;
+///     metrics.on_complete(|m| println!("received {} bytes, sent {} bytes", m.request_bytes(), m.response_bytes()));
+///     Ok(ctx)
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct BodyMetrics {
+    inner: Arc<BodyMetricsInner>,
+}
+
+impl BodyMetrics {
+    /// Bytes of request body read so far
+    pub fn request_bytes(&self) -> u64 {
+        self.inner.request_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Bytes of response body sent so far, after any content encoding
+    pub fn response_bytes(&self) -> u64 {
+        self.inner.response_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Run `f` once the response body is done, either fully sent or dropped.
+    /// Runs right away if the response is already done
+    pub fn on_complete<F: FnOnce(&BodyMetrics) + Send + 'static>(&self, f: F) {
+        let mut callbacks = self.inner.on_complete.lock();
+        if self.inner.completed.load(Ordering::SeqCst) {
+            drop(callbacks);
+            f(self);
+        } else {
+            callbacks.push(Box::new(f));
+        }
+    }
+
+    pub(crate) fn count_request(&self, body: RawBody) -> CountedRequestBody {
+        CountedRequestBody {
+            inner: body,
+            metrics: self.clone(),
+        }
+    }
+
+    pub(crate) fn count_response(&self, body: RawBody, trailers: Option<ResponseTrailers>) -> CountedBody {
+        CountedBody {
+            inner: body,
+            metrics: self.clone(),
+            trailers,
+        }
+    }
+
+    fn complete(&self) {
+        let callbacks = {
+            let mut callbacks = self.inner.on_complete.lock();
+            self.inner.completed.store(true, Ordering::SeqCst);
+            std::mem::take(&mut *callbacks)
+        };
+
+        for callback in callbacks {
+            callback(self);
+        }
+    }
+}
+
+/// Request body counting the bytes read from it
+pub(crate) struct CountedRequestBody {
+    inner: RawBody,
+    metrics: BodyMetrics,
+}
+
+impl HttpBody for CountedRequestBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let res = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &res {
+            self.metrics.inner.request_bytes.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+        }
+        res
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Default)]
+struct UnreadSlot {
+    body: Option<CountedRequestBody>,
+    /// Set once the data of the body ended, its trailers may still be read
+    read: bool,
+}
+
+/// The request body as the server received it, kept reachable once the
+/// request is dropped so what the handler left unread can be drained and the
+/// connection reused
+#[derive(Clone, Default)]
+pub(crate) struct UnreadBody(Arc<Mutex<UnreadSlot>>);
+
+impl UnreadBody {
+    /// Hand out `body` to the request, while keeping it
+    pub(crate) fn track(&self, body: CountedRequestBody) -> TrackedBody {
+        self.0.lock().body = Some(body);
+        TrackedBody(self.0.clone())
+    }
+
+    /// Whether the body was read to its end, or is still held by the handler
+    pub(crate) fn is_read(&self) -> bool {
+        let slot = self.0.lock();
+        Arc::strong_count(&self.0) > 1 || slot.read || slot.body.is_none()
+    }
+
+    /// Read and discard what is left of the body, giving up past `limit`
+    /// bytes or after `timeout`. Returns whether the body was read to its end
+    pub(crate) async fn drain(self, limit: u64, timeout: std::time::Duration) -> bool {
+        // The handler still holds the body, e.g. in a task reading it
+        if Arc::strong_count(&self.0) > 1 {
+            return true;
+        }
+
+        let mut body = match self.0.lock().body.take() {
+            Some(body) => body,
+            None => return true,
+        };
+        let drain = async {
+            let mut drained = 0;
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) if drained + chunk.len() as u64 <= limit => drained += chunk.len() as u64,
+                    _ => return false,
+                }
+            }
+            true
+        };
+        tokio::time::timeout(timeout, drain).await.unwrap_or(false)
+    }
+}
+
+/// A request body tracked by an `UnreadBody`, forwarding the size hint and
+/// trailers of the body it was given
+pub(crate) struct TrackedBody(Arc<Mutex<UnreadSlot>>);
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = SaphirError;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut slot = self.0.lock();
+        let slot = slot.deref_mut();
+        let poll = match slot.body.as_mut() {
+            Some(body) if !slot.read => Pin::new(body).poll_data(cx),
+            _ => Poll::Ready(None),
+        };
+        if let Poll::Ready(None) = poll {
+            slot.read = true;
+        }
+        poll.map(|chunk| chunk.map(|chunk| chunk.map_err(SaphirError::from)))
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match self.0.lock().body.as_mut() {
+            Some(body) => Pin::new(body).poll_trailers(cx).map_err(SaphirError::from),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let slot = self.0.lock();
+        slot.read || slot.body.as_ref().map(|body| body.is_end_stream()).unwrap_or(true)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.0.lock().body.as_ref() {
+            Some(body) => body.size_hint(),
+            None => SizeHint::with_exact(0),
+        }
+    }
+}
+
+/// Trailers of a response, set by its body once it is done. Hyper bodies
+/// can't carry trailers of their own, so they are attached to the response
+/// extensions and sent by the `CountedBody`
+#[derive(Clone, Default)]
+pub(crate) struct ResponseTrailers(Arc<Mutex<Option<HeaderMap>>>);
+
+impl ResponseTrailers {
+    pub(crate) fn set(&self, trailers: HeaderMap) {
+        *self.0.lock() = Some(trailers);
+    }
+
+    fn take(&self) -> Option<HeaderMap> {
+        self.0.lock().take()
+    }
+}
+
+/// Response body counting the bytes handed to the server
+#[doc(hidden)]
+pub struct CountedBody {
+    inner: RawBody,
+    metrics: BodyMetrics,
+    trailers: Option<ResponseTrailers>,
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let res = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &res {
+            self.metrics.inner.response_bytes.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+        }
+        res
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match Pin::new(&mut self.inner).poll_trailers(cx) {
+            Poll::Ready(Ok(None)) => Poll::Ready(Ok(self.trailers.as_ref().and_then(|t| t.take()))),
+            res => res,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.metrics.complete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn request_bytes_are_counted() {
+        let metrics = BodyMetrics::default();
+        let body = metrics.count_request(RawBody::from("hello world"));
+        assert_eq!(body.size_hint().exact(), Some(11));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 11);
+        assert_eq!(metrics.request_bytes(), 11);
+    }
+
+    #[tokio::test]
+    async fn declared_length_is_buffered_in_one_allocation() {
+        let chunks: Vec<Result<_, std::io::Error>> = vec![
+            Ok(Bytes::from(vec![1u8; 4000])),
+            Ok(Bytes::from(vec![2u8; 4000])),
+            Ok(Bytes::from(vec![3u8; 2000])),
+        ];
+        let mut body = RawBody::wrap_stream(futures::stream::iter(chunks));
+        let budget = MemoryBudget::new(16 * 1024);
+        let vec = read_body_limited(&mut body, 10_000, None, Some(&budget)).await.unwrap();
+        assert_eq!(vec.len(), 10_000);
+        assert_eq!(vec.capacity(), 10_000);
+        assert_eq!(budget.used(), 10_000);
+
+        // Rejected before the body is polled, which would never end
+        let mut body = RawBody::wrap_stream(futures::stream::pending::<Result<Bytes, std::io::Error>>());
+        let budget = MemoryBudget::new(1024);
+        let read = tokio::time::timeout(std::time::Duration::from_secs(1), read_body_limited(&mut body, 4096, None, Some(&budget))).await;
+        assert!(matches!(read, Ok(Err(SaphirError::Extract(ExtractError::TooLarge { limit: 1024 })))));
+        assert_eq!(budget.used(), 0);
+
+        // So is a body declared past the body limit of the server, rather than truncated
+        let mut body = RawBody::wrap_stream(futures::stream::pending::<Result<Bytes, std::io::Error>>());
+        let budget = MemoryBudget::new(16 * 1024);
+        let read = tokio::time::timeout(std::time::Duration::from_secs(1), read_body_limited(&mut body, 4096, Some(2048), Some(&budget))).await;
+        match read {
+            Ok(Err(SaphirError::Extract(e))) => {
+                assert_eq!(e.status(), 413);
+                assert!(matches!(e, ExtractError::TooLarge { limit: 2048 }));
+            }
+            _ => panic!("an oversized body was read"),
+        }
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[cfg(feature = "file")]
+    #[tokio::test]
+    async fn response_bytes_match_served_file_size() {
+        use crate::{file::File, response::Builder};
+        use std::io::Write;
+
+        let content = vec![42u8; 3 * crate::file::MAX_BUFFER + 17];
+        let path = std::env::temp_dir().join("saphir_body_metrics.bin");
+        std::fs::File::create(&path).unwrap().write_all(&content).unwrap();
+
+        let file = File::open(path.to_str().unwrap()).await.unwrap();
+        let res = Builder::new().file(file).build().unwrap().into_raw().unwrap();
+        let metrics = BodyMetrics::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_in_callback = calls.clone();
+        let size = content.len() as u64;
+        metrics.on_complete(move |m| {
+            assert_eq!(m.response_bytes(), size);
+            calls_in_callback.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let body = metrics.count_response(res.into_body().into_raw(), None);
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), content.len());
+        assert_eq!(metrics.response_bytes(), size);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        metrics.on_complete(move |m| assert_eq!(m.response_bytes(), size));
+    }
+}
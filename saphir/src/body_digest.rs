@@ -0,0 +1,249 @@
+//! Verification of request bodies against the digest sent by the client.
+//!
+//! The digest is taken from `Content-MD5` (RFC 1864) or from the `md5` entry
+//! of `Digest` (RFC 3230), and computed while the body is streamed to the
+//! handler. A mismatch is only known once the last chunk was read: the body
+//! seen by the handler then fails instead of ending, so a corrupted upload is
+//! never taken for a complete one. A body the handler didn't read to its end
+//! couldn't be checked, and is rejected as well, unless the handler answered
+//! with an error.
+
+use crate::{
+    body::Bytes,
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    request::Request,
+    response::Builder,
+    upload_scan::{scan_request_body, ScanRejected, ScanSession},
+};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+
+const CONTENT_MD5: &str = "content-md5";
+const DIGEST: &str = "digest";
+
+/// Middleware answering with a `400 Bad Request` the requests whose body
+/// doesn't match the `Content-MD5` or `Digest` header they carry, whatever
+/// the handler responded. So are the requests whose body the handler didn't
+/// read to its end, unless it answered with an error, e.g. a `401` sent
+/// before reading the body, which tells the client the real reason.
+///
+/// Requests without a digest are let through unless `require_digest` is set.
+/// A `Digest` header without an `md5` entry counts as no digest, since other
+/// algorithms aren't supported.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::body_digest::BodyDigestMiddleware;
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(BodyDigestMiddleware::new().require_digest(true), vec!["/upload/**"], None))
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct BodyDigestMiddleware {
+    require_digest: bool,
+}
+
+impl BodyDigestMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject requests carrying no digest, disabled by default
+    pub fn require_digest(mut self, required: bool) -> Self {
+        self.require_digest = required;
+        self
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let req = ctx.state.request_unchecked_mut();
+        let expected = match expected_digest(req) {
+            Ok(Some(expected)) => expected,
+            Ok(None) if !self.require_digest => return chain.next(ctx).await,
+            Ok(None) | Err(()) => {
+                ctx.after(Builder::new().status(400).build()?);
+                return Ok(ctx);
+            }
+        };
+
+        let session = Md5Session {
+            expected,
+            context: Some(md5::Context::new()),
+        };
+        let verdict = match scan_request_body(&mut ctx, Box::new(session)).await {
+            Some(verdict) => verdict,
+            None => {
+                ctx.after(Builder::new().status(400).build()?);
+                return Ok(ctx);
+            }
+        };
+
+        let mut ctx = chain.next(ctx).await?;
+        if verdict.rejects(ctx.state.response()) {
+            ctx.after(Builder::new().status(400).build()?);
+        }
+        Ok(ctx)
+    }
+}
+
+impl Middleware for BodyDigestMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+/// MD5 digest announced by the request, `Err` when it can't be decoded
+fn expected_digest(req: &Request) -> Result<Option<Vec<u8>>, ()> {
+    let headers = req.headers();
+    let encoded = match headers.get(CONTENT_MD5) {
+        Some(md5) => Some(md5.to_str().map_err(|_| ())?.trim().to_string()),
+        None => headers
+            .get_all(DIGEST)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .filter_map(|entry| {
+                let mut entry = entry.trim().splitn(2, '=');
+                match (entry.next(), entry.next()) {
+                    (Some(algorithm), Some(digest)) if algorithm.eq_ignore_ascii_case("md5") => Some(digest.to_string()),
+                    _ => None,
+                }
+            })
+            .next(),
+    };
+
+    match encoded {
+        Some(encoded) => match base64::decode(&encoded) {
+            Ok(digest) if digest.len() == 16 => Ok(Some(digest)),
+            _ => Err(()),
+        },
+        None => Ok(None),
+    }
+}
+
+struct Md5Session {
+    expected: Vec<u8>,
+    context: Option<md5::Context>,
+}
+
+impl ScanSession for Md5Session {
+    fn scan_chunk(&mut self, chunk: &Bytes) -> BoxFuture<'_, Result<(), ScanRejected>> {
+        if let Some(context) = self.context.as_mut() {
+            context.consume(chunk);
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn finish(&mut self) -> BoxFuture<'_, Result<(), ScanRejected>> {
+        let digest = self.context.take().map(|c| c.compute());
+        future::ready(match digest {
+            Some(digest) if digest[..] == self.expected[..] => Ok(()),
+            _ => Err(ScanRejected("body digest mismatch".to_string())),
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, router::Router};
+    use hyper::body::{Body as RawBody, HttpBody};
+    use std::sync::Arc;
+
+    /// Status of the response, and whether the handler read the whole body
+    /// when it was called
+    async fn upload(middleware: BodyDigestMiddleware, header: Option<(&str, &str)>) -> (u16, Option<bool>) {
+        let middleware: &'static BodyDigestMiddleware = Box::leak(Box::new(middleware));
+        let complete = Arc::new(parking_lot::Mutex::new(None));
+        let handler_complete = complete.clone();
+        let handler = move |mut req: Request<Body>| {
+            let complete = handler_complete.clone();
+            async move {
+                let mut read = true;
+                while let Some(chunk) = req.body_mut().data().await {
+                    read &= chunk.is_ok();
+                }
+                *complete.lock() = Some(read);
+                201
+            }
+        };
+        let router = Router::builder().route("/upload", http::Method::POST, handler).build();
+
+        let chunks = vec!["hello ", "world"];
+        let body = RawBody::wrap_stream(futures::stream::iter(chunks.into_iter().map(|c| Ok::<_, std::io::Error>(Bytes::from(c)))));
+        let mut req = http::Request::builder().method(http::Method::POST).uri("/upload");
+        if let Some((name, value)) = header {
+            req = req.header(name, value);
+        }
+        let req = Request::new(req.body(Body::from_raw(body)).unwrap(), None);
+
+        let mut ctx = middleware.next_inner(HttpContext::new(req, router), &MiddleChainEnd).await.unwrap();
+        let status = ctx.state.take_response().unwrap().status().as_u16();
+        let complete = complete.lock().take();
+        (status, complete)
+    }
+
+    fn md5_of_body() -> String {
+        base64::encode(&md5::compute(b"hello world")[..])
+    }
+
+    #[tokio::test]
+    async fn matching_digest_is_accepted() {
+        let md5 = md5_of_body();
+        assert_eq!(upload(BodyDigestMiddleware::new(), Some(("Content-MD5", &md5))).await, (201, Some(true)));
+
+        let digest = format!("SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=, MD5={}", md5);
+        assert_eq!(upload(BodyDigestMiddleware::new(), Some(("Digest", &digest))).await, (201, Some(true)));
+
+        assert_eq!(upload(BodyDigestMiddleware::new(), None).await, (201, Some(true)));
+    }
+
+    #[tokio::test]
+    async fn mismatching_digest_is_rejected() {
+        let wrong = base64::encode(&md5::compute(b"hello w0rld")[..]);
+        assert_eq!(upload(BodyDigestMiddleware::new(), Some(("Content-MD5", &wrong))).await, (400, Some(false)));
+
+        let digest = format!("md5={}", wrong);
+        assert_eq!(upload(BodyDigestMiddleware::new(), Some(("Digest", &digest))).await, (400, Some(false)));
+
+        assert_eq!(upload(BodyDigestMiddleware::new(), Some(("Content-MD5", "not base64!"))).await, (400, None));
+        assert_eq!(upload(BodyDigestMiddleware::new().require_digest(true), None).await, (400, None));
+    }
+
+    #[tokio::test]
+    async fn unread_body_is_rejected() {
+        let middleware: &'static BodyDigestMiddleware = Box::leak(Box::new(BodyDigestMiddleware::new()));
+        let router = Router::builder()
+            .route("/upload", http::Method::POST, |_req: Request<Body>| async { 201 })
+            .route("/private", http::Method::POST, |_req: Request<Body>| async { 401 })
+            .build();
+        let upload = |uri: &'static str, body: Body| {
+            let req = http::Request::builder()
+                .method(http::Method::POST)
+                .uri(uri)
+                .header("Content-MD5", md5_of_body())
+                .body(body)
+                .unwrap();
+            let ctx = HttpContext::new(Request::new(req, None), router.clone());
+            async move {
+                let mut ctx = middleware.next_inner(ctx, &MiddleChainEnd).await.unwrap();
+                ctx.state.take_response().unwrap().status().as_u16()
+            }
+        };
+
+        let unread = || {
+            Body::from_raw(RawBody::wrap_stream(futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(
+                "hello world",
+            ))])))
+        };
+        assert_eq!(upload("/upload", unread()).await, 400);
+        // An empty body is checked without being read
+        assert_eq!(upload("/upload", Body::empty()).await, 400);
+        // The handler rejecting the request before reading it is answered
+        assert_eq!(upload("/private", unread()).await, 401);
+    }
+}
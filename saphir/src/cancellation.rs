@@ -0,0 +1,144 @@
+use futures::{
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+use parking_lot::Mutex;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Token cancelled when the request it belongs to is abandoned.
+///
+/// The server cancels the token of a request when it stops handling it
+/// before a response was produced, which happens when the client
+/// disconnects or the connection is dropped. Work spawned by a handler on
+/// behalf of a request can keep a clone of the token to stop as soon as its
+/// result isn't awaited anymore.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// async fn report(req: Request<Body>) -> u16 {
+///     let token = req.cancellation_token().clone();
+///     tokio::spawn(async move {
+///         let expensive_work = tokio::time::delay_for(std::time::Duration::from_secs(30));
+///         tokio::select! {
+///             _ = expensive_work => println!("report generated"),
+///             _ = token.cancelled() => println!("client left, report abandoned"),
+///         }
+///     });
+///     202
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Cancel the token, waking every task waiting on it
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        for waker in self.inner.wakers.lock().drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Future resolving once the token is cancelled
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled { token: self.clone() }
+    }
+
+    /// Cancel the token if the returned guard is dropped before being
+    /// disarmed
+    pub(crate) fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(Some(self.clone()))
+    }
+}
+
+/// Future returned by `CancellationToken::cancelled`
+pub struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+
+        let mut wakers = self.token.inner.wakers.lock();
+        // Checked again with the lock held, so a concurrent cancel can't be missed
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+pub(crate) struct CancelOnDrop(Option<CancellationToken>);
+
+impl CancelOnDrop {
+    pub(crate) fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancelled_resolves_once_cancelled() {
+        let token = CancellationToken::new();
+        let waiting = tokio::spawn(token.cancelled());
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        assert!(!token.is_cancelled());
+
+        token.cancel();
+        waiting.await.unwrap();
+        assert!(token.is_cancelled());
+        token.cancelled().await;
+    }
+
+    #[test]
+    fn guard_cancels_unless_disarmed() {
+        let token = CancellationToken::new();
+        token.cancel_on_drop().disarm();
+        assert!(!token.is_cancelled());
+
+        drop(token.cancel_on_drop());
+        assert!(token.is_cancelled());
+    }
+}
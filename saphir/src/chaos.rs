@@ -0,0 +1,197 @@
+//! Injection of synthetic latency and faults, for resilience testing.
+//!
+//! The module only exists with the `chaos` feature, which `full` leaves out,
+//! so a server can't inject faults unless its build asked for it. Enable the
+//! feature in the builds of the test environments only:
+//!
+//! ```toml
+//! [features]
+//! chaos = ["saphir/chaos"]
+//! ```
+
+use crate::{
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Middleware delaying or failing a share of the requests: each fault is
+/// drawn on its own for every request, with its own rate between `0.0` and
+/// `1.0`. A failed request gets a `500 Internal Server Error` without
+/// reaching the handler, and a dropped request has its connection closed
+/// without a response.
+///
+/// Faults are scoped to routes with the paths the middleware is applied to.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::chaos::ChaosMiddleware;
+/// # use std::time::Duration;
+/// let chaos = ChaosMiddleware::new()
+///     .latency(0.2, Duration::from_millis(300))
+///     .error_rate(0.05)
+///     .drop_rate(0.01);
+///
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(chaos, vec!["/api/**"], None))
+///     .build();
+/// ```
+pub struct ChaosMiddleware {
+    latency: Option<(f64, Duration)>,
+    error_rate: f64,
+    drop_rate: f64,
+    state: AtomicU64,
+}
+
+impl ChaosMiddleware {
+    /// A middleware injecting nothing until faults are configured
+    pub fn new() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+        warn!("The chaos middleware is enabled, it will inject faults into the requests it is applied to");
+        ChaosMiddleware {
+            latency: None,
+            error_rate: 0.0,
+            drop_rate: 0.0,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// Delay `rate` of the requests by `delay` before handling them
+    pub fn latency(mut self, rate: f64, delay: Duration) -> Self {
+        self.latency = Some((rate, delay));
+        self
+    }
+
+    /// Answer `rate` of the requests with a `500 Internal Server Error`
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate;
+        self
+    }
+
+    /// Close the connection of `rate` of the requests without answering them
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Draw the faults from `seed`, to reproduce a run
+    pub fn seed(self, seed: u64) -> Self {
+        self.state.store(seed, Ordering::Relaxed);
+        self
+    }
+
+    /// Whether a fault happening at `rate` happens this time
+    fn strikes(&self, rate: f64) -> bool {
+        rate > 0.0 && self.sample() < rate
+    }
+
+    /// A uniform sample in `[0, 1)`, from a SplitMix64 sequence
+    fn sample(&self) -> f64 {
+        let mut z = self.state.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed).wrapping_add(GOLDEN_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    async fn next_inner(&'static self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        if let Some((rate, delay)) = self.latency {
+            if self.strikes(rate) {
+                tokio::time::delay_for(delay).await;
+            }
+        }
+
+        if self.strikes(self.drop_rate) {
+            return Err(SaphirError::Other("Connection dropped by the chaos middleware".to_string()));
+        }
+
+        if self.strikes(self.error_rate) {
+            ctx.after(Builder::new().status(500).build()?);
+            return Ok(ctx);
+        }
+
+        chain.next(ctx).await
+    }
+}
+
+impl Default for ChaosMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for ChaosMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, request::Request, router::Router};
+
+    const REQUESTS: usize = 2000;
+
+    /// Send `REQUESTS` requests through `chaos`, counting the statuses of the
+    /// answered ones and the dropped ones
+    async fn outcomes(chaos: ChaosMiddleware) -> (usize, usize, usize) {
+        let chaos: &'static ChaosMiddleware = Box::leak(Box::new(chaos));
+        let router = Router::builder().route("/", http::Method::GET, |_req: Request<Body>| async { 200 }).build();
+        let (mut ok, mut failed, mut dropped) = (0, 0, 0);
+        for _ in 0..REQUESTS {
+            let req = Request::new(http::Request::builder().uri("/").body(Body::empty()).unwrap(), None);
+            let ctx = HttpContext::new(req, router.clone());
+            match chaos.next_inner(ctx, &MiddleChainEnd).await {
+                Ok(mut ctx) => match ctx.state.take_response().unwrap().status().as_u16() {
+                    200 => ok += 1,
+                    500 => failed += 1,
+                    status => panic!("unexpected status {}", status),
+                },
+                Err(_) => dropped += 1,
+            }
+        }
+        (ok, failed, dropped)
+    }
+
+    fn assert_rate(count: usize, rate: f64) {
+        let measured = count as f64 / REQUESTS as f64;
+        assert!((measured - rate).abs() < 0.03, "measured a rate of {} instead of {}", measured, rate);
+    }
+
+    #[tokio::test]
+    async fn configured_fault_rates_hold() {
+        let (ok, failed, dropped) = outcomes(ChaosMiddleware::new().seed(7).error_rate(0.2).drop_rate(0.1)).await;
+        assert_eq!(ok + failed + dropped, REQUESTS);
+        assert_rate(dropped, 0.1);
+        // Only the requests which weren't dropped can fail
+        assert_rate(failed, 0.9 * 0.2);
+
+        let (ok, _, _) = outcomes(ChaosMiddleware::new().seed(7)).await;
+        assert_eq!(ok, REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn latency_delays_a_share_of_the_requests() {
+        let chaos = ChaosMiddleware::new().seed(11).latency(0.25, Duration::from_millis(20));
+        let delayed = (0..400).filter(|_| chaos.strikes(0.25)).count();
+        assert!((delayed as f64 / 400.0 - 0.25).abs() < 0.06);
+
+        let chaos: &'static ChaosMiddleware = Box::leak(Box::new(ChaosMiddleware::new().latency(1.0, Duration::from_millis(20))));
+        let req = Request::new(http::Request::builder().uri("/").body(Body::empty()).unwrap(), None);
+        let started = std::time::Instant::now();
+        chaos
+            .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
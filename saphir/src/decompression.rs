@@ -0,0 +1,237 @@
+//! Transparent decompression of request bodies.
+//!
+//! Compressed bodies are small by design, which makes them a cheap way to
+//! send a server far more data than what went over the wire. Bodies are
+//! therefore decoded up front, on the blocking thread pool, against limits on
+//! the number of encodings and on the expansion they produce, before reaching
+//! the handlers.
+
+use crate::{
+    body::{Body, Bytes},
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt};
+use http::header;
+use hyper::body::Body as RawBody;
+use std::io::Read;
+
+const DEFAULT_MAX_ENCODINGS: usize = 2;
+const DEFAULT_MAX_RATIO: u64 = 100;
+const DEFAULT_MAX_DECODED_SIZE: u64 = 16_777_216;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Coding {
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+/// Middleware decoding request bodies sent with a `Content-Encoding`.
+///
+/// Encodings are undone in the reverse order they are listed in, so
+/// `Content-Encoding: deflate, gzip` is decoded from gzip first. Handlers
+/// receive the identity body, without the `Content-Encoding` header. The
+/// request is rejected with:
+///
+/// - `415 Unsupported Media Type` for an encoding other than `gzip`,
+///   `deflate` or `br`
+/// - `400 Bad Request` for more encodings than `max_encodings`, or a body
+///   that cannot be decoded
+/// - `413 Payload Too Large` when the decoded body gets bigger than
+///   `max_ratio` times the received body, or than `max_decoded_size`
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::decompression::DecompressionMiddleware;
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(DecompressionMiddleware::new().max_ratio(20), vec!["/upload/**"], None))
+///     .build();
+/// ```
+pub struct DecompressionMiddleware {
+    max_encodings: usize,
+    max_ratio: u64,
+    max_decoded_size: u64,
+}
+
+impl Default for DecompressionMiddleware {
+    fn default() -> Self {
+        DecompressionMiddleware {
+            max_encodings: DEFAULT_MAX_ENCODINGS,
+            max_ratio: DEFAULT_MAX_RATIO,
+            max_decoded_size: DEFAULT_MAX_DECODED_SIZE,
+        }
+    }
+}
+
+impl DecompressionMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of encodings applied to a body, 2 by default
+    pub fn max_encodings(mut self, max: usize) -> Self {
+        self.max_encodings = max;
+        self
+    }
+
+    /// Maximum ratio between the decoded and the received body size, 100 by
+    /// default
+    pub fn max_ratio(mut self, ratio: u64) -> Self {
+        self.max_ratio = ratio;
+        self
+    }
+
+    /// Maximum size of a decoded body in bytes, 16 MiB by default
+    pub fn max_decoded_size(mut self, size: u64) -> Self {
+        self.max_decoded_size = size;
+        self
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let req = ctx.state.request_unchecked_mut();
+        let codings = match req.headers().get(header::CONTENT_ENCODING).map(|h| h.to_str().map(parse_codings)) {
+            None => return chain.next(ctx).await,
+            Some(Ok(Ok(codings))) => codings,
+            Some(Ok(Err(status))) => return respond(ctx, status),
+            Some(Err(_)) => return respond(ctx, 400),
+        };
+
+        if codings.len() > self.max_encodings {
+            return respond(ctx, 400);
+        }
+
+        let body = req.body_mut().take().await?;
+        let limit = self.max_decoded_size.min((body.len() as u64).saturating_mul(self.max_ratio));
+        // Decoding up to `max_decoded_size` bytes would hold the worker thread
+        let decoded = match tokio::task::spawn_blocking(move || decode(body, &codings, limit)).await {
+            Ok(Ok(decoded)) => decoded,
+            Ok(Err(status)) => return respond(ctx, status),
+            // The decoder panicked on the body
+            Err(_) => return respond(ctx, 400),
+        };
+
+        req.headers_mut().remove(header::CONTENT_ENCODING);
+        req.headers_mut().insert(header::CONTENT_LENGTH, decoded.len().into());
+        *req.body_mut() = Body::from_raw(RawBody::from(decoded));
+
+        chain.next(ctx).await
+    }
+}
+
+impl Middleware for DecompressionMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+fn respond(mut ctx: HttpContext, status: u16) -> Result<HttpContext, SaphirError> {
+    ctx.after(Builder::new().status(status).build()?);
+    Ok(ctx)
+}
+
+/// Parse a `Content-Encoding` header into the list of applied codings, failing
+/// with the status to respond with
+fn parse_codings(header: &str) -> Result<Vec<Coding>, u16> {
+    header
+        .split(',')
+        .map(|coding| coding.trim())
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"))
+        .map(|coding| match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "deflate" => Ok(Coding::Deflate),
+            "br" => Ok(Coding::Brotli),
+            _ => Err(415),
+        })
+        .collect()
+}
+
+/// Undo `codings`, failing as soon as any intermediate body gets bigger than
+/// `limit`
+fn decode(body: Bytes, codings: &[Coding], limit: u64) -> Result<Bytes, u16> {
+    let mut data = body.to_vec();
+    for coding in codings.iter().rev() {
+        let reader: Box<dyn Read> = match coding {
+            Coding::Gzip => Box::new(flate2::read::GzDecoder::new(data.as_slice())),
+            Coding::Deflate => Box::new(flate2::read::ZlibDecoder::new(data.as_slice())),
+            Coding::Brotli => Box::new(brotli::Decompressor::new(data.as_slice(), 4096)),
+        };
+
+        let mut decoded = Vec::new();
+        reader.take(limit.saturating_add(1)).read_to_end(&mut decoded).map_err(|_| 400u16)?;
+        if decoded.len() as u64 > limit {
+            return Err(413);
+        }
+
+        data = decoded;
+    }
+
+    Ok(Bytes::from(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::MiddleChainEnd, request::Request, router::Router};
+    use flate2::write::GzEncoder;
+    use http::Method;
+    use std::io::Write;
+
+    async fn echo(mut req: Request<Body>) -> Vec<u8> {
+        req.body_mut().take().await.unwrap().to_vec()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn send(middleware: DecompressionMiddleware, encoding: &str, body: Vec<u8>) -> (u16, Vec<u8>) {
+        let middleware: &'static DecompressionMiddleware = Box::leak(Box::new(middleware));
+        let router = Router::builder().route("/", Method::POST, echo).build();
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .header(header::CONTENT_ENCODING, encoding)
+            .body(Body::from_raw(RawBody::from(body)))
+            .unwrap();
+
+        let mut ctx = middleware
+            .next_inner(HttpContext::new(Request::new(req, None), router), &MiddleChainEnd)
+            .await
+            .unwrap();
+        let res = ctx.state.take_response().unwrap();
+        let status = res.status().as_u16();
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (status, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn layered_encodings_are_decoded() {
+        let body = gzip(&gzip(b"hello, world"));
+        assert_eq!(send(DecompressionMiddleware::new(), "gzip, gzip", body).await, (200, b"hello, world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn too_many_encodings_are_rejected() {
+        let body = gzip(&gzip(b"hello, world"));
+        assert_eq!(send(DecompressionMiddleware::new().max_encodings(1), "gzip, gzip", body).await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn bomb_like_ratio_is_rejected() {
+        let bomb = gzip(&vec![0u8; 10_000_000]);
+        assert!(bomb.len() < 100_000);
+        assert_eq!(send(DecompressionMiddleware::new(), "gzip", bomb.clone()).await.0, 413);
+        assert_eq!(send(DecompressionMiddleware::new(), "gzip, gzip", gzip(&bomb)).await.0, 413);
+    }
+
+    #[tokio::test]
+    async fn unknown_or_corrupt_encodings_are_rejected() {
+        assert_eq!(send(DecompressionMiddleware::new(), "compress", b"data".to_vec()).await.0, 415);
+        assert_eq!(send(DecompressionMiddleware::new(), "gzip", b"not gzip".to_vec()).await.0, 400);
+    }
+}
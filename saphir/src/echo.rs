@@ -0,0 +1,227 @@
+//! Echo of the requests received, for development.
+//!
+//! The `EchoMiddleware` answers the requests it is applied to with a JSON
+//! description of what the server parsed from them, instead of handling them.
+//! It is meant to check what a client or a proxy actually sends, and does
+//! nothing unless enabled.
+
+use crate::{
+    body::Body,
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    request::Request,
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt};
+use http_body::Body as HttpBody;
+use serde_json::{json, Map, Value};
+
+const DEFAULT_MAX_BODY: usize = 4096;
+
+/// Middleware answering with a JSON dump of the request: its method, path,
+/// the captures of the route it matches, its headers, its query parameters,
+/// and the beginning of its body.
+///
+/// It is disabled by default and passes requests through until `enabled` is
+/// set, so it can stay in the middleware stack and be toggled from the
+/// configuration. Never enable it in production: it hands back every header
+/// it receives, credentials included.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::echo::EchoMiddleware;
+/// let echo = EchoMiddleware::new().enabled(cfg!(debug_assertions)).max_body(1024);
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(echo, vec!["/**"], None))
+///     .build();
+/// ```
+pub struct EchoMiddleware {
+    enabled: bool,
+    max_body: usize,
+}
+
+impl Default for EchoMiddleware {
+    fn default() -> Self {
+        EchoMiddleware {
+            enabled: false,
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+}
+
+impl EchoMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer requests with their dump, disabled by default
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Bytes of the body included in the dump, 4 KiB by default. The rest of
+    /// the body isn't read
+    pub fn max_body(mut self, size: usize) -> Self {
+        self.max_body = size;
+        self
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        if !self.enabled {
+            return chain.next(ctx).await;
+        }
+
+        let mut req = ctx.state.take_request().ok_or(SaphirError::RequestMovedBeforeHandler)?;
+        // The request isn't handled, matching it against the routes only serves to find its captures
+        let route_found = ctx.router.as_ref().map(|router| router.resolve(&mut req).is_ok()).unwrap_or(false);
+        let dump = self.dump(&mut req, route_found).await?;
+
+        let body = serde_json::to_vec_pretty(&dump)?;
+        ctx.after(
+            Builder::new()
+                .status(200)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .build()?,
+        );
+        Ok(ctx)
+    }
+
+    async fn dump(&self, req: &mut Request<Body>, route_found: bool) -> Result<Value, SaphirError> {
+        let mut headers = Map::new();
+        for name in req.headers().keys() {
+            let values = req
+                .headers()
+                .get_all(name)
+                .iter()
+                .map(|v| Value::String(String::from_utf8_lossy(v.as_bytes()).to_string()))
+                .collect();
+            headers.insert(name.as_str().to_string(), Value::Array(values));
+        }
+
+        let query: Vec<Value> = req
+            .uri()
+            .query()
+            .map(|q| q.split('&').filter(|p| !p.is_empty()).map(query_param).collect())
+            .unwrap_or_default();
+
+        let (body, body_truncated) = self.read_body(req).await?;
+
+        let params = if route_found { json!(req.captures()) } else { Value::Null };
+        Ok(json!({
+            "method": req.method().as_str(),
+            "path": req.uri().path(),
+            "version": format!("{:?}", req.version()),
+            "params": params,
+            "headers": headers,
+            "query": query,
+            "body": String::from_utf8_lossy(&body),
+            "body_truncated": body_truncated,
+        }))
+    }
+
+    /// The first `max_body` bytes of the body, and whether there was more
+    async fn read_body(&self, req: &mut Request<Body>) -> Result<(Vec<u8>, bool), SaphirError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = req.body_mut().data().await {
+            let chunk = chunk?;
+            let room = self.max_body - body.len();
+            if chunk.len() > room {
+                body.extend_from_slice(&chunk[..room]);
+                return Ok((body, true));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok((body, false))
+    }
+}
+
+impl Middleware for EchoMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+/// A `name=value` query parameter as a decoded `[name, value]` pair
+fn query_param(param: &str) -> Value {
+    let mut param = param.splitn(2, '=');
+    let name = decode(param.next().unwrap_or_default());
+    let value = decode(param.next().unwrap_or_default());
+    json!([name, value])
+}
+
+/// Decode `+` and percent-encoded bytes of a query component, leaving
+/// invalid escapes as they are
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => match std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::MiddleChainEnd, router::Router};
+
+    async fn echo(middleware: EchoMiddleware) -> (u16, Value) {
+        let middleware: &'static EchoMiddleware = Box::leak(Box::new(middleware));
+        let router = Router::builder()
+            .route("/users/{id}", http::Method::POST, |_req: Request<Body>| async { 201 })
+            .build();
+        let req = http::Request::builder()
+            .method(http::Method::POST)
+            .uri("/users/42?tag=a%20b&tag=c+d&flag")
+            .header("X-Trace", "one")
+            .header("X-Trace", "two")
+            .body(Body::from_raw(hyper::Body::from("hello echo")))
+            .unwrap();
+        let ctx = HttpContext::new(Request::new(req, None), router);
+
+        let mut ctx = middleware.next_inner(ctx, &MiddleChainEnd).await.unwrap();
+        let res = ctx.state.take_response().unwrap();
+        let status = res.status().as_u16();
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn request_is_echoed() {
+        let (status, dump) = echo(EchoMiddleware::new().enabled(true).max_body(5)).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            dump,
+            json!({
+                "method": "POST",
+                "path": "/users/42",
+                "version": "HTTP/1.1",
+                "params": {"id": "42"},
+                "headers": {"x-trace": ["one", "two"]},
+                "query": [["tag", "a b"], ["tag", "c d"], ["flag", ""]],
+                "body": "hello",
+                "body_truncated": true,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_echo_lets_requests_through() {
+        assert_eq!(echo(EchoMiddleware::new()).await, (201, Value::Null));
+    }
+}
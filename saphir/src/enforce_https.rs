@@ -0,0 +1,185 @@
+//! Enforcement of HTTPS for every request.
+//!
+//! A request is considered secure when it was received over a TLS connection
+//! accepted by the server, or, once the proxy in front of the server is
+//! trusted, when it carries `X-Forwarded-Proto: https`.
+
+use crate::{
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    request::Request,
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt};
+use http::header;
+
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// What to answer to a plaintext request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HttpsPolicy {
+    /// Redirect to the `https://` equivalent with a `308 Permanent Redirect`,
+    /// so clients repeat the request with the same method and body
+    Redirect,
+    /// Reject with a `403 Forbidden`
+    Reject,
+}
+
+/// Middleware answering plaintext requests according to a `HttpsPolicy`
+/// instead of handling them.
+///
+/// `X-Forwarded-Proto` is ignored unless `trust_forwarded_proto` is set,
+/// since any client can send it: only trust it when every request goes
+/// through a proxy which overwrites it.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::enforce_https::EnforceHttpsMiddleware;
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(EnforceHttpsMiddleware::redirect().trust_forwarded_proto(true), vec!["/**"], None))
+///     .build();
+/// ```
+pub struct EnforceHttpsMiddleware {
+    policy: HttpsPolicy,
+    trust_forwarded_proto: bool,
+    https_port: Option<u16>,
+}
+
+impl EnforceHttpsMiddleware {
+    pub fn new(policy: HttpsPolicy) -> Self {
+        EnforceHttpsMiddleware {
+            policy,
+            trust_forwarded_proto: false,
+            https_port: None,
+        }
+    }
+
+    /// Redirect plaintext requests to HTTPS
+    pub fn redirect() -> Self {
+        Self::new(HttpsPolicy::Redirect)
+    }
+
+    /// Reject plaintext requests
+    pub fn reject() -> Self {
+        Self::new(HttpsPolicy::Reject)
+    }
+
+    /// Consider requests carrying `X-Forwarded-Proto: https` as secure,
+    /// disabled by default
+    pub fn trust_forwarded_proto(mut self, trusted: bool) -> Self {
+        self.trust_forwarded_proto = trusted;
+        self
+    }
+
+    /// Port put in the redirections, for HTTPS served on another port than
+    /// `443`
+    pub fn https_port(mut self, port: u16) -> Self {
+        self.https_port = Some(port);
+        self
+    }
+
+    fn is_secure(&self, req: &Request) -> bool {
+        if req.is_tls() {
+            return true;
+        }
+
+        self.trust_forwarded_proto
+            && req
+                .headers()
+                .get(X_FORWARDED_PROTO)
+                .and_then(|h| h.to_str().ok())
+                // The first protocol is the one of the client, the others were added by proxies along the way
+                .and_then(|h| h.split(',').next())
+                .map(|proto| proto.trim().eq_ignore_ascii_case("https"))
+                .unwrap_or(false)
+    }
+
+    fn https_location(&self, req: &Request) -> Option<String> {
+        let host = req.host()?;
+        let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        Some(match self.https_port {
+            Some(port) if port != 443 => format!("https://{}:{}{}", host, port, path),
+            _ => format!("https://{}{}", host, path),
+        })
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let req = ctx.state.request_unchecked();
+        if self.is_secure(req) {
+            return chain.next(ctx).await;
+        }
+
+        let res = match self.policy {
+            HttpsPolicy::Reject => Builder::new().status(403).build()?,
+            HttpsPolicy::Redirect => match self.https_location(req) {
+                Some(location) => Builder::new().status(308).header(header::LOCATION, location).build()?,
+                None => Builder::new().status(400).build()?,
+            },
+        };
+        ctx.after(res);
+        Ok(ctx)
+    }
+}
+
+impl Middleware for EnforceHttpsMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, request::TlsConnection, router::Router};
+
+    async fn serve(middleware: EnforceHttpsMiddleware, req: http::request::Builder) -> (u16, Option<String>) {
+        let middleware: &'static EnforceHttpsMiddleware = Box::leak(Box::new(middleware));
+        let router = Router::builder().route("/pay", http::Method::POST, |_req: Request<Body>| async { 201 }).build();
+        let req = Request::new(req.method(http::Method::POST).body(Body::empty()).unwrap(), None);
+        let mut ctx = middleware.next_inner(HttpContext::new(req, router), &MiddleChainEnd).await.unwrap();
+        let res = ctx.state.take_response().unwrap();
+        let location = res.headers().get(header::LOCATION).map(|l| l.to_str().unwrap().to_string());
+        (res.status().as_u16(), location)
+    }
+
+    fn plaintext() -> http::request::Builder {
+        http::Request::builder().uri("/pay?amount=10").header(header::HOST, "shop.example.com:8080")
+    }
+
+    #[tokio::test]
+    async fn plaintext_requests_are_redirected() {
+        let (status, location) = serve(EnforceHttpsMiddleware::redirect(), plaintext()).await;
+        assert_eq!(status, 308);
+        assert_eq!(location.as_deref(), Some("https://shop.example.com/pay?amount=10"));
+
+        let (_, location) = serve(EnforceHttpsMiddleware::redirect().https_port(8443), plaintext()).await;
+        assert_eq!(location.as_deref(), Some("https://shop.example.com:8443/pay?amount=10"));
+
+        let (status, _) = serve(EnforceHttpsMiddleware::redirect(), plaintext().extension(TlsConnection)).await;
+        assert_eq!(status, 201);
+    }
+
+    #[tokio::test]
+    async fn plaintext_requests_are_rejected() {
+        let (status, location) = serve(EnforceHttpsMiddleware::reject(), plaintext()).await;
+        assert_eq!((status, location), (403, None));
+
+        let (status, _) = serve(EnforceHttpsMiddleware::reject(), plaintext().extension(TlsConnection)).await;
+        assert_eq!(status, 201);
+    }
+
+    #[tokio::test]
+    async fn forwarded_proto_is_only_used_once_trusted() {
+        let forwarded = || plaintext().header(X_FORWARDED_PROTO, "https, http");
+        let (status, _) = serve(EnforceHttpsMiddleware::reject(), forwarded()).await;
+        assert_eq!(status, 403);
+
+        let (status, _) = serve(EnforceHttpsMiddleware::reject().trust_forwarded_proto(true), forwarded()).await;
+        assert_eq!(status, 201);
+
+        let proxied_plaintext = plaintext().header(X_FORWARDED_PROTO, "http");
+        let (status, _) = serve(EnforceHttpsMiddleware::reject().trust_forwarded_proto(true), proxied_plaintext).await;
+        assert_eq!(status, 403);
+    }
+}
@@ -24,6 +24,17 @@ pub enum InternalError {
     Stack,
 }
 
+impl Display for InternalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match self {
+            InternalError::Http(e) => Display::fmt(e, f),
+            InternalError::Hyper(e) => Display::fmt(e, f),
+            InternalError::ToStr(e) => Display::fmt(e, f),
+            InternalError::Stack => f.write_str("Stack"),
+        }
+    }
+}
+
 /// Error type throughout the saphir stack
 pub enum SaphirError {
     ///
@@ -52,10 +63,8 @@ pub enum SaphirError {
     /// Error from serializing form data
     #[cfg(feature = "form")]
     SerdeUrlSer(serde_urlencoded::ser::Error),
-    ///
-    MissingParameter(String, bool),
-    ///
-    InvalidParameter(String, bool),
+    /// A handler argument couldn't be extracted from the request
+    Extract(ExtractError),
 }
 
 impl Debug for SaphirError {
@@ -75,8 +84,7 @@ impl Debug for SaphirError {
             SaphirError::SerdeUrlDe(d) => std::fmt::Debug::fmt(d, f),
             #[cfg(feature = "form")]
             SaphirError::SerdeUrlSer(d) => std::fmt::Debug::fmt(d, f),
-            SaphirError::MissingParameter(d, _) => std::fmt::Debug::fmt(d, f),
-            SaphirError::InvalidParameter(d, _) => std::fmt::Debug::fmt(d, f),
+            SaphirError::Extract(d) => std::fmt::Debug::fmt(d, f),
         }
     }
 }
@@ -108,6 +116,12 @@ impl From<serde_urlencoded::ser::Error> for SaphirError {
     }
 }
 
+impl From<ExtractError> for SaphirError {
+    fn from(e: ExtractError) -> Self {
+        SaphirError::Extract(e)
+    }
+}
+
 impl From<HttpCrateError> for SaphirError {
     fn from(e: HttpCrateError) -> Self {
         SaphirError::Internal(InternalError::Http(e))
@@ -164,11 +178,11 @@ impl Responder for SaphirError {
         match self {
             SaphirError::Internal(e) => {
                 warn!("{}Saphir encountered an internal error that was returned as a responder: {:?}", op_id, e);
-                builder.status(500)
+                builder.status(500).extension(CapturedError(e.to_string()))
             }
             SaphirError::Io(e) => {
                 warn!("{}Saphir encountered an Io error that was returned as a responder: {:?}", op_id, e);
-                builder.status(500)
+                builder.status(500).extension(CapturedError(e.to_string()))
             }
             SaphirError::BodyAlreadyTaken => {
                 warn!("{}A controller handler attempted to take the request body more thant one time", op_id);
@@ -176,11 +190,11 @@ impl Responder for SaphirError {
             }
             SaphirError::Custom(e) => {
                 warn!("{}A custom error was returned as a responder: {:?}", op_id, e);
-                builder.status(500)
+                builder.status(500).extension(CapturedError(e.to_string()))
             }
             SaphirError::Other(e) => {
                 warn!("{}Saphir encountered an Unknown error that was returned as a responder: {:?}", op_id, e);
-                builder.status(500)
+                builder.status(500).extension(CapturedError(e.to_string()))
             }
             #[cfg(feature = "json")]
             SaphirError::SerdeJson(e) => {
@@ -197,24 +211,7 @@ impl Responder for SaphirError {
                 debug!("{}Unable to serialize form type: {:?}", op_id, e);
                 builder.status(400)
             }
-            SaphirError::MissingParameter(name, is_query) => {
-                if is_query {
-                    debug!("{}Missing query parameter {}", op_id, name);
-                } else {
-                    debug!("{}Missing path parameter {}", op_id, name);
-                }
-
-                builder.status(400)
-            }
-            SaphirError::InvalidParameter(name, is_query) => {
-                if is_query {
-                    debug!("{}Unable to parse query parameter {}", op_id, name);
-                } else {
-                    debug!("{}Unable to parse path parameter {}", op_id, name);
-                }
-
-                builder.status(400)
-            }
+            SaphirError::Extract(e) => e.respond_with_builder(builder, ctx),
             SaphirError::RequestMovedBeforeHandler => {
                 warn!(
                     "{}A request was moved out of its context by a middleware, but the middleware did not stop request processing",
@@ -230,3 +227,331 @@ impl Responder for SaphirError {
         }
     }
 }
+
+/// Where a handler parameter is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterLocation {
+    Path,
+    Query,
+}
+
+impl Display for ParameterLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match self {
+            ParameterLocation::Path => f.write_str("path"),
+            ParameterLocation::Query => f.write_str("query"),
+        }
+    }
+}
+
+/// Failure to extract a handler argument from a request, behind
+/// `SaphirError::Extract`.
+///
+/// Each variant responds with its `status()`, and its `Display` message as a
+/// plain text body. A handler wanting other responses can take the
+/// extraction result and match on the error:
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::error::ExtractError;
+/// # use std::collections::HashMap;
+/// // Quantities by item
+/// type Order = HashMap<String, u32>;
+///
+/// async fn order(mut req: Request<Body<Json<Order>>>) -> Result<u16, (u16, String)> {
+///     match req.body_mut().take_as::<Json<Order>>().await {
+///         Ok(_order) => Ok(201),
+///         Err(SaphirError::Extract(ExtractError::InvalidJson(e))) => Err((422, format!("Invalid order: {}", e))),
+///         Err(_) => Err((400, "Unable to read the order".to_string())),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ExtractError {
+    /// A required parameter wasn't sent
+    MissingParameter { name: String, location: ParameterLocation },
+    /// A parameter couldn't be parsed into the type of its argument
+    InvalidParameter { name: String, location: ParameterLocation },
+    /// The body isn't json, or doesn't match the type of its argument
+    #[cfg(feature = "json")]
+    InvalidJson(serde_json::error::Error),
+    /// The body or query string isn't a form matching the type of its
+    /// argument
+    #[cfg(feature = "form")]
+    InvalidForm(serde_urlencoded::de::Error),
+    /// Buffering the body went past the memory limit of the listener, in
+    /// bytes
+    TooLarge { limit: usize },
+    /// The body has a `Content-Type` header with none of the accepted media
+    /// types
+    UnsupportedMediaType { media_type: String },
+    /// The body is in an accepted media type, but not in one of its accepted
+    /// charsets
+    UnsupportedCharset { media_type: String, charset: String },
+    /// The body has no `Content-Type` header
+    MissingContentType,
+    /// The `Content-Type` header of the body can't be read, or lacks a
+    /// parameter its media type requires, like the `boundary` of multipart
+    /// bodies
+    MalformedContentType,
+}
+
+impl ExtractError {
+    /// Status the error responds with
+    pub fn status(&self) -> u16 {
+        match self {
+            ExtractError::TooLarge { .. } => 413,
+            ExtractError::UnsupportedMediaType { .. } | ExtractError::UnsupportedCharset { .. } | ExtractError::MissingContentType => 415,
+            _ => 400,
+        }
+    }
+}
+
+impl Display for ExtractError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match self {
+            ExtractError::MissingParameter { name, location } => write!(f, "Missing {} parameter: {}", location, name),
+            ExtractError::InvalidParameter { name, location } => write!(f, "Invalid {} parameter: {}", location, name),
+            #[cfg(feature = "json")]
+            ExtractError::InvalidJson(e) => write!(f, "Invalid json body: {}", e),
+            #[cfg(feature = "form")]
+            ExtractError::InvalidForm(e) => write!(f, "Invalid form: {}", e),
+            ExtractError::TooLarge { limit } => write!(f, "Request body larger than {} bytes", limit),
+            ExtractError::UnsupportedMediaType { media_type } => write!(f, "Unsupported media type: {}", media_type),
+            ExtractError::UnsupportedCharset { media_type, charset } => write!(f, "Unsupported charset for {}: {}", media_type, charset),
+            ExtractError::MissingContentType => f.write_str("Missing Content-Type"),
+            ExtractError::MalformedContentType => f.write_str("Malformed Content-Type"),
+        }
+    }
+}
+
+impl StdError for ExtractError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            #[cfg(feature = "json")]
+            ExtractError::InvalidJson(e) => Some(e),
+            #[cfg(feature = "form")]
+            ExtractError::InvalidForm(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Responder for ExtractError {
+    #[allow(unused_variables)]
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        let op_id = {
+            #[cfg(not(feature = "operation"))]
+            {
+                String::new()
+            }
+
+            #[cfg(feature = "operation")]
+            {
+                format!("[Operation id: {}] ", ctx.operation_id)
+            }
+        };
+
+        debug!("{}Unable to extract the request: {}", op_id, self);
+        builder
+            .status(self.status())
+            .header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(self.to_string())
+    }
+}
+
+/// Description of the error that produced a `500` response, attached to the
+/// response extensions so it can be reported once the request is done
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedError(pub String);
+
+/// Render an error and its whole `source()` chain on a single line
+fn error_chain(e: &(dyn StdError + 'static)) -> String {
+    let mut chain = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        chain.push_str(": ");
+        chain.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    chain
+}
+
+/// Log the error chain and answer with a generic 500. The chain is only
+/// written to the response body when `expose` is set, which is the case for
+/// debug builds.
+#[allow(unused_variables)]
+fn respond_with_error_chain(e: &(dyn StdError + 'static), builder: Builder, ctx: &HttpContext, expose: bool) -> Builder {
+    let op_id = {
+        #[cfg(not(feature = "operation"))]
+        {
+            String::new()
+        }
+
+        #[cfg(feature = "operation")]
+        {
+            format!("[Operation id: {}] ", ctx.operation_id)
+        }
+    };
+
+    let chain = error_chain(e);
+    warn!("{}A handler returned an error as a responder: {}", op_id, chain);
+    let builder = builder.status(500).extension(CapturedError(chain.clone()));
+    if expose {
+        builder.body(chain)
+    } else {
+        builder
+    }
+}
+
+impl Responder for Box<dyn StdError + Send + Sync> {
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        respond_with_error_chain(self.as_ref(), builder, ctx, cfg!(debug_assertions))
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl Responder for anyhow::Error {
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        respond_with_error_chain(self.as_ref(), builder, ctx, cfg!(debug_assertions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, request::Request, router::Router};
+
+    #[derive(Debug)]
+    struct TopError(IoError);
+
+    impl Display for TopError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+            f.write_str("unable to load the invoice")
+        }
+    }
+
+    impl StdError for TopError {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn ctx() -> HttpContext {
+        let req = Request::new(http::Request::builder().body(Body::empty()).unwrap(), None);
+        HttpContext::new(req, Router::builder().build())
+    }
+
+    async fn body_string(builder: Builder) -> (u16, String) {
+        let res = builder.build().unwrap();
+        let status = res.status().as_u16();
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    fn top_error() -> TopError {
+        TopError(IoError::new(std::io::ErrorKind::NotFound, "invoice.pdf is missing"))
+    }
+
+    #[test]
+    fn error_chain_includes_sources() {
+        assert_eq!(error_chain(&top_error()), "unable to load the invoice: invoice.pdf is missing");
+    }
+
+    #[tokio::test]
+    async fn error_message_is_hidden_unless_exposed() {
+        let (status, body) = body_string(respond_with_error_chain(&top_error(), Builder::new(), &ctx(), false)).await;
+        assert_eq!(status, 500);
+        assert!(body.is_empty());
+
+        let (status, body) = body_string(respond_with_error_chain(&top_error(), Builder::new(), &ctx(), true)).await;
+        assert_eq!(status, 500);
+        assert_eq!(body, "unable to load the invoice: invoice.pdf is missing");
+    }
+
+    #[tokio::test]
+    async fn boxed_error_responds_with_500() {
+        let e: Box<dyn StdError + Send + Sync> = Box::new(top_error());
+        let ctx = ctx();
+        let (status, _) = body_string(e.respond_with_builder(Builder::new(), &ctx)).await;
+        assert_eq!(status, 500);
+    }
+
+    #[tokio::test]
+    async fn extract_errors_respond_with_their_status_and_message() {
+        let path = ParameterLocation::Path;
+        let query = ParameterLocation::Query;
+        let mut cases = vec![
+            (
+                ExtractError::MissingParameter {
+                    name: "id".to_string(),
+                    location: path,
+                },
+                400,
+                "Missing path parameter: id",
+            ),
+            (
+                ExtractError::InvalidParameter {
+                    name: "page".to_string(),
+                    location: query,
+                },
+                400,
+                "Invalid query parameter: page",
+            ),
+            (ExtractError::TooLarge { limit: 1024 }, 413, "Request body larger than 1024 bytes"),
+            (
+                ExtractError::UnsupportedMediaType {
+                    media_type: "application/xml".to_string(),
+                },
+                415,
+                "Unsupported media type: application/xml",
+            ),
+            (
+                ExtractError::UnsupportedCharset {
+                    media_type: "application/json".to_string(),
+                    charset: "iso-8859-1".to_string(),
+                },
+                415,
+                "Unsupported charset for application/json: iso-8859-1",
+            ),
+            (ExtractError::MissingContentType, 415, "Missing Content-Type"),
+            (ExtractError::MalformedContentType, 400, "Malformed Content-Type"),
+        ];
+        #[cfg(feature = "json")]
+        cases.push((
+            ExtractError::InvalidJson(serde_json::from_str::<u32>("\"one\"").unwrap_err()),
+            400,
+            "Invalid json body: invalid type: string \"one\", expected u32 at line 1 column 5",
+        ));
+        #[cfg(feature = "form")]
+        cases.push((
+            ExtractError::InvalidForm(serde_urlencoded::from_str::<Vec<(String, u32)>>("page=one").unwrap_err()),
+            400,
+            "Invalid form: invalid digit found in string",
+        ));
+
+        let ctx = ctx();
+        for (e, status, message) in cases {
+            assert_eq!(e.status(), status);
+            // Wrapped or not, the error responds the same
+            assert_eq!(
+                body_string(SaphirError::from(e).respond_with_builder(Builder::new(), &ctx)).await,
+                (status, message.to_string())
+            );
+        }
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[tokio::test]
+    async fn anyhow_error_responds_with_500() {
+        let e = anyhow::Error::new(top_error()).context("GET /invoices/42 failed");
+        let ctx = ctx();
+        let (status, body) = body_string(e.respond_with_builder(Builder::new(), &ctx)).await;
+        assert_eq!(status, 500);
+        if cfg!(debug_assertions) {
+            assert!(body.starts_with("GET /invoices/42 failed: unable to load the invoice"));
+        } else {
+            assert!(body.is_empty());
+        }
+    }
+}
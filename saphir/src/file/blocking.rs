@@ -0,0 +1,127 @@
+use crate::error::SaphirError;
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+/// A blocking job handed to a `BlockingExecutor`
+pub type BlockingJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Executor running the blocking filesystem calls of the file middleware
+/// (metadata lookups, existence checks, ...) away from the async worker
+/// threads.
+pub trait BlockingExecutor: Send + Sync {
+    /// Run `job` on a thread where blocking is allowed
+    fn execute(&self, job: BlockingJob);
+}
+
+/// Run the jobs on the tokio blocking thread pool. This is the default
+/// executor of the file middleware.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioBlocking;
+
+impl BlockingExecutor for TokioBlocking {
+    fn execute(&self, job: BlockingJob) {
+        tokio::task::spawn_blocking(job);
+    }
+}
+
+/// Dedicated pool of `size` threads running the blocking jobs, so the file
+/// middleware doesn't compete with the rest of the application for the
+/// tokio blocking threads.
+///
+/// ```rust
+/// # use saphir::file::{blocking::BlockingPool, middleware::FileMiddlewareBuilder};
+/// let middleware = FileMiddlewareBuilder::new("static", "/var/www")
+///     .blocking_executor(BlockingPool::new(4))
+///     .build();
+/// ```
+pub struct BlockingPool {
+    sender: Mutex<Sender<BlockingJob>>,
+    size: usize,
+}
+
+impl BlockingPool {
+    /// Start a pool of `size` threads, at least one
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<BlockingJob>();
+        let receiver = std::sync::Arc::new(Mutex::new(receiver));
+        for i in 0..size {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("saphir-blocking-{}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // The pool was dropped
+                        Err(_) => break,
+                    }
+                })
+                .expect("Unable to spawn a blocking pool thread");
+        }
+
+        BlockingPool {
+            sender: Mutex::new(sender),
+            size,
+        }
+    }
+
+    /// Number of threads of the pool
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl BlockingExecutor for BlockingPool {
+    fn execute(&self, job: BlockingJob) {
+        if self.sender.lock().send(job).is_err() {
+            warn!("Blocking pool threads are gone, dropping a job");
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Blocking filesystem calls made by the thread, for the tests to check
+    /// the async worker threads make none
+    pub(crate) static FILESYSTEM_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Mark a blocking filesystem call, counted per thread in tests
+#[inline]
+pub(crate) fn filesystem_call() {
+    #[cfg(test)]
+    FILESYSTEM_CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+/// Run `f` on `executor` and wait for its result
+pub(crate) async fn run_blocking<F, T>(executor: &dyn BlockingExecutor, f: F) -> Result<T, SaphirError>
+where
+    F: 'static + FnOnce() -> T + Send,
+    T: 'static + Send,
+{
+    let (sender, receiver) = oneshot::channel();
+    executor.execute(Box::new(move || {
+        let _ = sender.send(f());
+    }));
+    receiver
+        .await
+        .map_err(|_| SaphirError::Other("Blocking job was dropped before completing".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pool_runs_jobs_on_its_own_threads() {
+        let pool = BlockingPool::new(2);
+        let name = run_blocking(&pool, || thread::current().name().map(str::to_string)).await.unwrap();
+        assert!(name.unwrap().starts_with("saphir-blocking-"));
+        assert_eq!(pool.size(), 2);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::file::MAX_BUFFER;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Read buffers recycled across file streams.
+///
+/// Every `FileStream` needs a `MAX_BUFFER` bytes buffer to read from its
+/// source. Streams built with a pool take their buffer from it and give it
+/// back once dropped, so servers sending a lot of files don't allocate a new
+/// one for each response. The pool is cheap to clone, clones share the same
+/// buffers.
+///
+/// ```rust
+/// # use saphir::file::{buffer_pool::BufferPool, middleware::FileMiddlewareBuilder};
+/// let pool = BufferPool::new(32);
+/// let middleware = FileMiddlewareBuilder::new("static", "/var/www").buffer_pool(pool).build();
+/// ```
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Create a pool keeping at most `max_buffers` idle buffers, buffers
+    /// given back past this limit are freed
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+        }
+    }
+
+    /// Number of idle buffers held by the pool
+    pub fn len(&self) -> usize {
+        self.buffers.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.buffers.lock().pop().unwrap_or_else(|| vec![0; MAX_BUFFER])
+    }
+
+    pub(crate) fn give_back(&self, buffer: Vec<u8>) {
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.max_buffers && buffer.len() == MAX_BUFFER {
+            buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{FileCursor, FileStream};
+    use futures::StreamExt;
+    use hyper::body::Bytes;
+    use std::path::PathBuf;
+
+    fn stream(pool: &BufferPool) -> FileStream {
+        FileStream::new(FileCursor::new(vec![1; 3 * MAX_BUFFER], None, PathBuf::from("pooled.bin"))).with_buffer_pool(pool.clone())
+    }
+
+    #[tokio::test]
+    async fn buffers_are_recycled() {
+        let pool = BufferPool::new(1);
+        let mut first = stream(&pool);
+        let mut second = stream(&pool);
+        assert!(first.next().await.is_some());
+        assert!(second.next().await.is_some());
+        assert!(pool.is_empty());
+
+        drop(first);
+        drop(second);
+        assert_eq!(pool.len(), 1);
+
+        let content: Vec<Bytes> = stream(&pool).map(|c| c.unwrap()).collect().await;
+        assert_eq!(content.iter().map(|c| c.len()).sum::<usize>(), 3 * MAX_BUFFER);
+        assert_eq!(pool.len(), 1);
+    }
+}
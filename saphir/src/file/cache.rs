@@ -1,6 +1,6 @@
 use crate::{
     error::SaphirError,
-    file::{compress_file, middleware::PathExt, Compression, Encoder, File, FileCursor, FileInfo, FileStream, SaphirFile, MAX_BUFFER},
+    file::{compress_file, Compression, Encoder, File, FileCursor, FileInfo, FileStream, SaphirFile, MAX_BUFFER},
 };
 use futures::{
     io::{AsyncRead, AsyncSeek, Cursor},
@@ -72,7 +72,7 @@ impl FileCache {
         if let Some(cached_file) = self.get((path_str.to_string(), compression)).await {
             Ok(FileStream::new(cached_file))
         } else if let Some(cached_raw_file) = self.get((path_str.to_string(), Compression::Raw)).await {
-            let file_size = cached_raw_file.get_path().size();
+            let file_size = cached_raw_file.get_size();
             if file_size + self.get_size().await <= self.max_capacity && file_size <= self.max_file_size {
                 let mime = cached_raw_file.get_mime().cloned();
                 let compressed_file = compress_file(Box::pin(cached_raw_file), Encoder::None, compression).await?;
@@ -88,7 +88,7 @@ impl FileCache {
             }
         } else {
             let file = File::open(path_str).await?;
-            let file_size = file.get_path().size();
+            let file_size = file.get_size();
             if file_size + self.get_size().await <= self.max_capacity && file_size <= self.max_file_size {
                 let mime = file.get_mime().cloned();
                 let compressed_file = compress_file(Box::pin(file), Encoder::None, compression).await?;
@@ -299,4 +299,8 @@ impl FileInfo for FileCacher {
     fn get_size(&self) -> u64 {
         self.inner.get_size()
     }
+
+    fn get_file_id(&self) -> Option<(u64, u64)> {
+        self.inner.get_file_id()
+    }
 }
@@ -0,0 +1,269 @@
+use crate::{
+    file::{FileInfo, FileStream, SaphirFile},
+    http_context::HttpContext,
+    responder::Responder,
+    response::Builder,
+};
+use futures::io::{AsyncRead, AsyncSeek};
+use futures_util::{
+    io::SeekFrom,
+    stream::Stream,
+    task::{Context, Poll},
+};
+use hyper::body::Bytes;
+use std::{io, path::PathBuf, pin::Pin};
+
+/// Several files read one after the other as if they were a single file
+struct ConcatFile {
+    files: Vec<Pin<Box<dyn SaphirFile>>>,
+    sizes: Vec<u64>,
+    /// Files that may not be positioned at their start anymore
+    dirty: Vec<bool>,
+    current: usize,
+    path: PathBuf,
+    size: u64,
+}
+
+impl ConcatFile {
+    fn new(files: Vec<Pin<Box<dyn SaphirFile>>>) -> Self {
+        let sizes: Vec<u64> = files.iter().map(|f| f.get_size()).collect();
+        let path = files.first().map(|f| f.get_path().clone()).unwrap_or_default();
+        ConcatFile {
+            dirty: vec![false; files.len()],
+            size: sizes.iter().sum(),
+            files,
+            sizes,
+            current: 0,
+            path,
+        }
+    }
+
+    /// Resolve an absolute offset to the index of the file holding it and the
+    /// offset inside that file
+    fn locate(&self, offset: u64) -> (usize, u64) {
+        let mut start = 0;
+        for (i, size) in self.sizes.iter().enumerate() {
+            if offset < start + size {
+                return (i, offset - start);
+            }
+            start += size;
+        }
+        (self.files.len(), 0)
+    }
+
+    fn position(&self, offset: u64) -> u64 {
+        self.sizes.iter().take(self.current).sum::<u64>() + offset
+    }
+}
+
+impl FileInfo for ConcatFile {
+    fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn get_mime(&self) -> Option<&mime::Mime> {
+        self.files.first().and_then(|f| f.get_mime())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for ConcatFile {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        while this.current < this.files.len() {
+            let current = this.current;
+            if this.dirty[current] {
+                match this.files[current].as_mut().poll_seek(cx, SeekFrom::Start(0)) {
+                    Poll::Ready(Ok(_)) => this.dirty[current] = false,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match this.files[current].as_mut().poll_read(cx, buf) {
+                Poll::Ready(Ok(0)) if !buf.is_empty() => {
+                    this.dirty[current] = true;
+                    this.current += 1;
+                }
+                res => return res,
+            }
+        }
+
+        Poll::Ready(Ok(0))
+    }
+}
+
+impl AsyncSeek for ConcatFile {
+    fn poll_seek(mut self: Pin<&mut Self>, cx: &mut Context<'_>, pos: SeekFrom) -> Poll<io::Result<u64>> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => offset_by(self.size, offset),
+            SeekFrom::Current(offset) => {
+                // Only the position in the current file is known, ask it
+                let current = self.current;
+                if current >= self.files.len() {
+                    offset_by(self.size, offset)
+                } else {
+                    match self.files[current].as_mut().poll_seek(cx, SeekFrom::Current(0)) {
+                        Poll::Ready(Ok(inner)) => offset_by(self.position(inner), offset),
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+            }
+        };
+
+        let target = match target {
+            Some(target) => target,
+            None => return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))),
+        };
+
+        let (index, offset) = self.locate(target);
+        if index < self.files.len() {
+            match self.files[index].as_mut().poll_seek(cx, SeekFrom::Start(offset)) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let this = &mut *self;
+        for (i, dirty) in this.dirty.iter_mut().enumerate() {
+            *dirty = i != index;
+        }
+        this.current = index;
+        Poll::Ready(Ok(target))
+    }
+}
+
+fn offset_by(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
+
+/// Stream of several files sent back to back as one body.
+///
+/// The size is the sum of the sources, and a range is resolved against the
+/// concatenation, so it can start in a file and end in another one. With
+/// `auto_range`, a stream assembled from segments serves the ranges clients
+/// request, seeking into the source holding the start of the range.
+///
+/// ```rust,no_run
+/// # use saphir::prelude::*;
+/// # use saphir::file::concat::ConcatStream;
+/// async fn logs(_req: Request<Body>) -> Result<ConcatStream, SaphirError> {
+///     let sources = vec![File::open("/var/log/app.log.1").await?, File::open("/var/log/app.log").await?];
+///     Ok(ConcatStream::new(sources).auto_range(true))
+/// }
+/// ```
+pub struct ConcatStream {
+    inner: FileStream,
+}
+
+impl ConcatStream {
+    pub fn new<T: SaphirFile + 'static>(sources: Vec<T>) -> Self {
+        let files = sources.into_iter().map(|f| Box::pin(f) as Pin<Box<dyn SaphirFile>>).collect();
+        ConcatStream {
+            inner: FileStream::new(ConcatFile::new(files)),
+        }
+    }
+
+    pub async fn set_range(&mut self, range: (u64, u64)) -> io::Result<()> {
+        self.inner.set_range(range).await
+    }
+
+    /// Serve the `Range` requested in the context, resolved against the
+    /// concatenation, see `FileStream::auto_range`
+    pub fn auto_range(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.auto_range(enabled);
+        self
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.inner.get_size()
+    }
+}
+
+impl Stream for ConcatStream {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl From<ConcatStream> for FileStream {
+    fn from(other: ConcatStream) -> Self {
+        other.inner
+    }
+}
+
+impl Responder for ConcatStream {
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        self.inner.respond_with_builder(builder, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::File;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    async fn sources() -> Vec<File> {
+        let mut files = Vec::new();
+        for (i, content) in [&b"first-"[..], &b"second-"[..], &b"third"[..]].iter().enumerate() {
+            let path = std::env::temp_dir().join(format!("saphir_concat_stream_{}.txt", i));
+            std::fs::File::create(&path).unwrap().write_all(content).unwrap();
+            files.push(File::open(path.to_str().unwrap()).await.unwrap());
+        }
+        files
+    }
+
+    async fn collect(mut stream: ConcatStream) -> Vec<u8> {
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        body
+    }
+
+    #[tokio::test]
+    async fn concatenates_all_sources() {
+        let stream = ConcatStream::new(sources().await);
+        assert_eq!(stream.get_size(), 18);
+        assert_eq!(collect(stream).await, b"first-second-third");
+    }
+
+    #[tokio::test]
+    async fn range_spans_file_boundaries() {
+        let mut stream = ConcatStream::new(sources().await);
+        stream.set_range((4, 14)).await.unwrap();
+        assert_eq!(collect(stream).await, b"t-second-th");
+    }
+
+    #[tokio::test]
+    async fn requested_range_is_served_across_sources() {
+        let req = http::Request::builder().header(http::header::RANGE, "bytes=3-8");
+        let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+
+        let res = ConcatStream::new(sources().await)
+            .auto_range(true)
+            .respond_with_builder(Builder::new(), &ctx)
+            .build()
+            .unwrap();
+        assert_eq!(res.status(), 206);
+        assert_eq!(res.headers().get(http::header::CONTENT_RANGE).unwrap(), "bytes 3-8/18");
+        assert_eq!(res.headers().get(http::header::CONTENT_LENGTH).unwrap(), "6");
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, &b"st-sec"[..]);
+    }
+}
@@ -0,0 +1,56 @@
+//! `Content-MD5` (RFC 1864) digests of served files.
+//!
+//! The digest lets legacy clients detect a corrupted download. It is not a
+//! security mechanism: anyone able to alter the body can recompute it.
+
+use crate::file::MAX_BUFFER;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::{prelude::*, sync::RwLock};
+
+/// Base64 encoded MD5 digest of the whole file at `path`
+pub async fn content_md5<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; MAX_BUFFER];
+    loop {
+        let size = file.read(&mut buffer).await?;
+        if size == 0 {
+            break;
+        }
+        context.consume(&buffer[..size]);
+    }
+
+    Ok(base64::encode(&context.compute()[..]))
+}
+
+/// Digests of files, computed again only when their modification time changes
+#[derive(Clone, Default)]
+pub struct ContentMd5Cache {
+    inner: Arc<RwLock<HashMap<PathBuf, (SystemTime, String)>>>,
+}
+
+impl ContentMd5Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+        let path = path.as_ref();
+        let mtime = tokio::fs::metadata(path).await?.modified()?;
+        if let Some((cached_mtime, digest)) = self.inner.read().await.get(path) {
+            if *cached_mtime == mtime {
+                return Ok(digest.clone());
+            }
+        }
+
+        let digest = content_md5(path).await?;
+        self.inner.write().await.insert(path.to_path_buf(), (mtime, digest.clone()));
+        Ok(digest)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::file::{blocking::filesystem_call, FileInfo};
+use flate2::write::GzDecoder;
+use futures::io::{AsyncRead, AsyncSeek};
+use futures_util::{
+    io::SeekFrom,
+    task::{Context, Poll},
+};
+use std::{
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+use tokio::{fs::File as TokioFile, io::AsyncRead as TokioAsyncRead};
+
+const READ_CHUNK: usize = 16_384;
+
+/// Size of the content of the gzip file at `path`, taken from its trailer.
+/// The trailer holds the size modulo 4 GiB, and only for the last member of
+/// the file, so it is only right for single member files under 4 GiB, like
+/// the ones produced by `gzip`.
+pub(crate) fn gzip_decoded_size(path: &Path) -> io::Result<u64> {
+    filesystem_call();
+    let mut file = std::fs::File::open(path)?;
+    file.seek(io::SeekFrom::End(-4))?;
+    let mut size = [0u8; 4];
+    file.read_exact(&mut size)?;
+    Ok(u32::from_le_bytes(size) as u64)
+}
+
+/// Decompressed content of a gzip file, decoded as it is read. Seeking is
+/// only supported forward, by decoding the skipped content.
+pub struct GzipDecodedFile {
+    inner: Pin<Box<TokioFile>>,
+    decoder: Option<GzDecoder<Vec<u8>>>,
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+    read_buffer: Vec<u8>,
+    /// Path of the decompressed file, the gzip file without its `.gz`
+    path: PathBuf,
+    size: u64,
+    position: u64,
+}
+
+impl GzipDecodedFile {
+    /// Open the gzip file `gzip_path` as the content of `path`, which is
+    /// `size` bytes once decompressed
+    pub async fn open(gzip_path: &Path, path: &Path, size: u64) -> io::Result<Self> {
+        Ok(GzipDecodedFile {
+            inner: Box::pin(TokioFile::open(gzip_path).await?),
+            decoder: Some(GzDecoder::new(Vec::new())),
+            decoded: Vec::new(),
+            decoded_pos: 0,
+            read_buffer: vec![0; READ_CHUNK],
+            path: path.to_path_buf(),
+            size,
+            position: 0,
+        })
+    }
+
+    /// Decode more of the file, resolving to `false` once it is all decoded
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        while self.decoded_pos >= self.decoded.len() {
+            let decoder = match self.decoder.as_mut() {
+                Some(decoder) => decoder,
+                None => return Poll::Ready(Ok(false)),
+            };
+
+            let read = match self.inner.as_mut().poll_read(cx, &mut self.read_buffer) {
+                Poll::Ready(Ok(read)) => read,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            self.decoded_pos = 0;
+            if read == 0 {
+                self.decoded = self.decoder.take().map(|decoder| decoder.finish()).transpose()?.unwrap_or_default();
+            } else {
+                decoder.write_all(&self.read_buffer[..read])?;
+                self.decoded = std::mem::take(decoder.get_mut());
+            }
+        }
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl FileInfo for GzipDecodedFile {
+    fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn get_mime(&self) -> Option<&mime::Mime> {
+        None
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for GzipDecodedFile {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_fill(cx) {
+            Poll::Ready(Ok(true)) => {
+                let available = &this.decoded[this.decoded_pos..];
+                let read = available.len().min(buf.len());
+                buf[..read].copy_from_slice(&available[..read]);
+                this.decoded_pos += read;
+                this.position += read as u64;
+                Poll::Ready(Ok(read))
+            }
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(0)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncSeek for GzipDecodedFile {
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context<'_>, pos: SeekFrom) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let target = match pos {
+            SeekFrom::Start(target) if target >= this.position => target,
+            _ => return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidInput, "gzip content can only be seeked forward"))),
+        };
+
+        while this.position < target {
+            match this.poll_fill(cx) {
+                Poll::Ready(Ok(true)) => {
+                    let skipped = ((this.decoded.len() - this.decoded_pos) as u64).min(target - this.position);
+                    this.decoded_pos += skipped as usize;
+                    this.position += skipped;
+                }
+                Poll::Ready(Ok(false)) => break,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(this.position))
+    }
+}
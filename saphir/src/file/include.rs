@@ -0,0 +1,270 @@
+use crate::{
+    file::{middleware::PathExt, File, FileStream},
+    http_context::HttpContext,
+    responder::Responder,
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use hyper::body::{Body as RawBody, Bytes, Sender};
+use std::path::{Component, Path, PathBuf};
+
+const DIRECTIVE_START: &[u8] = b"<!--#include";
+const DIRECTIVE_END: &[u8] = b"-->";
+/// Longest directive looked for, anything longer is sent as is
+const MAX_DIRECTIVE_LEN: usize = 1024;
+const DEFAULT_MAX_DEPTH: usize = 8;
+/// Emitted in place of a directive which could not be processed
+const DIRECTIVE_ERROR: &[u8] = b"[an error occurred while processing this directive]";
+
+/// A `FileStream` whose `<!--#include file="..." -->` directives are replaced
+/// by the content of the included files while it is streamed, see
+/// `FileStream::server_side_includes`.
+pub struct IncludeStream {
+    stream: FileStream,
+    root: PathBuf,
+    max_depth: usize,
+}
+
+impl FileStream {
+    /// Process the server-side include directives of the stream: each
+    /// `<!--#include file="partial.html" -->` is replaced by the content of
+    /// `partial.html`, found relative to the directory of the including
+    /// file. Included files are processed the same way.
+    ///
+    /// Includes must stay inside `root`: absolute paths and `..` components
+    /// are refused, as are files more than 8 levels of inclusion deep by
+    /// default. A directive which can't be processed is replaced by an error
+    /// message, the rest of the file is still sent.
+    ///
+    /// The assembled body has no known length, it is sent without
+    /// `Content-Length` and without support for ranges.
+    ///
+    /// ```rust,no_run
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::{include::IncludeStream, FileStream};
+    /// async fn page(_req: Request<Body>) -> Result<IncludeStream, SaphirError> {
+    ///     let file = File::open("/var/www/index.shtml").await?;
+    ///     Ok(FileStream::new(file).server_side_includes("/var/www"))
+    /// }
+    /// ```
+    pub fn server_side_includes<P: Into<PathBuf>>(self, root: P) -> IncludeStream {
+        IncludeStream {
+            stream: self,
+            root: root.into(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl IncludeStream {
+    /// How many levels of files can be included, 8 by default
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+}
+
+impl Responder for IncludeStream {
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        let path = self.stream.inner.get_path().clone();
+        let mime = self.stream.inner.get_mime().cloned().or_else(|| path.mime()).unwrap_or(mime::TEXT_HTML_UTF_8);
+        let dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+        let (mut sender, body) = RawBody::channel();
+        let IncludeStream { stream, root, max_depth } = self;
+        tokio::spawn(async move {
+            let includes = Includes { root, max_depth };
+            if includes.assemble(&mut sender, stream, dir, 0).await.is_err() {
+                sender.abort();
+            }
+        });
+
+        builder.expect_default_status().header(http::header::CONTENT_TYPE, mime.to_string()).body(body)
+    }
+}
+
+struct Includes {
+    root: PathBuf,
+    max_depth: usize,
+}
+
+/// Sending the assembled body failed, it must be aborted
+struct Aborted;
+
+impl Includes {
+    /// Send `stream`, coming from a file of the `dir` directory, with its
+    /// directives replaced
+    fn assemble<'a>(&'a self, sender: &'a mut Sender, mut stream: FileStream, dir: PathBuf, depth: usize) -> BoxFuture<'a, Result<(), Aborted>> {
+        async move {
+            let mut pending: Vec<u8> = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(|e| {
+                    warn!("Unable to read a file with server-side includes: {}", e);
+                    Aborted
+                })?;
+                pending.extend_from_slice(&chunk);
+
+                loop {
+                    let start = match find(&pending, DIRECTIVE_START) {
+                        Some(start) => start,
+                        None => {
+                            // Keep what could be the beginning of a directive cut by the chunk
+                            let keep = (DIRECTIVE_START.len() - 1).min(pending.len());
+                            let ready: Vec<u8> = pending.drain(..pending.len() - keep).collect();
+                            send(sender, ready).await?;
+                            break;
+                        }
+                    };
+
+                    let end = match find(&pending[start..], DIRECTIVE_END) {
+                        Some(end) => start + end + DIRECTIVE_END.len(),
+                        None if pending.len() - start > MAX_DIRECTIVE_LEN => {
+                            // Not a directive, only a comment looking like one
+                            let ready: Vec<u8> = pending.drain(..start + DIRECTIVE_START.len()).collect();
+                            send(sender, ready).await?;
+                            continue;
+                        }
+                        None => {
+                            let ready: Vec<u8> = pending.drain(..start).collect();
+                            send(sender, ready).await?;
+                            break;
+                        }
+                    };
+
+                    let before: Vec<u8> = pending.drain(..start).collect();
+                    send(sender, before).await?;
+                    let directive: Vec<u8> = pending.drain(..end - start).collect();
+                    self.include(sender, &directive, &dir, depth).await?;
+                }
+            }
+
+            send(sender, pending).await
+        }
+        .boxed()
+    }
+
+    /// Replace `directive` by the file it includes
+    async fn include(&self, sender: &mut Sender, directive: &[u8], dir: &Path, depth: usize) -> Result<(), Aborted> {
+        let included = match self.resolve(directive, dir, depth).await {
+            Ok(path) => path,
+            Err(reason) => {
+                warn!("Server-side include refused: {}", reason);
+                return send(sender, DIRECTIVE_ERROR.to_vec()).await;
+            }
+        };
+
+        match File::open(included.to_str().unwrap_or_default()).await {
+            Ok(file) => {
+                let dir = included.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+                self.assemble(sender, FileStream::new(file), dir, depth + 1).await
+            }
+            Err(e) => {
+                warn!("Unable to open included file {}: {}", included.display(), e);
+                send(sender, DIRECTIVE_ERROR.to_vec()).await
+            }
+        }
+    }
+
+    /// Path of the file included by `directive`, checked to be inside the root
+    async fn resolve(&self, directive: &[u8], dir: &Path, depth: usize) -> Result<PathBuf, String> {
+        if depth >= self.max_depth {
+            return Err(format!("more than {} levels of includes", self.max_depth));
+        }
+
+        let directive = String::from_utf8_lossy(directive);
+        let file = directive
+            .split("file=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .ok_or_else(|| format!("malformed directive {}", directive))?;
+        let relative = Path::new(file);
+        if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("{} leaves the directory of the including file", file));
+        }
+
+        let path = tokio::fs::canonicalize(dir.join(relative)).await.map_err(|e| format!("{}: {}", file, e))?;
+        let root = tokio::fs::canonicalize(&self.root)
+            .await
+            .map_err(|e| format!("{}: {}", self.root.display(), e))?;
+        if !path.starts_with(&root) {
+            return Err(format!("{} is outside of {}", path.display(), root.display()));
+        }
+
+        Ok(path)
+    }
+}
+
+async fn send(sender: &mut Sender, data: Vec<u8>) -> Result<(), Aborted> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    sender.send_data(Bytes::from(data)).await.map_err(|_| Aborted)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn www(files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join("saphir_server_side_includes");
+        for (name, content) in files {
+            let path = root.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+        }
+        root
+    }
+
+    async fn assembled(root: &Path, name: &str, max_depth: usize) -> String {
+        let req = crate::request::Request::new(http::Request::builder().body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+        let file = File::open(root.join(name).to_str().unwrap()).await.unwrap();
+        let res = FileStream::new(file)
+            .server_side_includes(root)
+            .max_depth(max_depth)
+            .respond_with_builder(Builder::new(), &ctx)
+            .build()
+            .unwrap();
+        assert!(res.headers().get(http::header::CONTENT_LENGTH).is_none());
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn partials_are_spliced_in() {
+        let root = www(&[
+            ("index.shtml", "<html><!--#include file=\"partials/header.html\" --><p>body</p></html>"),
+            ("partials/header.html", "<header><!--#include file=\"logo.html\" --></header>"),
+            ("partials/logo.html", "<img src=\"logo.png\">"),
+        ]);
+
+        assert_eq!(
+            assembled(&root, "index.shtml", 8).await,
+            "<html><header><img src=\"logo.png\"></header><p>body</p></html>"
+        );
+        assert_eq!(
+            assembled(&root, "index.shtml", 1).await,
+            "<html><header>[an error occurred while processing this directive]</header><p>body</p></html>"
+        );
+    }
+
+    #[tokio::test]
+    async fn includes_cannot_leave_the_root() {
+        let root = www(&[
+            (
+                "escape.shtml",
+                "a<!--#include file=\"../escape.shtml\" -->b<!--#include file=\"/etc/passwd\" -->c",
+            ),
+            ("recursive.shtml", "x<!--#include file=\"recursive.shtml\" -->"),
+        ]);
+
+        let error = String::from_utf8(DIRECTIVE_ERROR.to_vec()).unwrap();
+        assert_eq!(assembled(&root, "escape.shtml", 8).await, format!("a{}b{}c", error, error));
+        assert_eq!(assembled(&root, "recursive.shtml", 3).await, format!("xxxx{}", error));
+    }
+}
@@ -1,30 +1,151 @@
 use crate::{
     file::{
+        blocking::{filesystem_call, run_blocking, BlockingExecutor, TokioBlocking},
+        buffer_pool::BufferPool,
         cache::FileCache,
         conditional_request::{format_systemtime, is_fresh, is_precondition_failed},
+        content_md5::ContentMd5Cache,
         etag::{EntityTag, SystemTimeExt},
-        range::Range,
+        gzip::{gzip_decoded_size, GzipDecodedFile},
+        mime_db::MimeDatabase,
+        range::{Range, RangeUnitHandler},
         range_requests::{extract_range, is_range_fresh, is_satisfiable_range},
-        Compression,
+        transcode::ImageTranscoding,
+        Compression, FileStream,
     },
     prelude::*,
 };
+use futures::future::BoxFuture;
 use mime::Mime;
 use mime_guess::from_path;
 use percent_encoding::percent_decode;
 use std::{
-    path::{Path, PathBuf},
-    str::{FromStr, Utf8Error},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
     time::SystemTime,
 };
 
 const DEFAULT_CACHE_MAX_FILE_SIZE: u64 = 2_097_152;
 const DEFAULT_CACHE_MAX_CAPACITY: u64 = 536_870_912;
 
+/// Middleware serving static files from `www_path`.
+///
+/// Responses are compressed according to `Accept-Encoding`, unless the request
+/// carries a satisfiable `Range`: ranges always apply to the identity
+/// representation, so partial responses are sent uncompressed. A client
+/// refusing every coding the file can be sent with, `identity` included, gets
+/// a `406 Not Acceptable`.
+///
+/// With `FileMiddlewareBuilder::precompressed_gzip`, a `<file>.gz` sibling is
+/// sent as is to the clients accepting gzip: it is then the entity, and a
+/// `Range` applies to its compressed bytes. The other clients get the
+/// identity file, or the decompressed content of the sibling when there is no
+/// identity file, with ranges applying to the decompressed bytes.
+///
+/// Ranges in a unit other than `bytes` get a `416 Range Not Satisfiable`,
+/// unless a `RangeUnitHandler` is registered for the unit with
+/// `FileMiddlewareBuilder::range_unit_handler`.
+///
+/// Request paths are mapped under `www_path` component by component, and
+/// paths climbing out of it with `..` are refused with a `400 Bad Request`.
+/// A `FileAuthorizer` set with `FileMiddlewareBuilder::authorizer` is asked
+/// about the mapped path before anything is looked up on the filesystem.
+///
+/// With `FileMiddlewareBuilder::image_transcoding`, JPEG and PNG files are
+/// sent transcoded to the clients accepting a better format, see
+/// `ImageTranscoding`.
 pub struct FileMiddleware {
     base_path: PathBuf,
     www_path: PathBuf,
+    mounted: bool,
     cache: FileCache,
+    content_md5: Option<ContentMd5Cache>,
+    accept_variants: Vec<(String, String)>,
+    buffer_pool: Option<BufferPool>,
+    mime_database: Option<MimeDatabase>,
+    blocking_executor: Arc<dyn BlockingExecutor>,
+    precompressed_gzip: bool,
+    ignore_range: bool,
+    ignore_range_extensions: Vec<String>,
+    range_unit_handlers: Vec<(String, Arc<dyn RangeUnitHandler>)>,
+    authorizer: Option<Arc<dyn FileAuthorizer>>,
+    image_transcoding: Option<ImageTranscoding>,
+}
+
+/// Decision of a `FileAuthorizer`: a `bool`, or a boxed future of one for
+/// checks that have to wait, e.g. on a session store
+pub trait Authorization {
+    fn into_future(self) -> BoxFuture<'static, bool>;
+}
+
+impl Authorization for bool {
+    fn into_future(self) -> BoxFuture<'static, bool> {
+        Box::pin(futures::future::ready(self))
+    }
+}
+
+impl Authorization for BoxFuture<'static, bool> {
+    fn into_future(self) -> BoxFuture<'static, bool> {
+        self
+    }
+}
+
+/// Check deciding whether the request of `ctx` may be served the file at
+/// `path`, see `FileMiddlewareBuilder::authorizer`. It is implemented by the
+/// closures taking the context and the path, and returning an
+/// `Authorization`.
+pub trait FileAuthorizer: Send + Sync {
+    /// Whether `path`, the request path mapped under `www_path`, may be served.
+    /// Nothing was looked up yet on the filesystem: the path may not exist,
+    /// or be a directory whose `index.html` would be served
+    fn authorize(&self, ctx: &HttpContext, path: &Path) -> BoxFuture<'static, bool>;
+}
+
+impl<F, A> FileAuthorizer for F
+where
+    F: Fn(&HttpContext, &Path) -> A + Send + Sync,
+    A: Authorization,
+{
+    fn authorize(&self, ctx: &HttpContext, path: &Path) -> BoxFuture<'static, bool> {
+        (self)(ctx, path).into_future()
+    }
+}
+
+/// Outcome of mapping a request path to a file
+enum Resolved {
+    File(ResolvedFile),
+    Status(u16),
+}
+
+/// The identity representation of a file: `last_modified` and `size` come
+/// from the gzip sibling and its decompressed content when only it exists.
+/// A transcoded image is served from the cache with the `last_modified` of
+/// its source
+struct ResolvedFile {
+    path: PathBuf,
+    is_variant: bool,
+    transcoded: bool,
+    identity_exists: bool,
+    last_modified: SystemTime,
+    size: u64,
+    mime_type: Mime,
+    gzip: Option<GzipSibling>,
+}
+
+/// A precompressed `<file>.gz`
+struct GzipSibling {
+    path: PathBuf,
+    last_modified: SystemTime,
+    size: u64,
+}
+
+/// How the gzip sibling of a file is served
+enum Precompressed {
+    /// As is, encoded
+    Encoded(GzipSibling),
+    /// Decompressed, there being no identity file
+    Decoded(GzipSibling),
 }
 
 impl FileMiddleware {
@@ -32,89 +153,191 @@ impl FileMiddleware {
         FileMiddleware {
             base_path: PathBuf::from(base_path.to_string()),
             www_path: PathBuf::from(www_path.to_string()),
+            mounted: false,
             cache: FileCache::new(DEFAULT_CACHE_MAX_FILE_SIZE, DEFAULT_CACHE_MAX_CAPACITY),
+            content_md5: None,
+            accept_variants: Vec::new(),
+            buffer_pool: None,
+            mime_database: None,
+            blocking_executor: Arc::new(TokioBlocking),
+            precompressed_gzip: false,
+            ignore_range: false,
+            ignore_range_extensions: Vec::new(),
+            range_unit_handlers: Vec::new(),
+            authorizer: None,
+            image_transcoding: None,
         }
     }
 
-    async fn next_inner(&self, mut ctx: HttpContext, _chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+    /// Serve the directory `root` at the URL `prefix`: the prefix is stripped
+    /// from the request path before mapping it under `root`, so with a prefix
+    /// of `/assets`, `/assets/app.js` is served from `<root>/app.js`. Paths
+    /// outside of the prefix get a `404 Not Found`.
+    ///
+    /// The middleware has to be applied to the whole prefix with a catch-all
+    /// segment:
+    ///
+    /// ```rust
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::middleware::FileMiddleware;
+    /// let server = Server::builder()
+    ///     .configure_middlewares(|m| m.apply(FileMiddleware::mount("/assets", "/var/www/assets"), vec!["/assets/**"], None))
+    ///     .build();
+    /// ```
+    pub fn mount(prefix: &str, root: &str) -> Self {
+        FileMiddleware {
+            base_path: PathBuf::from(prefix.trim_matches('/')),
+            mounted: true,
+            ..Self::new("", root)
+        }
+    }
+
+    async fn next_inner(&'static self, mut ctx: HttpContext, _chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
         let mut builder = Builder::new();
         let mut cache = self.cache.clone();
         let req = ctx.state.request_unchecked();
-        let path = match self.file_path_from_path(req.uri().path()) {
-            Ok(path) => path,
-            Err(_) => {
-                ctx.after(builder.status(400).build()?);
+        let uri_path = req.uri().path().to_string();
+        let accept = req.headers().get(header::ACCEPT).and_then(|h| h.to_str().ok()).unwrap_or_default().to_string();
+        if let Some(authorizer) = self.authorizer.as_ref() {
+            let status = match self.mapped_path(&uri_path) {
+                Ok(path) if authorizer.authorize(&ctx, &path).await => None,
+                Ok(path) => {
+                    debug!("Access to {} denied by the authorizer", path.display());
+                    Some(403)
+                }
+                Err(status) => Some(status),
+            };
+            if let Some(status) = status {
+                ctx.after(builder.status(status).build()?);
                 return Ok(ctx);
             }
-        };
-
-        let path = match (self.path_exists(&path), path.extension().is_none()) {
-            (false, false) => {
-                info!("Path doesn't exist: {}", path.display());
-                ctx.after(builder.status(404).build()?);
+        }
+        let resolved = run_blocking(&*self.blocking_executor, move || self.resolve(&uri_path, &accept)).await?;
+        let ResolvedFile {
+            path,
+            is_variant,
+            transcoded,
+            identity_exists,
+            last_modified,
+            size,
+            mime_type,
+            gzip,
+        } = match resolved {
+            Resolved::File(file) => file,
+            Resolved::Status(status) => {
+                ctx.after(builder.status(status).build()?);
                 return Ok(ctx);
             }
-            (false, true) => {
-                let index_path = match self.file_path_from_path("/index.html") {
-                    Ok(path) => path,
-                    Err(_) => {
-                        ctx.after(builder.status(400).build()?);
-                        return Ok(ctx);
-                    }
-                };
-                if !self.path_exists(&index_path) {
-                    info!("Path doesn't exist: {}", path.display());
-                    ctx.after(builder.status(404).build()?);
-                    return Ok(ctx);
-                } else {
-                    index_path
-                }
-            }
-            (true, _) => path,
         };
 
-        if !self.path_is_under_base_path(&path) {
-            ctx.after(builder.status(401).build()?);
-            return Ok(ctx);
-        }
+        let req = ctx.state.request_unchecked();
+        let accept_encoding = req.headers().get(header::ACCEPT_ENCODING).and_then(|header| header.to_str().ok());
+        let identity_accepted = encoding_quality(accept_encoding, Compression::Raw) > 0.0;
+        let has_gzip_sibling = gzip.is_some();
+        let identity_size = size;
+        let precompressed = match gzip {
+            Some(gzip) if encoding_quality(accept_encoding, Compression::Gzip) > 0.0 => Some(Precompressed::Encoded(gzip)),
+            Some(gzip) if !identity_exists => Some(Precompressed::Decoded(gzip)),
+            _ => None,
+        };
 
-        let (last_modified, mut size) = (path.mtime(), path.size());
-        let etag = EntityTag::new(false, format!("{}-{}", last_modified.timestamp(), size).as_str());
+        // The encoded sibling is an entity of its own, with its own validators
+        let (last_modified, mut size, tag) = match precompressed.as_ref() {
+            Some(Precompressed::Encoded(gzip)) => (gzip.last_modified, gzip.size, format!("{}-{}-gz", gzip.last_modified.timestamp(), gzip.size)),
+            _ if transcoded => (last_modified, size, format!("{}-{}-{}", last_modified.timestamp(), size, mime_type.subtype())),
+            _ => (last_modified, size, format!("{}-{}", last_modified.timestamp(), size)),
+        };
+        let etag = EntityTag::new(false, tag.as_str());
+        if has_gzip_sibling {
+            builder = builder.header(header::VARY, "Accept-Encoding");
+        }
 
         if is_precondition_failed(req, &etag, &last_modified) {
             ctx.after(builder.status(412).build()?);
             return Ok(ctx);
         }
 
-        let mime_type = Self::guess_path_mime(&path);
-
         if is_fresh(&req, &etag, &last_modified) {
+            if is_variant {
+                builder = builder.header(header::VARY, "Accept");
+            }
             ctx.after(builder.status(304).header(header::LAST_MODIFIED, format_systemtime(last_modified)).build()?);
             return Ok(ctx);
         }
 
         let mut is_partial_content = false;
-
-        let compression = req
-            .headers()
-            .get(header::ACCEPT_ENCODING)
-            .and_then(|header| header.to_str().ok())
-            .map(|str| str.split(',').map(|encoding| Compression::from_str(encoding.trim()).unwrap_or_default()).max())
-            .flatten()
-            .unwrap_or_default();
-
-        if let Some(range) = req
+        let ignore_range = self.ignores_range(&path);
+        let range = req
             .headers()
             .get(header::RANGE)
             .and_then(|header| header.to_str().ok())
             .and_then(|header| Range::from_str(header).ok())
-        {
+            .filter(|_| !ignore_range);
+
+        // Ranges of other units apply to the identity file, through the handler of
+        // their unit
+        if let Some(Range::Unregistered(unit, range_set)) = range.as_ref() {
+            if is_range_fresh(req, &etag, &last_modified) {
+                let partial = match self.range_unit_handler(unit).filter(|_| identity_exists) {
+                    Some(handler) => {
+                        let (path, range_set) = (path.clone(), range_set.clone());
+                        run_blocking(&*self.blocking_executor, move || handler.respond(&path, identity_size, &range_set)).await?
+                    }
+                    None => None,
+                };
+                let mut builder = match partial {
+                    Some(partial) if has_gzip_sibling => partial.header(header::VARY, "Accept-Encoding"),
+                    Some(partial) => partial,
+                    None => builder.status(StatusCode::RANGE_NOT_SATISFIABLE),
+                };
+                if is_variant {
+                    builder = builder.header(header::VARY, "Accept");
+                }
+                ctx.after(
+                    builder
+                        .header(http::header::ACCEPT_RANGES, self.accept_ranges(false))
+                        .header(header::ETAG, etag.get_tag())
+                        .build()?,
+                );
+                return Ok(ctx);
+            }
+        }
+
+        // Precompressed files are never encoded on the fly
+        let negotiated = match precompressed.as_ref() {
+            Some(Precompressed::Encoded(_)) => Some(Compression::Raw),
+            Some(Precompressed::Decoded(_)) => Some(Compression::Raw).filter(|_| identity_accepted),
+            None => negotiate_compression(accept_encoding),
+        };
+        let mut compression = match negotiated {
+            Some(compression) => compression,
+            None => {
+                ctx.after(builder.status(406).build()?);
+                return Ok(ctx);
+            }
+        };
+
+        // A satisfiable range takes precedence over compression: byte ranges always
+        // refer to the identity representation, so partial content is never encoded.
+        // A client refusing the identity representation gets the whole encoded file.
+        // The encoded sibling is the identity of the compressed representation, ranges
+        // apply to its compressed bytes.
+        if let Some(range) = range.filter(|range| range.unit() == "bytes" && (identity_accepted || precompressed.is_some())) {
             if let (true, Some(content_range)) = (is_range_fresh(&req, &etag, &last_modified), is_satisfiable_range(&range, size as u64)) {
-                if let Some(range) = extract_range(&content_range) {
-                    let file = cache.open_file_with_range(&path, range).await?;
-                    size = file.get_size();
-                    builder = builder.file(file);
+                if let Some((start, end)) = extract_range(&content_range) {
+                    let file = match precompressed.as_ref() {
+                        Some(Precompressed::Encoded(gzip)) => cache.open_file_with_range(&gzip.path, (start, end)).await?,
+                        Some(Precompressed::Decoded(gzip)) => {
+                            let mut file = FileStream::new(GzipDecodedFile::open(&gzip.path, &path, size).await?);
+                            file.set_range((start, end)).await?;
+                            file
+                        }
+                        None => cache.open_file_with_range(&path, (start, end)).await?,
+                    };
+                    size = end - start + 1;
+                    builder = builder.file(self.pooled(file));
                 }
+                compression = Compression::Raw;
                 builder = builder
                     .header(http::header::CONTENT_RANGE, content_range.to_string())
                     .status(StatusCode::PARTIAL_CONTENT);
@@ -123,17 +346,32 @@ impl FileMiddleware {
         }
 
         if !is_partial_content {
-            let file = cache.open_file(&path, compression).await?;
+            let file = match precompressed.as_ref() {
+                Some(Precompressed::Encoded(gzip)) => cache.open_file(&gzip.path, Compression::Raw).await?,
+                Some(Precompressed::Decoded(gzip)) => FileStream::new(GzipDecodedFile::open(&gzip.path, &path, size).await?),
+                None => cache.open_file(&path, compression).await?,
+            };
+            let file = self.pooled(file);
             size = file.get_size();
             builder = builder.file(file);
         }
 
-        if compression != Compression::Raw {
-            builder = builder.header(header::CONTENT_ENCODING, compression.to_string())
+        if let Some(Precompressed::Encoded(_)) = precompressed {
+            builder = builder.header(header::CONTENT_ENCODING, Compression::Gzip.to_string());
+            ctx.set_response_encoding(Compression::Gzip.to_string());
+        } else if compression != Compression::Raw {
+            builder = builder.header(header::CONTENT_ENCODING, compression.to_string());
+            ctx.set_response_encoding(compression.to_string());
+        } else if let (Some(md5_cache), false, None) = (&self.content_md5, is_partial_content, precompressed.as_ref()) {
+            builder = builder.header("Content-MD5", md5_cache.get(&path).await?);
+        }
+
+        if is_variant {
+            builder = builder.header(header::VARY, "Accept");
         }
 
         builder = builder
-            .header(http::header::ACCEPT_RANGES, "bytes")
+            .header(http::header::ACCEPT_RANGES, self.accept_ranges(ignore_range))
             .header(header::CONTENT_TYPE, mime_type.to_string())
             .header(header::CONTENT_LENGTH, size)
             .header(header::CACHE_CONTROL, "public")
@@ -144,23 +382,180 @@ impl FileMiddleware {
         Ok(ctx)
     }
 
-    fn file_path_from_path(&self, path: &str) -> Result<PathBuf, Utf8Error> {
-        percent_decode(path[1..].as_bytes())
-            .decode_utf8()
-            .map(|path_str| PathBuf::from(path_str.to_string()))
-            .map(|path| {
-                if path.starts_with(&self.base_path) {
-                    path.strip_prefix(&self.base_path).unwrap().to_path_buf()
+    /// Whether the `Range` of the requests for `path` is ignored
+    fn ignores_range(&self, path: &Path) -> bool {
+        self.ignore_range
+            || path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| self.ignore_range_extensions.iter().any(|ignored| ignored.eq_ignore_ascii_case(e)))
+                .unwrap_or(false)
+    }
+
+    /// The handler of the range `unit`, units being case-insensitive
+    fn range_unit_handler(&self, unit: &str) -> Option<Arc<dyn RangeUnitHandler>> {
+        self.range_unit_handlers
+            .iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(unit))
+            .map(|(_, handler)| handler.clone())
+    }
+
+    /// `Accept-Ranges` of the served files: `bytes` and the units with a
+    /// handler
+    fn accept_ranges(&self, ignore_range: bool) -> String {
+        if ignore_range {
+            return "none".to_string();
+        }
+        let mut units = vec!["bytes"];
+        units.extend(self.range_unit_handlers.iter().map(|(unit, _)| unit.as_str()));
+        units.join(", ")
+    }
+
+    /// Map the request path to the file to serve, along with its metadata.
+    /// Blocks on the filesystem, so it runs on the blocking executor
+    fn resolve(&self, uri_path: &str, accept: &str) -> Resolved {
+        let path = match self.file_path_from_path(uri_path) {
+            Ok(path) => path,
+            Err(status) => return Resolved::Status(status),
+        };
+
+        let variant = if !self.path_exists(&path) && path.extension().is_none() {
+            self.accept_variant(accept, &path)
+        } else {
+            None
+        };
+        let is_variant = variant.is_some();
+        let path = variant.unwrap_or(path);
+
+        let path = match (self.path_or_gzip_exists(&path), path.extension().is_none()) {
+            (false, false) => {
+                info!("Path doesn't exist: {}", path.display());
+                return Resolved::Status(404);
+            }
+            (false, true) => {
+                let index_path = self.www_path.join("index.html");
+                if !self.path_or_gzip_exists(&index_path) {
+                    info!("Path doesn't exist: {}", path.display());
+                    return Resolved::Status(404);
                 } else {
-                    path
+                    index_path
                 }
-            })
-            .map(|path| self.www_path.join(path))
-            .map(|path| if path.is_dir() { path.join("index.html") } else { path })
+            }
+            (true, _) => path,
+        };
+
+        if !self.path_is_under_base_path(&path) {
+            return Resolved::Status(401);
+        }
+
+        let gzip = Some(gzip_path(&path))
+            .filter(|gzip_path| self.precompressed_gzip && self.path_exists(gzip_path))
+            .map(|gzip_path| GzipSibling {
+                last_modified: gzip_path.mtime(),
+                size: gzip_path.size(),
+                path: gzip_path,
+            });
+        let identity_exists = self.path_exists(&path);
+        let (last_modified, size) = match gzip.as_ref() {
+            Some(gzip) if !identity_exists => match gzip_decoded_size(&gzip.path) {
+                Ok(size) => (gzip.last_modified, size),
+                Err(_) => return Resolved::Status(500),
+            },
+            _ => (path.mtime(), path.size()),
+        };
+
+        let mime_type = self.guess_path_mime(&path);
+        let transcoding = self.image_transcoding.as_ref().filter(|t| identity_exists && t.transcodes(&mime_type));
+        let transcoded = transcoding.and_then(|t| {
+            let to = t.targets().iter().find(|to| accepts(accept, to.essence_str()))?;
+            t.cached(&path, &mime_type, to, last_modified.timestamp(), size)
+                .map(|cached| (cached, to.clone()))
+        });
+        if let Some((cached, to)) = transcoded {
+            return Resolved::File(ResolvedFile {
+                last_modified,
+                size: cached.size(),
+                mime_type: to,
+                is_variant: true,
+                transcoded: true,
+                identity_exists,
+                gzip: None,
+                path: cached,
+            });
+        }
+
+        // Clients not accepting the transcoded formats get the source, it varies on
+        // `Accept` too
+        Resolved::File(ResolvedFile {
+            last_modified,
+            size,
+            mime_type,
+            is_variant: is_variant || transcoding.is_some(),
+            transcoded: false,
+            identity_exists,
+            gzip,
+            path,
+        })
+    }
+
+    /// Whether the file at `path`, or its gzip sibling if they are served,
+    /// exists
+    fn path_or_gzip_exists(&self, path: &Path) -> bool {
+        self.path_exists(path) || (self.precompressed_gzip && self.path_exists(gzip_path(path)))
+    }
+
+    /// Map a request path to a path under `www_path`, or the status to answer
+    /// with when it can't be
+    fn file_path_from_path(&self, path: &str) -> Result<PathBuf, u16> {
+        let file_path = self.mapped_path(path)?;
+        filesystem_call();
+        Ok(if file_path.is_dir() { file_path.join("index.html") } else { file_path })
+    }
+
+    /// Map a request path to a path under `www_path` without looking it up
+    fn mapped_path(&self, path: &str) -> Result<PathBuf, u16> {
+        let decoded = percent_decode(path.trim_start_matches('/').as_bytes()).decode_utf8().map_err(|_| 400u16)?;
+        let path = Path::new(decoded.as_ref());
+        let path = match path.strip_prefix(&self.base_path) {
+            Ok(stripped) => stripped,
+            Err(_) if self.mounted => return Err(404),
+            Err(_) => path,
+        };
+
+        // Only plain names are kept: `..`, or a root left by a decoded `//`,
+        // would lead out of `www_path`
+        let mut file_path = self.www_path.clone();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => file_path.push(name),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(400),
+            }
+        }
+
+        Ok(file_path)
+    }
+
+    fn pooled(&self, file: FileStream) -> FileStream {
+        match self.buffer_pool.as_ref() {
+            Some(pool) => file.with_buffer_pool(pool.clone()),
+            None => file,
+        }
+    }
+
+    /// Pick the first existing `path.<extension>` whose type is accepted by the
+    /// client, following the order the variants were registered in
+    fn accept_variant(&self, accept: &str, path: &Path) -> Option<PathBuf> {
+        self.accept_variants
+            .iter()
+            .filter(|(mime, _)| mime == "*/*" || accepts(accept, mime))
+            .map(|(_, extension)| path.with_extension(extension))
+            .find(|path| self.path_exists(path))
     }
 
     fn path_exists<P: AsRef<Path>>(&self, path: P) -> bool {
         let path = path.as_ref();
+        filesystem_call();
         path.exists() && !self.path_is_hidden(path)
     }
 
@@ -172,13 +567,39 @@ impl FileMiddleware {
         Path::starts_with(path.as_ref(), &self.www_path)
     }
 
-    fn guess_path_mime<P: AsRef<Path>>(path: P) -> mime::Mime {
+    fn guess_path_mime<P: AsRef<Path>>(&self, path: P) -> mime::Mime {
         let path = path.as_ref();
-        path.mime()
-            .unwrap_or_else(|| if path.is_dir() { mime::TEXT_HTML_UTF_8 } else { mime::TEXT_PLAIN_UTF_8 })
+        let mime = match self.mime_database.as_ref() {
+            Some(db) => db.get(path),
+            None => path.mime(),
+        };
+        mime.unwrap_or_else(|| {
+            filesystem_call();
+            if path.is_dir() {
+                mime::TEXT_HTML_UTF_8
+            } else {
+                mime::TEXT_PLAIN_UTF_8
+            }
+        })
     }
 }
 
+/// The `<file>.gz` path of `path`
+fn gzip_path(path: &Path) -> PathBuf {
+    let mut gzip_path = path.as_os_str().to_os_string();
+    gzip_path.push(".gz");
+    PathBuf::from(gzip_path)
+}
+
+/// Whether an `Accept` header explicitly lists `mime` with a non-zero quality
+fn accepts(accept: &str, mime: &str) -> bool {
+    accept.split(',').any(|media_range| {
+        let mut params = media_range.split(';').map(|p| p.trim());
+        params.next().map(|range| range.eq_ignore_ascii_case(mime)).unwrap_or(false)
+            && !params.any(|p| p.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0))
+    })
+}
+
 impl Middleware for FileMiddleware {
     fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
         self.next_inner(ctx, chain).boxed()
@@ -188,8 +609,20 @@ impl Middleware for FileMiddleware {
 pub struct FileMiddlewareBuilder {
     base_path: PathBuf,
     www_path: PathBuf,
+    mounted: bool,
     max_file_size: Option<u64>,
     max_capacity: Option<u64>,
+    content_md5: bool,
+    accept_variants: Vec<(String, String)>,
+    buffer_pool: Option<BufferPool>,
+    mime_database: Option<MimeDatabase>,
+    blocking_executor: Option<Arc<dyn BlockingExecutor>>,
+    precompressed_gzip: bool,
+    ignore_range: bool,
+    ignore_range_extensions: Vec<String>,
+    range_unit_handlers: Vec<(String, Arc<dyn RangeUnitHandler>)>,
+    authorizer: Option<Arc<dyn FileAuthorizer>>,
+    image_transcoding: Option<ImageTranscoding>,
 }
 
 impl FileMiddlewareBuilder {
@@ -197,8 +630,30 @@ impl FileMiddlewareBuilder {
         FileMiddlewareBuilder {
             base_path: PathBuf::from(base_path),
             www_path: PathBuf::from(www_path),
+            mounted: false,
             max_file_size: None,
             max_capacity: None,
+            content_md5: false,
+            accept_variants: Vec::new(),
+            buffer_pool: None,
+            mime_database: None,
+            blocking_executor: None,
+            precompressed_gzip: false,
+            ignore_range: false,
+            ignore_range_extensions: Vec::new(),
+            range_unit_handlers: Vec::new(),
+            authorizer: None,
+            image_transcoding: None,
+        }
+    }
+
+    /// Serve the directory `root` at the URL `prefix`, see
+    /// `FileMiddleware::mount`
+    pub fn mount(prefix: &str, root: &str) -> Self {
+        FileMiddlewareBuilder {
+            base_path: PathBuf::from(prefix.trim_matches('/')),
+            mounted: true,
+            ..Self::new("", root)
         }
     }
 
@@ -212,18 +667,229 @@ impl FileMiddlewareBuilder {
         self
     }
 
+    /// Send a `Content-MD5` header with full, uncompressed responses. Digests
+    /// are cached until the file is modified. Partial and compressed responses
+    /// never carry it.
+    ///
+    /// MD5 only lets clients detect a corrupted transfer, it does not protect
+    /// against tampering.
+    pub fn content_md5(mut self, enabled: bool) -> Self {
+        self.content_md5 = enabled;
+        self
+    }
+
+    /// Serve a variant of an extension-less path according to the `Accept`
+    /// header: a request for `/img/photo` is answered with `photo.<extension>`
+    /// if the client accepts `mime`. Variants are tried in the order they are
+    /// added, and `*/*` matches any client, which makes it the fallback.
+    /// Responses served from a variant carry `Vary: Accept`.
+    ///
+    /// ```rust
+    /// # use saphir::file::middleware::FileMiddlewareBuilder;
+    /// let middleware = FileMiddlewareBuilder::new("static", "/var/www")
+    ///     .accept_variant("image/webp", "webp")
+    ///     .accept_variant("*/*", "jpg")
+    ///     .build();
+    /// ```
+    pub fn accept_variant(mut self, mime: &str, extension: &str) -> Self {
+        self.accept_variants.push((mime.to_string(), extension.to_string()));
+        self
+    }
+
+    /// Recycle the read buffers of the served files through `pool`
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Pick the `Content-Type` of the served files from `db` instead of the
+    /// built-in guesses
+    pub fn mime_database(mut self, db: MimeDatabase) -> Self {
+        self.mime_database = Some(db);
+        self
+    }
+
+    /// Run the blocking filesystem lookups made for each request on
+    /// `executor`. They go to the tokio blocking threads by default, a
+    /// `BlockingPool` bounds them to a fixed number of threads.
+    pub fn blocking_executor<E: 'static + BlockingExecutor>(mut self, executor: E) -> Self {
+        self.blocking_executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Serve the `<file>.gz` sibling of the requested files when there is
+    /// one, without compressing on the fly, see `FileMiddleware`. Disabled by
+    /// default.
+    pub fn precompressed_gzip(mut self, enabled: bool) -> Self {
+        self.precompressed_gzip = enabled;
+        self
+    }
+
+    /// Ignore the `Range` of the requests and always serve whole files with
+    /// a `200 OK`, advertising `Accept-Ranges: none`. To only do so for some
+    /// routes, apply a middleware ignoring ranges to them, and another one
+    /// to the rest.
+    pub fn ignore_range(mut self, ignore: bool) -> Self {
+        self.ignore_range = ignore;
+        self
+    }
+
+    /// Ignore the `Range` of the requests for the files with `extension`,
+    /// as with `ignore_range`
+    ///
+    /// ```rust
+    /// # use saphir::file::middleware::FileMiddlewareBuilder;
+    /// let middleware = FileMiddlewareBuilder::new("static", "/var/www")
+    ///     .ignore_range_for_extension("svg")
+    ///     .ignore_range_for_extension("json")
+    ///     .build();
+    /// ```
+    pub fn ignore_range_for_extension(mut self, extension: &str) -> Self {
+        self.ignore_range_extensions.push(extension.to_string());
+        self
+    }
+
+    /// Serve the ranges of `unit` with `handler`, making the middleware
+    /// advertise `unit` in `Accept-Ranges`. Ranges of units without a handler
+    /// get a `416 Range Not Satisfiable`.
+    ///
+    /// ```rust
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::middleware::FileMiddlewareBuilder;
+    /// # use std::path::Path;
+    /// // Serves `items=<n>`, the n-th line of the file
+    /// let middleware = FileMiddlewareBuilder::new("static", "/var/www")
+    ///     .range_unit_handler("items", |path: &Path, _size: u64, range_set: &str| {
+    ///         let index = range_set.trim().parse::<usize>().ok()?;
+    ///         let lines = std::fs::read_to_string(path).ok()?;
+    ///         let item = lines.lines().nth(index)?.to_string();
+    ///         Some(Builder::new().status(206).header("Content-Range", format!("items {}-{}/*", index, index)).body(item))
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn range_unit_handler<H: 'static + RangeUnitHandler>(mut self, unit: &str, handler: H) -> Self {
+        self.range_unit_handlers.push((unit.to_string(), Arc::new(handler)));
+        self
+    }
+
+    /// Only serve the files `authorizer` allows, answering the other
+    /// requests with a `403 Forbidden`. It runs before the file is looked up,
+    /// so denied requests can't learn whether it exists.
+    ///
+    /// ```rust
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::middleware::FileMiddlewareBuilder;
+    /// # use futures::FutureExt;
+    /// # use std::path::Path;
+    /// # async fn owns(_user: &str, _path: &Path) -> bool { true }
+    /// // Uploads are stored under /var/uploads/<user>/
+    /// let middleware = FileMiddlewareBuilder::new("uploads", "/var/uploads")
+    ///     .authorizer(|ctx: &HttpContext, path: &Path| {
+    ///         let user = ctx.state.request().and_then(|r| r.headers().get("X-User")).and_then(|u| u.to_str().ok()).map(str::to_string);
+    ///         let path = path.to_path_buf();
+    ///         async move {
+    ///             match user {
+    ///                 Some(user) => owns(&user, &path).await,
+    ///                 None => false,
+    ///             }
+    ///         }
+    ///         .boxed()
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn authorizer<A: 'static + FileAuthorizer>(mut self, authorizer: A) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Transcode the JPEG and PNG files to the formats of `transcoding` for
+    /// the clients accepting them, see `ImageTranscoding`. Responses for
+    /// these files carry `Vary: Accept`, and a transcoded file has an `ETag`
+    /// of its own.
+    pub fn image_transcoding(mut self, transcoding: ImageTranscoding) -> Self {
+        self.image_transcoding = Some(transcoding);
+        self
+    }
+
     pub fn build(self) -> Result<FileMiddleware, SaphirError> {
         Ok(FileMiddleware {
             base_path: self.base_path,
             www_path: self.www_path,
+            mounted: self.mounted,
             cache: FileCache::new(
                 self.max_file_size.unwrap_or(DEFAULT_CACHE_MAX_FILE_SIZE),
                 self.max_capacity.unwrap_or(DEFAULT_CACHE_MAX_CAPACITY),
             ),
+            content_md5: if self.content_md5 { Some(ContentMd5Cache::new()) } else { None },
+            accept_variants: self.accept_variants,
+            buffer_pool: self.buffer_pool,
+            mime_database: self.mime_database,
+            blocking_executor: self.blocking_executor.unwrap_or_else(|| Arc::new(TokioBlocking)),
+            precompressed_gzip: self.precompressed_gzip,
+            ignore_range: self.ignore_range,
+            ignore_range_extensions: self.ignore_range_extensions,
+            range_unit_handlers: self.range_unit_handlers,
+            authorizer: self.authorizer,
+            image_transcoding: self.image_transcoding,
         })
     }
 }
 
+/// Quality `Accept-Encoding` gives to `compression`, `identity` standing for
+/// `Compression::Raw`. Codings not listed take the quality of `*` if any,
+/// and are refused otherwise, except `identity` which is accepted unless
+/// refused explicitly (RFC 7231 section 5.3.4)
+fn encoding_quality(accept_encoding: Option<&str>, compression: Compression) -> f32 {
+    let accept_encoding = match accept_encoding {
+        Some(accept_encoding) => accept_encoding,
+        None => return if compression == Compression::Raw { 1.0 } else { 0.0 },
+    };
+
+    let name = match compression {
+        Compression::Raw => "identity".to_string(),
+        compression => compression.to_string(),
+    };
+    let mut wildcard = None;
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|p| {
+                let mut p = p.splitn(2, '=');
+                match (p.next(), p.next()) {
+                    (Some(q), Some(value)) if q.trim().eq_ignore_ascii_case("q") => value.trim().parse::<f32>().ok(),
+                    _ => None,
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+        if coding.eq_ignore_ascii_case(&name) {
+            return quality;
+        } else if coding == "*" {
+            wildcard = Some(quality);
+        }
+    }
+
+    match (wildcard, compression) {
+        (Some(quality), _) => quality,
+        (None, Compression::Raw) => 1.0,
+        (None, _) => 0.0,
+    }
+}
+
+/// The compression `Accept-Encoding` prefers among those a file can be sent
+/// with, `None` when it refuses them all. Equal qualities prefer the
+/// strongest compression
+fn negotiate_compression(accept_encoding: Option<&str>) -> Option<Compression> {
+    let candidates = [Compression::Raw, Compression::Deflate, Compression::Gzip, Compression::Brotli];
+    candidates
+        .iter()
+        .map(|c| (*c, encoding_quality(accept_encoding, *c)))
+        .filter(|(_, quality)| *quality > 0.0)
+        .max_by(|(a, qa), (b, qb)| qa.partial_cmp(qb).unwrap_or(std::cmp::Ordering::Equal).then(a.cmp(b)))
+        .map(|(c, _)| c)
+}
+
 pub trait PathExt {
     fn is_hidden(&self) -> bool;
     fn mtime(&self) -> SystemTime;
@@ -239,11 +905,13 @@ impl PathExt for Path {
 
     /// Get modified time from a path.
     fn mtime(&self) -> SystemTime {
+        filesystem_call();
         self.metadata().and_then(|meta| meta.modified()).unwrap()
     }
 
     /// Get file size from a path.
     fn size(&self) -> u64 {
+        filesystem_call();
         self.metadata().map(|meta| meta.len()).unwrap_or_default()
     }
 
@@ -252,3 +920,551 @@ impl PathExt for Path {
         from_path(&self).first()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::MiddleChainEnd, router::Router};
+    use std::io::Write;
+
+    async fn serve(www_path: &Path, headers: &[(header::HeaderName, &str)]) -> HttpContext {
+        serve_with(FileMiddleware::new("/", www_path.to_str().unwrap()), headers).await
+    }
+
+    async fn serve_with(middleware: FileMiddleware, headers: &[(header::HeaderName, &str)]) -> HttpContext {
+        serve_uri(middleware, "/data.txt", headers).await
+    }
+
+    async fn serve_uri(middleware: FileMiddleware, uri: &str, headers: &[(header::HeaderName, &str)]) -> HttpContext {
+        let mut req = http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        let req = Request::new(req.body(Body::empty()).unwrap(), None);
+
+        let middleware: &'static FileMiddleware = Box::leak(Box::new(middleware));
+        middleware
+            .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+            .await
+            .unwrap()
+    }
+
+    async fn body_bytes(res: Response) -> Bytes {
+        hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap()
+    }
+
+    fn www_path(name: &str) -> PathBuf {
+        let www_path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&www_path).unwrap();
+        std::fs::File::create(www_path.join("data.txt"))
+            .unwrap()
+            .write_all(b"abcdefghijklmnopqrstuvwxyz")
+            .unwrap();
+        www_path
+    }
+
+    #[tokio::test]
+    async fn refused_encodings_are_never_sent() {
+        let www_path = www_path("saphir_file_middleware_refused_encodings");
+
+        let mut ctx = serve(&www_path, &[(header::ACCEPT_ENCODING, "identity;q=0, *;q=0")]).await;
+        let res = ctx.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+
+        // Refusing the identity alone leaves the compressed representations, even with a range
+        let mut ctx = serve(
+            &www_path,
+            &[(header::ACCEPT_ENCODING, "identity;q=0, gzip;q=0.5, *;q=0"), (header::RANGE, "bytes=3-9")],
+        )
+        .await;
+        let res = ctx.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+
+        let mut ctx = serve(&www_path, &[(header::ACCEPT_ENCODING, "gzip;q=0, br;q=0")]).await;
+        let res = ctx.state.take_response_unchecked();
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"abcdefghijklmnopqrstuvwxyz"));
+    }
+
+    #[test]
+    fn encodings_are_negotiated_by_quality() {
+        assert_eq!(negotiate_compression(None), Some(Compression::Raw));
+        assert_eq!(negotiate_compression(Some("gzip, br")), Some(Compression::Brotli));
+        assert_eq!(negotiate_compression(Some("gzip, br;q=0.8")), Some(Compression::Gzip));
+        assert_eq!(negotiate_compression(Some("*")), Some(Compression::Brotli));
+        assert_eq!(negotiate_compression(Some("*;q=0")), None);
+        assert_eq!(negotiate_compression(Some("compress")), Some(Compression::Raw));
+    }
+
+    #[tokio::test]
+    async fn range_takes_precedence_over_compression() {
+        let www_path = www_path("saphir_file_middleware_range_gzip");
+
+        let mut ctx = serve(&www_path, &[(header::ACCEPT_ENCODING, "gzip"), (header::RANGE, "bytes=3-9")]).await;
+        assert_eq!(ctx.response_encoding(), None);
+        let res = ctx.state.take_response_unchecked();
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 3-9/26");
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "7");
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"defghij"));
+
+        let res = serve(&www_path, &[(header::ACCEPT_ENCODING, "gzip")]).await.state.take_response_unchecked();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[tokio::test]
+    async fn response_encoding_reflects_chosen_compression() {
+        let www_path = www_path("saphir_file_middleware_response_encoding");
+
+        let ctx = serve(&www_path, &[(header::ACCEPT_ENCODING, "gzip")]).await;
+        assert_eq!(ctx.response_encoding(), Some("gzip"));
+
+        let ctx = serve(&www_path, &[]).await;
+        assert_eq!(ctx.response_encoding(), None);
+    }
+
+    #[tokio::test]
+    async fn content_md5_is_sent_with_full_responses() {
+        let www_path = std::env::temp_dir().join("saphir_file_middleware_content_md5");
+        std::fs::create_dir_all(&www_path).unwrap();
+        std::fs::File::create(www_path.join("data.txt"))
+            .unwrap()
+            .write_all(b"The quick brown fox jumps over the lazy dog")
+            .unwrap();
+        let middleware = || FileMiddlewareBuilder::new("/", www_path.to_str().unwrap()).content_md5(true).build().unwrap();
+
+        let res = serve_with(middleware(), &[]).await.state.take_response_unchecked();
+        assert_eq!(res.headers().get("Content-MD5").unwrap(), "nhB9nTcrtoJr2B01QqQZ1g==");
+
+        let res = serve_with(middleware(), &[(header::RANGE, "bytes=0-3")]).await.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get("Content-MD5").is_none());
+    }
+
+    #[tokio::test]
+    async fn accept_variant_is_chosen_from_accept() {
+        let www_path = std::env::temp_dir().join("saphir_file_middleware_accept_variant");
+        std::fs::create_dir_all(www_path.join("img")).unwrap();
+        std::fs::File::create(www_path.join("img/photo.webp")).unwrap().write_all(b"webp data").unwrap();
+        std::fs::File::create(www_path.join("img/photo.jpg")).unwrap().write_all(b"jpg data").unwrap();
+        let middleware = || {
+            FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+                .accept_variant("image/webp", "webp")
+                .accept_variant("*/*", "jpg")
+                .build()
+                .unwrap()
+        };
+
+        let webp_accept = "image/avif,image/webp,image/apng,*/*;q=0.8";
+        let res = serve_uri(middleware(), "/img/photo", &[(header::ACCEPT, webp_accept)])
+            .await
+            .state
+            .take_response_unchecked();
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/webp");
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"webp data"));
+
+        for accept in &["image/png,*/*;q=0.8", "image/webp;q=0"] {
+            let res = serve_uri(middleware(), "/img/photo", &[(header::ACCEPT, accept)])
+                .await
+                .state
+                .take_response_unchecked();
+            assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/jpeg");
+            assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"jpg data"));
+        }
+
+        let res = serve_uri(middleware(), "/img/photo.jpg", &[(header::ACCEPT, webp_accept)])
+            .await
+            .state
+            .take_response_unchecked();
+        assert!(res.headers().get(header::VARY).is_none());
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"jpg data"));
+    }
+
+    #[tokio::test]
+    async fn mounted_prefix_is_stripped_and_traversal_blocked() {
+        let www_path = www_path("saphir_file_middleware_mount");
+        let root = www_path.join("assets");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::File::create(root.join("sub").join("x.css")).unwrap().write_all(b"p {}").unwrap();
+        let mounted = FileMiddleware::mount("/assets/", root.to_str().unwrap());
+
+        assert_eq!(mounted.file_path_from_path("/assets/sub/x.css"), Ok(root.join("sub").join("x.css")));
+        assert_eq!(mounted.file_path_from_path("/assets/./sub/x.css"), Ok(root.join("sub").join("x.css")));
+        assert_eq!(mounted.file_path_from_path("/assets/../data.txt"), Err(400));
+        assert_eq!(mounted.file_path_from_path("/assets/sub/%2e%2e/%2e%2e/data.txt"), Err(400));
+        assert_eq!(mounted.file_path_from_path("/assets/%2Fetc/passwd"), Ok(root.join("etc").join("passwd")));
+        assert_eq!(mounted.file_path_from_path("/assetsX/sub/x.css"), Err(404));
+        assert_eq!(mounted.file_path_from_path("/sub/x.css"), Err(404));
+
+        let mut ctx = serve_uri(mounted, "/assets/sub/x.css", &[]).await;
+        let res = ctx.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_bytes(res).await, "p {}");
+
+        let mut ctx = serve_uri(FileMiddleware::new("/", root.to_str().unwrap()), "/../data.txt", &[]).await;
+        assert_eq!(ctx.state.take_response_unchecked().status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn serve_precompressed(www_path: &Path, uri: &str, headers: &[(header::HeaderName, &str)]) -> Response {
+        let middleware = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+            .precompressed_gzip(true)
+            .build()
+            .unwrap();
+        serve_uri(middleware, uri, headers).await.state.take_response_unchecked()
+    }
+
+    #[tokio::test]
+    async fn range_applies_to_the_compressed_sibling() {
+        let www_path = www_path("saphir_file_middleware_range_compressed");
+        let compressed = gzip(b"abcdefghijklmnopqrstuvwxyz");
+        std::fs::File::create(www_path.join("data.txt"))
+            .unwrap()
+            .write_all(b"abcdefghijklmnopqrstuvwxyz")
+            .unwrap();
+        std::fs::File::create(www_path.join("data.txt.gz")).unwrap().write_all(&compressed).unwrap();
+
+        let res = serve_precompressed(&www_path, "/data.txt", &[(header::ACCEPT_ENCODING, "gzip"), (header::RANGE, "bytes=0-9")]).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/plain");
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept-Encoding");
+        assert_eq!(
+            res.headers().get(header::CONTENT_RANGE).unwrap().to_str().unwrap(),
+            format!("bytes 0-9/{}", compressed.len())
+        );
+        assert_eq!(body_bytes(res).await, compressed[..10]);
+
+        let res = serve_precompressed(&www_path, "/data.txt", &[(header::ACCEPT_ENCODING, "gzip")]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_bytes(res).await, compressed);
+
+        // Clients not accepting gzip still get the identity file
+        let res = serve_precompressed(&www_path, "/data.txt", &[(header::ACCEPT_ENCODING, "br"), (header::RANGE, "bytes=0-2")]).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 0-2/26");
+        assert_eq!(body_bytes(res).await, "abc");
+    }
+
+    #[tokio::test]
+    async fn range_applies_to_the_decompressed_sibling() {
+        let www_path = www_path("saphir_file_middleware_range_decompressed");
+        std::fs::File::create(www_path.join("only.txt.gz"))
+            .unwrap()
+            .write_all(&gzip(b"abcdefghijklmnopqrstuvwxyz"))
+            .unwrap();
+
+        let res = serve_precompressed(&www_path, "/only.txt", &[(header::ACCEPT_ENCODING, "identity"), (header::RANGE, "bytes=2-5")]).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 2-5/26");
+        assert_eq!(body_bytes(res).await, "cdef");
+
+        let res = serve_precompressed(&www_path, "/only.txt", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "26");
+        assert_eq!(body_bytes(res).await, "abcdefghijklmnopqrstuvwxyz");
+
+        let res = serve_precompressed(&www_path, "/only.txt", &[(header::ACCEPT_ENCODING, "identity;q=0")]).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn ignored_ranges_get_the_whole_file() {
+        let www_path = www_path("saphir_file_middleware_ignore_range");
+        std::fs::File::create(www_path.join("data.json")).unwrap().write_all(b"[1, 2, 3]").unwrap();
+        let middleware = || {
+            FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+                .ignore_range_for_extension("JSON")
+                .build()
+                .unwrap()
+        };
+
+        let res = serve_uri(middleware(), "/data.json", &[(header::RANGE, "bytes=0-1")])
+            .await
+            .state
+            .take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "none");
+        assert!(res.headers().get(header::CONTENT_RANGE).is_none());
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"[1, 2, 3]"));
+
+        let res = serve_with(middleware(), &[(header::RANGE, "bytes=0-1")]).await.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+
+        let ignoring_all = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap()).ignore_range(true).build().unwrap();
+        let res = serve_with(ignoring_all, &[(header::RANGE, "bytes=0-1")]).await.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "none");
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"abcdefghijklmnopqrstuvwxyz"));
+    }
+
+    #[tokio::test]
+    async fn denied_files_are_never_opened() {
+        /// Counts the filesystem lookups, which all go through the executor
+        #[derive(Clone, Default)]
+        struct Counted(Arc<std::sync::atomic::AtomicUsize>);
+
+        impl BlockingExecutor for Counted {
+            fn execute(&self, job: crate::file::blocking::BlockingJob) {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                TokioBlocking.execute(job);
+            }
+        }
+
+        let www_path = www_path("saphir_file_middleware_authorizer");
+        std::fs::create_dir_all(www_path.join("private")).unwrap();
+        std::fs::write(www_path.join("private").join("secret.txt"), b"secret").unwrap();
+        let lookups = Counted::default();
+        let private = www_path.join("private");
+        let middleware = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+            .blocking_executor(lookups.clone())
+            .authorizer(move |ctx: &HttpContext, path: &Path| {
+                let token = ctx.state.request().and_then(|r| r.headers().get(header::AUTHORIZATION)).is_some();
+                token || !path.starts_with(&private)
+            })
+            .build()
+            .unwrap();
+        let middleware: &'static FileMiddleware = Box::leak(Box::new(middleware));
+        let get = |uri: &'static str, token: bool| async move {
+            let mut req = http::Request::builder().uri(uri);
+            if token {
+                req = req.header(header::AUTHORIZATION, "Bearer token");
+            }
+            let req = Request::new(req.body(Body::empty()).unwrap(), None);
+            let mut ctx = middleware
+                .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+                .await
+                .unwrap();
+            ctx.state.take_response_unchecked()
+        };
+
+        for uri in &["/private/secret.txt", "/private/missing.txt", "/private"] {
+            assert_eq!(get(uri, false).await.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(lookups.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let res = get("/private/secret.txt", true).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"secret"));
+        assert_eq!(get("/data.txt", false).await.status(), StatusCode::OK);
+        assert!(lookups.0.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    }
+
+    #[tokio::test]
+    async fn images_are_transcoded_once_for_the_accepting_clients() {
+        let www_path = www_path("saphir_file_middleware_transcoding");
+        std::fs::write(www_path.join("photo.jpg"), b"jpeg").unwrap();
+        let cache_dir = std::env::temp_dir().join("saphir_file_middleware_transcoding_cache");
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let transcodings = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = transcodings.clone();
+        let transcoder = move |image: &[u8], from: &Mime, to: &Mime| {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            assert_eq!((image, from, to.essence_str()), (&b"jpeg"[..], &mime::IMAGE_JPEG, "image/webp"));
+            Ok(b"RIFF\x04\0\0\0WEBP".to_vec())
+        };
+        let middleware: &'static FileMiddleware = Box::leak(Box::new(
+            FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+                .image_transcoding(ImageTranscoding::new(transcoder, &cache_dir))
+                .build()
+                .unwrap(),
+        ));
+        let get = |accept: &'static str| async move {
+            let req = http::Request::builder().uri("/photo.jpg").header(header::ACCEPT, accept);
+            let req = Request::new(req.body(Body::empty()).unwrap(), None);
+            let mut ctx = middleware
+                .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+                .await
+                .unwrap();
+            ctx.state.take_response_unchecked()
+        };
+
+        let mut etags = Vec::new();
+        for _ in 0..2 {
+            let res = get("image/webp,*/*").await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/webp");
+            assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+            etags.push(res.headers().get(header::ETAG).unwrap().clone());
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"RIFF\x04\0\0\0WEBP"));
+        }
+        // The second request was served from the cache
+        assert_eq!(transcodings.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(etags[0], etags[1]);
+
+        let res = get("image/png,*/*").await;
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/jpeg");
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        assert_ne!(res.headers().get(header::ETAG).unwrap(), etags[0]);
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"jpeg"));
+        assert_eq!(transcodings.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn other_range_units_need_a_handler() {
+        let www_path = www_path("saphir_file_middleware_range_unit");
+        let res = serve(&www_path, &[(header::RANGE, "items=0-9")]).await.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+
+        let middleware = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+            .range_unit_handler("items", |path: &Path, size: u64, range_set: &str| {
+                let (from, to) = items_bounds(range_set)?;
+                let content = std::fs::read(path).ok()?;
+                assert_eq!(content.len() as u64, size);
+                let items = content.get(from..=to)?.to_vec();
+                Some(
+                    Builder::new()
+                        .status(206)
+                        .header(header::CONTENT_RANGE, format!("items {}-{}/{}", from, to, size))
+                        .body(items),
+                )
+            })
+            .build()
+            .unwrap();
+        let middleware: &'static FileMiddleware = Box::leak(Box::new(middleware));
+        let serve_items = |range: &'static str| async move {
+            let req = Request::new(
+                http::Request::builder()
+                    .uri("/data.txt")
+                    .header(header::RANGE, range)
+                    .body(Body::empty())
+                    .unwrap(),
+                None,
+            );
+            let mut ctx = middleware
+                .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+                .await
+                .unwrap();
+            ctx.state.take_response_unchecked()
+        };
+
+        let res = serve_items("Items=0-9").await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "items 0-9/26");
+        assert_eq!(res.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes, items");
+        assert_eq!(body_bytes(res).await, Bytes::from_static(b"abcdefghij"));
+
+        assert_eq!(serve_items("items=30-40").await.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(serve_items("pages=0-9").await.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(serve_items("bytes=0-1").await.status(), StatusCode::PARTIAL_CONTENT);
+    }
+
+    /// `<from>-<to>` of an `items` range
+    fn items_bounds(range_set: &str) -> Option<(usize, usize)> {
+        let mut bounds = range_set.splitn(2, '-').map(|b| b.trim().parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(from)), Some(Ok(to))) if from <= to => Some((from, to)),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn content_type_comes_from_the_mime_database() {
+        let www_path = www_path("saphir_file_middleware_mime_db");
+        std::fs::File::create(www_path.join("bracket.gcode")).unwrap().write_all(b"G0 X0 Y0").unwrap();
+        let db = MimeDatabase::from_mime_types("text/x-gcode gcode").unwrap();
+        let middleware = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap()).mime_database(db).build().unwrap();
+
+        let res = serve_uri(middleware, "/bracket.gcode", &[]).await.state.take_response_unchecked();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/x-gcode");
+
+        let res = serve_uri(FileMiddleware::new("/", www_path.to_str().unwrap()), "/bracket.gcode", &[])
+            .await
+            .state
+            .take_response_unchecked();
+        assert_ne!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/x-gcode");
+    }
+
+    /// Executor counting the blocking filesystem calls made by its jobs
+    #[derive(Clone, Default)]
+    struct RecordingExecutor(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl BlockingExecutor for RecordingExecutor {
+        fn execute(&self, job: crate::file::blocking::BlockingJob) {
+            let calls = self.0.clone();
+            TokioBlocking.execute(Box::new(move || {
+                let before = crate::file::blocking::FILESYSTEM_CALLS.with(|c| c.get());
+                job();
+                let made = crate::file::blocking::FILESYSTEM_CALLS.with(|c| c.get()) - before;
+                calls.fetch_add(made, std::sync::atomic::Ordering::SeqCst);
+            }));
+        }
+    }
+
+    #[tokio::test]
+    async fn filesystem_calls_run_off_the_worker_thread() {
+        let www_path = www_path("saphir_file_middleware_blocking");
+        std::fs::File::create(www_path.join("data.txt")).unwrap().write_all(b"blocking").unwrap();
+        std::fs::create_dir_all(www_path.join("docs")).unwrap();
+        std::fs::write(www_path.join("docs").join("index.html"), b"<p>docs</p>").unwrap();
+        std::fs::write(www_path.join("packed.txt.gz"), gzip(b"packed")).unwrap();
+        std::fs::write(www_path.join("LICENSE"), b"MIT").unwrap();
+        let executor = RecordingExecutor::default();
+        let middleware = FileMiddlewareBuilder::new("/", www_path.to_str().unwrap())
+            .blocking_executor(executor.clone())
+            .precompressed_gzip(true)
+            .build()
+            .unwrap();
+        let middleware: &'static FileMiddleware = Box::leak(Box::new(middleware));
+        let calls_on_this_thread = || crate::file::blocking::FILESYSTEM_CALLS.with(|c| c.get());
+        let before = calls_on_this_thread();
+
+        // The second request for a file is served from the file cache, which
+        // keeps the files once they were sent
+        for (uri, body) in &[
+            ("/data.txt", "blocking"),
+            ("/data.txt", "blocking"),
+            ("/docs", "<p>docs</p>"),
+            ("/packed.txt", "packed"),
+            ("/LICENSE", "MIT"),
+        ] {
+            let req = Request::new(http::Request::builder().uri(*uri).body(Body::empty()).unwrap(), None);
+            let mut ctx = middleware
+                .next_inner(HttpContext::new(req, Router::builder().build()), &MiddleChainEnd)
+                .await
+                .unwrap();
+            let res = ctx.state.take_response_unchecked();
+            assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), &body.len().to_string(), "{}", uri);
+            assert_eq!(body_bytes(res).await, body.as_bytes(), "{}", uri);
+        }
+
+        // Nor do the files answered by handlers
+        let path = www_path.join("data.txt");
+        let ctx = HttpContext::new(
+            Request::new(http::Request::builder().uri("/").body(Body::empty()).unwrap(), None),
+            Router::builder().build(),
+        );
+        let file = crate::file::File::open(path.to_str().unwrap()).await.unwrap();
+        let res = file.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "8");
+        let res = Builder::new()
+            .download_file(path.to_str().unwrap(), "data.txt", &ctx)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "8");
+
+        assert_eq!(calls_on_this_thread(), before);
+        assert!(executor.0.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::error::SaphirError;
+use mime::Mime;
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+/// Mapping of file extensions to MIME types used to pick the `Content-Type`
+/// of served files.
+///
+/// The table can be loaded from a `mime.types` file, the format shipped by
+/// most systems as `/etc/mime.types`, or filled in memory. Extensions missing
+/// from the table fall back to the built-in guesses, unless the fallback is
+/// disabled.
+///
+/// ```rust
+/// # use saphir::file::{middleware::FileMiddlewareBuilder, mime_db::MimeDatabase};
+/// let db = MimeDatabase::from_mime_types("text/x-gcode gcode nc\n# G-code dialects\napplication/x-cnc tap").unwrap();
+/// let middleware = FileMiddlewareBuilder::new("static", "/var/www").mime_database(db).build();
+/// ```
+pub struct MimeDatabase {
+    types: HashMap<String, Mime>,
+    fallback: bool,
+}
+
+impl Default for MimeDatabase {
+    fn default() -> Self {
+        MimeDatabase {
+            types: HashMap::new(),
+            fallback: true,
+        }
+    }
+}
+
+impl MimeDatabase {
+    /// An empty table, every type comes from the built-in guesses until
+    /// entries are added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the content of a `mime.types` file: each line holds a MIME type
+    /// followed by its extensions, and `#` starts a comment. A type listed
+    /// without extensions is ignored.
+    pub fn from_mime_types(content: &str) -> Result<Self, SaphirError> {
+        let mut db = Self::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let mime = match fields.next() {
+                Some(mime) => Mime::from_str(mime).map_err(|e| SaphirError::Other(format!("Invalid MIME type on line {}: {:?}", number + 1, e)))?,
+                None => continue,
+            };
+
+            for extension in fields {
+                db.types.insert(extension.to_ascii_lowercase(), mime.clone());
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Load a `mime.types` file, see `from_mime_types`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SaphirError> {
+        Self::from_mime_types(&std::fs::read_to_string(path)?)
+    }
+
+    /// Map `extension`, without its leading dot, to `mime`
+    pub fn insert(mut self, extension: &str, mime: Mime) -> Self {
+        self.types.insert(extension.trim_start_matches('.').to_ascii_lowercase(), mime);
+        self
+    }
+
+    /// Whether extensions missing from the table are guessed, enabled by
+    /// default
+    pub fn fallback(mut self, enabled: bool) -> Self {
+        self.fallback = enabled;
+        self
+    }
+
+    /// The MIME type of `path`, according to its extension
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<Mime> {
+        let path = path.as_ref();
+        let known = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| self.types.get(&e.to_ascii_lowercase()))
+            .cloned();
+
+        if known.is_none() && self.fallback {
+            mime_guess::from_path(path).first()
+        } else {
+            known
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_types_are_parsed() {
+        let db = MimeDatabase::from_mime_types("# comment\ntext/x-gcode  gcode NC\napplication/x-empty\n\nimage/png png # override").unwrap();
+        assert_eq!(db.get("part.gcode"), Some(Mime::from_str("text/x-gcode").unwrap()));
+        assert_eq!(db.get("part.nc"), Some(Mime::from_str("text/x-gcode").unwrap()));
+        assert_eq!(db.get("PART.GCODE"), Some(Mime::from_str("text/x-gcode").unwrap()));
+        assert_eq!(db.get("logo.png"), Some(mime::IMAGE_PNG));
+        assert_eq!(db.get("page.html"), Some(mime::TEXT_HTML));
+        assert_eq!(db.fallback(false).get("page.html"), None);
+
+        assert!(MimeDatabase::from_mime_types("not-a-type gcode").is_err());
+    }
+}
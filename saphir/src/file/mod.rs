@@ -13,22 +13,48 @@ use tokio::{
     prelude::*,
 };
 
-use crate::{error::SaphirError, file::middleware::PathExt, http_context::HttpContext, responder::Responder, response::Builder};
+use crate::{
+    error::SaphirError,
+    file::{
+        buffer_pool::BufferPool,
+        content_range::ContentRange,
+        middleware::PathExt,
+        range::Range,
+        range_requests::{extract_range, is_satisfiable_range},
+    },
+    http_context::HttpContext,
+    responder::Responder,
+    response::Builder,
+};
 use flate2::write::{DeflateEncoder, GzEncoder};
 use futures::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, Cursor};
 use mime::Mime;
 use nom::lib::std::str::FromStr;
-use std::io::Write;
+use std::{io::Write, time::Duration};
 
+pub mod blocking;
+pub mod buffer_pool;
 mod cache;
+pub mod concat;
 pub mod conditional_request;
+pub mod content_md5;
 pub mod content_range;
 pub mod etag;
+pub mod gzip;
+pub mod include;
 pub mod middleware;
+pub mod mime_db;
 pub mod range;
 pub mod range_requests;
+pub mod transcode;
 
 pub const MAX_BUFFER: usize = 65534;
+/// How often a tailed file is checked for appended data by default
+pub const DEFAULT_TAIL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Response extension marking a body produced by `Builder::file`
+#[derive(Clone, Copy)]
+pub(crate) struct StreamedFile;
 
 pub trait SaphirFile: AsyncRead + AsyncSeek + FileInfo + Sync + Send {}
 
@@ -38,6 +64,24 @@ pub trait FileInfo {
     fn get_path(&self) -> &PathBuf;
     fn get_mime(&self) -> Option<&mime::Mime>;
     fn get_size(&self) -> u64;
+
+    /// Identity of the file on its filesystem, telling it apart from another
+    /// file later put at the same path. `None` when it isn't known
+    fn get_file_id(&self) -> Option<(u64, u64)> {
+        None
+    }
+}
+
+/// Device and inode of the file of `metadata`, on the platforms having them
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
 }
 
 pub struct File {
@@ -45,6 +89,8 @@ pub struct File {
     path: PathBuf,
     mime: Option<mime::Mime>,
     seek_has_started: bool,
+    /// Metadata of the file when it was opened
+    metadata: std::fs::Metadata,
 }
 
 impl FileInfo for File {
@@ -57,7 +103,11 @@ impl FileInfo for File {
     }
 
     fn get_size(&self) -> u64 {
-        self.path.size()
+        self.metadata.len()
+    }
+
+    fn get_file_id(&self) -> Option<(u64, u64)> {
+        file_id(&self.metadata)
     }
 }
 
@@ -66,6 +116,7 @@ impl File {
         let path = path_str.to_string();
         match TokioFile::open(path_str).await {
             Ok(file) => Ok(File {
+                metadata: file.metadata().await?,
                 inner: Box::pin(file),
                 path: PathBuf::from(path),
                 mime: None,
@@ -75,6 +126,11 @@ impl File {
             Err(e) => Err(e),
         }
     }
+
+    /// Last modification time of the file when it was opened
+    pub(crate) fn modified(&self) -> io::Result<std::time::SystemTime> {
+        self.metadata.modified()
+    }
 }
 
 impl AsyncRead for File {
@@ -109,6 +165,69 @@ impl AsyncSeek for File {
     }
 }
 
+impl File {
+    /// Turn the file into a stream of the byte range requested by the
+    /// request of `ctx`, so a handler or a middleware that already opened
+    /// the file doesn't need to open it again to serve a range.
+    ///
+    /// The stream is answered with a `206 Partial Content` and its
+    /// `Content-Range`. Without a `Range` header, or when the range isn't
+    /// satisfiable or is conditional through `If-Range`, the whole file is
+    /// streamed.
+    pub async fn into_range_stream(self, ctx: &HttpContext) -> io::Result<FileStream> {
+        let mut stream = FileStream::new(self);
+        if let Some((content_range, range)) = requested_range(ctx, stream.get_size()) {
+            stream.set_range(range).await?;
+            stream.content_range = Some(content_range);
+        }
+
+        Ok(stream)
+    }
+
+    /// Turn the file into a stream encoded with `compression`, unless the
+    /// request of `ctx` asks for a byte range.
+    ///
+    /// Ranges and compression can't be combined: byte ranges refer to the
+    /// identity representation. When both apply, the identity range is
+    /// served as with `into_range_stream`, without compression, and the
+    /// conflict is logged.
+    ///
+    /// ```rust,no_run
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::{Compression, FileStream};
+    /// async fn dataset(ctx: &HttpContext) -> Result<FileStream, SaphirError> {
+    ///     Ok(File::open("/var/data/points.csv").await?.into_encoded_stream(ctx, Compression::Gzip).await?)
+    /// }
+    /// ```
+    pub async fn into_encoded_stream(self, ctx: &HttpContext, compression: Compression) -> io::Result<FileStream> {
+        if compression == Compression::Raw {
+            return self.into_range_stream(ctx).await;
+        }
+
+        if requested_range(ctx, self.get_size()).is_some() {
+            error!(
+                "Range requested along with {} compression for {}: serving the identity range without compression",
+                compression.to_string(),
+                self.path.display()
+            );
+            return self.into_range_stream(ctx).await;
+        }
+
+        let mime = self.mime.clone();
+        let path = self.path.clone();
+        let encoded = compress_file(Box::pin(self), Encoder::None, compression).await?;
+        Ok(FileStream::new(FileCursor::new(encoded, mime, path)).content_encoding(compression))
+    }
+}
+
+/// The satisfiable byte range requested in `ctx`, for a file of `size` bytes
+pub(crate) fn requested_range(ctx: &HttpContext, size: u64) -> Option<(ContentRange, (u64, u64))> {
+    let range = Range::from_str(ctx.range()?).ok()?;
+    let content_range = is_satisfiable_range(&range, size)?;
+    let range = extract_range(&content_range)?;
+    Some((content_range, range))
+}
+
 impl Responder for File {
     fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
         let mime = if let Some(mime) = &self.get_mime() {
@@ -117,7 +236,7 @@ impl Responder for File {
             self.get_path()
                 .mime()
                 .unwrap_or_else(|| {
-                    if self.get_path().is_dir() {
+                    if self.metadata.is_dir() {
                         mime::TEXT_HTML_UTF_8
                     } else {
                         mime::TEXT_PLAIN_UTF_8
@@ -270,7 +389,14 @@ pub async fn compress_file(mut file: Pin<Box<dyn SaphirFile>>, mut encoder: Enco
             Compression::Raw => Encoder::None,
         }
     } else if compression == Compression::Raw {
-        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        if !encoder.is_none() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+
+        // Nothing to encode, the file is only loaded in memory
+        let mut raw = Vec::with_capacity(file.get_size() as usize);
+        file.read_to_end(&mut raw).await?;
+        return Ok(raw);
     }
 
     loop {
@@ -301,31 +427,254 @@ pub async fn compress_file(mut file: Pin<Box<dyn SaphirFile>>, mut encoder: Enco
 pub struct FileStream {
     inner: Pin<Box<dyn SaphirFile>>,
     buffer: Vec<u8>,
+    /// Buffer the source is read into, allocated on the first read
+    read_buffer: Vec<u8>,
+    buffer_pool: Option<BufferPool>,
     end_of_file: bool,
     range_len: Option<u64>,
     amount_read: usize,
+    flush_each_chunk: bool,
+    flush_pending: bool,
+    prefix: Option<Bytes>,
+    suffix: Option<Bytes>,
+    content_range: Option<ContentRange>,
+    auto_range: bool,
+    ignore_range: bool,
+    /// Offset to seek to before the first read
+    pending_seek: Option<u64>,
+    content_encoding: Compression,
+    tail: Option<Tail>,
+    /// Size of the source when the stream was created, the length promised
+    /// to the client. Unknown for the sources reporting no size, like the
+    /// files of `/proc`, which are then read to their end
+    declared_size: Option<u64>,
+}
+
+type MetadataFuture = Pin<Box<dyn std::future::Future<Output = io::Result<std::fs::Metadata>> + Send + Sync>>;
+
+/// Waiting state of a stream following the appends to its file
+struct Tail {
+    interval: Duration,
+    delay: Option<tokio::time::Delay>,
+    /// Lookup of the file at the path of the source, once the delay elapsed
+    check: Option<MetadataFuture>,
 }
 
 impl FileStream {
     pub fn new<T: SaphirFile + 'static>(inner: T) -> Self {
+        let declared_size = Some(inner.get_size()).filter(|size| *size > 0);
         FileStream {
             inner: Box::pin(inner),
             buffer: Vec::with_capacity(MAX_BUFFER),
+            read_buffer: Vec::new(),
+            buffer_pool: None,
             end_of_file: false,
             range_len: None,
             amount_read: 0,
+            flush_each_chunk: false,
+            flush_pending: false,
+            prefix: None,
+            suffix: None,
+            content_range: None,
+            auto_range: false,
+            ignore_range: false,
+            pending_seek: None,
+            content_encoding: Compression::Raw,
+            tail: None,
+            declared_size,
         }
     }
 
+    /// Emit `prefix` before the file content
+    pub fn with_prefix(mut self, prefix: Bytes) -> Self {
+        self.prefix = Some(prefix).filter(|p| !p.is_empty());
+        self
+    }
+
+    /// Emit `suffix` after the file content
+    pub fn with_suffix(mut self, suffix: Bytes) -> Self {
+        self.suffix = Some(suffix).filter(|s| !s.is_empty());
+        self
+    }
+
+    /// Take the read buffer from `pool` instead of allocating it, and give it
+    /// back once the stream is dropped
+    pub fn with_buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Force every chunk to be flushed to the socket as soon as it is produced.
+    ///
+    /// By default, data is accumulated up to `MAX_BUFFER` bytes before being
+    /// yielded, and the server is free to coalesce chunks before writing them.
+    /// With this option enabled, whatever data is available is yielded as soon
+    /// as the underlying source stops being ready, and the stream hands control
+    /// back to the server after each chunk so it gets written out immediately.
+    ///
+    /// This is meant for latency sensitive streams (SSE, progress reports), it
+    /// trades throughput for latency: expect more, smaller writes and more
+    /// syscalls when used on large files.
+    pub fn flush_each_chunk(mut self, flush: bool) -> Self {
+        self.flush_each_chunk = flush;
+        self
+    }
+
+    /// Let the responder serve the `Range` requested in the context, with a
+    /// `206 Partial Content` and its `Content-Range`, so no middleware is
+    /// needed to serve ranges of the stream.
+    ///
+    /// The range is ignored, and the whole stream sent, when the stream can't
+    /// be positioned anymore: a range was already set, data was already read
+    /// from it, or it has a prefix or a suffix.
+    ///
+    /// ```rust,no_run
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::FileStream;
+    /// async fn video(_req: Request<Body>) -> Result<FileStream, SaphirError> {
+    ///     Ok(FileStream::new(File::open("/var/media/intro.mp4").await?).auto_range(true))
+    /// }
+    /// ```
+    pub fn auto_range(mut self, enabled: bool) -> Self {
+        self.auto_range = enabled;
+        self
+    }
+
+    /// Always send the whole stream with a `200 OK`, whatever `Range` was
+    /// requested, and advertise it with `Accept-Ranges: none` so clients
+    /// stop asking. Meant for small files, or for clients known to misuse
+    /// ranges. `auto_range` has no effect on such a stream.
+    pub fn ignore_range(mut self, ignore: bool) -> Self {
+        self.ignore_range = ignore;
+        self
+    }
+
+    /// Declare the source as holding the file encoded with `compression`, so
+    /// the responder sends the matching `Content-Encoding`.
+    ///
+    /// Byte ranges always refer to the identity representation, so an
+    /// encoded stream is never ranged: `auto_range` is ignored, and logged as
+    /// an error, when a range is requested for it. Use
+    /// `File::into_encoded_stream` to serve the identity range instead.
+    pub fn content_encoding(mut self, compression: Compression) -> Self {
+        self.content_encoding = compression;
+        self
+    }
+
+    /// Follow the appends to the file, like `tail -f`: once the end of the
+    /// file is reached, the stream waits for more data instead of ending,
+    /// checking every 500ms by default. Meant for live log viewers over SSE
+    /// or long polling, combined with `flush_each_chunk`.
+    ///
+    /// The stream ends when the file at the path of the source is truncated
+    /// below what was already sent, deleted, or replaced by another file, as
+    /// by a rotation: the client can ask again to follow the new file. The
+    /// path is looked up on the tokio blocking threads. A followed stream has no known length, so it is sent without
+    /// `Content-Length` and never ranged. Tailing has no effect on a stream
+    /// with a range set.
+    ///
+    /// ```rust,no_run
+    /// # use saphir::prelude::*;
+    /// # use saphir::file::FileStream;
+    /// async fn live_log(_req: Request<Body>) -> Result<FileStream, SaphirError> {
+    ///     Ok(FileStream::new(File::open("/var/log/app.log").await?).tail(true).flush_each_chunk(true))
+    /// }
+    /// ```
+    pub fn tail(mut self, tail: bool) -> Self {
+        self.tail = if tail {
+            Some(Tail {
+                interval: self.tail.take().map(|t| t.interval).unwrap_or(DEFAULT_TAIL_INTERVAL),
+                delay: None,
+                check: None,
+            })
+        } else {
+            None
+        };
+        self
+    }
+
+    /// How often a tailed stream checks its file for appended data
+    pub fn tail_interval(mut self, interval: Duration) -> Self {
+        if let Some(tail) = self.tail.as_mut() {
+            tail.interval = interval;
+        }
+        self
+    }
+
+    /// Whether the stream follows the appends to its file
+    fn is_tailing(&self) -> bool {
+        self.tail.is_some() && self.range_len.is_none()
+    }
+
+    /// Wait for the next check of a tailed file, resolving to whether the
+    /// file can still be followed
+    fn poll_tail(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        let tail = match self.tail.as_mut() {
+            Some(tail) => tail,
+            None => return Poll::Ready(false),
+        };
+        if tail.check.is_none() {
+            let interval = tail.interval;
+            let delay = tail.delay.get_or_insert_with(|| tokio::time::delay_for(interval));
+            if std::future::Future::poll(Pin::new(delay), cx).is_pending() {
+                return Poll::Pending;
+            }
+            tail.delay = None;
+            tail.check = Some(Box::pin(tokio::fs::metadata(self.inner.get_path().clone())));
+        }
+
+        let metadata = match tail.check.as_mut().map(|check| std::future::Future::poll(check.as_mut(), cx)) {
+            Some(Poll::Ready(metadata)) => metadata,
+            _ => return Poll::Pending,
+        };
+        tail.check = None;
+        Poll::Ready(match metadata {
+            Ok(metadata) => {
+                let replaced = self.inner.get_file_id().map(|id| file_id(&metadata) != Some(id)).unwrap_or(false);
+                !replaced && metadata.len() >= self.amount_read as u64
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Whether a range can still be applied to the stream
+    fn is_rangeable(&self) -> bool {
+        self.range_len.is_none() && self.amount_read == 0 && self.prefix.is_none() && self.suffix.is_none() && self.tail.is_none()
+    }
+
+    /// Send only the bytes from `start` to `end` of the stream, both
+    /// included, like the ranges of a `Range` header. `end` used to be
+    /// excluded, which sent one byte short of the ranges
     pub async fn set_range(&mut self, range: (u64, u64)) -> io::Result<()> {
         let (start, end) = range;
         self.inner.seek(SeekFrom::Start(start)).await?;
-        self.range_len = Some(end - start);
+        self.range_len = Some(end - start + 1);
         Ok(())
     }
 
+    /// Size of the source when the stream was created. The stream never
+    /// sends more, and fails when the source ends before it was all sent, as
+    /// when the file is truncated while being streamed. A source reporting a
+    /// size of 0 is read to its end, its size isn't known
     pub fn get_size(&self) -> u64 {
-        self.inner.get_size()
+        self.declared_size.unwrap_or(0)
+    }
+
+    /// Bytes of the source the stream has to send, unknown when tailing or
+    /// when the source reports no size
+    fn expected_len(&self) -> Option<u64> {
+        if self.is_tailing() {
+            None
+        } else {
+            self.range_len.or(self.declared_size)
+        }
+    }
+
+    /// Length of the whole body: the file content, or the selected range of
+    /// it, along with the prefix and suffix
+    fn body_len(&self) -> Option<u64> {
+        let affix_len = self.prefix.as_ref().map(|p| p.len()).unwrap_or(0) + self.suffix.as_ref().map(|s| s.len()).unwrap_or(0);
+        self.range_len.or(self.declared_size).map(|len| len + affix_len as u64)
     }
 }
 
@@ -333,54 +682,114 @@ impl Stream for FileStream {
     type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(prefix) = self.prefix.take() {
+            return Poll::Ready(Some(Ok(prefix)));
+        }
+
         if self.end_of_file {
-            return Poll::Ready(None);
-        }
-
-        if let Some(range_len) = self.range_len {
-            let usize_range = range_len as usize;
-            let mut buffer = vec![0; usize_range];
-            while self.amount_read < usize_range && !self.end_of_file {
-                match self.inner.as_mut().poll_read(cx, &mut buffer) {
-                    Poll::Ready(Ok(s)) => {
-                        if s + self.amount_read <= usize_range {
-                            self.buffer.extend_from_slice(&buffer[0..s]);
-                            self.amount_read += s;
-                            self.end_of_file = s == 0 || self.amount_read == usize_range;
-                        } else {
-                            let amount_to_read = usize_range - self.amount_read;
-                            self.buffer.extend_from_slice(&buffer[0..amount_to_read]);
-                            self.end_of_file = true;
-                        }
-                    }
+            return Poll::Ready(self.suffix.take().map(Ok));
+        }
+
+        if let Some(offset) = self.pending_seek {
+            match self.inner.as_mut().poll_seek(cx, SeekFrom::Start(offset)) {
+                Poll::Ready(Ok(_)) => self.pending_seek = None,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // Returning pending once after a chunk makes the server flush what it has
+        // buffered before polling us again
+        if self.flush_pending {
+            self.flush_pending = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
 
-                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+        let this = &mut *self;
+        if this.read_buffer.is_empty() {
+            this.read_buffer = match this.buffer_pool.as_ref() {
+                Some(pool) => pool.take(),
+                None => vec![0; MAX_BUFFER],
+            };
+        }
 
-                    Poll::Pending => return Poll::Pending,
+        while this.buffer.len() < MAX_BUFFER && !this.end_of_file {
+            // Chunks are capped to MAX_BUFFER, and reads never go past the declared length,
+            // which is the range end for ranged reads
+            let expected_len = this.expected_len();
+            let mut to_read = MAX_BUFFER - this.buffer.len();
+            if let Some(expected_len) = expected_len {
+                to_read = to_read.min((expected_len as usize).saturating_sub(this.amount_read));
+                if to_read == 0 {
+                    this.end_of_file = true;
+                    break;
                 }
             }
-        } else {
-            let mut buffer = vec![0; MAX_BUFFER];
-            while self.buffer.len() < MAX_BUFFER && !self.end_of_file {
-                match self.inner.as_mut().poll_read(cx, &mut buffer) {
-                    Poll::Ready(Ok(s)) => {
-                        if s > 0 {
-                            self.buffer.extend_from_slice(&buffer[0..s]);
-                        }
-                        self.end_of_file = s == 0;
+
+            match this.inner.as_mut().poll_read(cx, &mut this.read_buffer[..to_read]) {
+                Poll::Ready(Ok(0)) if this.is_tailing() => {
+                    if !this.buffer.is_empty() {
+                        break;
                     }
+                    match this.poll_tail(cx) {
+                        Poll::Ready(true) => continue,
+                        Poll::Ready(false) => this.end_of_file = true,
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
 
-                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+                // Ending the body now would send less than the Content-Length, failing the
+                // stream aborts the response instead
+                Poll::Ready(Ok(0)) if expected_len.is_some() => {
+                    // What was read is sent first, the next poll reads nothing again
+                    if !this.buffer.is_empty() {
+                        break;
+                    }
+                    let expected_len = expected_len.unwrap_or_default();
+                    warn!(
+                        "{} shrank while being streamed: it ended after {} of the {} bytes to send, aborting the response",
+                        this.inner.get_path().display(),
+                        this.amount_read,
+                        expected_len
+                    );
+                    return Poll::Ready(Some(Err(Box::new(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("file ended after {} of {} bytes", this.amount_read, expected_len),
+                    )))));
+                }
 
-                    Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(s)) => {
+                    this.buffer.extend_from_slice(&this.read_buffer[0..s]);
+                    this.amount_read += s;
+                    this.end_of_file = s == 0 || expected_len.map(|len| this.amount_read as u64 >= len).unwrap_or(false);
                 }
+
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+
+                Poll::Pending if this.flush_each_chunk && !this.buffer.is_empty() => break,
+
+                Poll::Pending => return Poll::Pending,
             }
         }
 
+        if self.buffer.is_empty() && self.end_of_file {
+            return Poll::Ready(self.suffix.take().map(Ok));
+        }
+
+        self.flush_pending = self.flush_each_chunk;
         Poll::Ready(Some(Ok(Bytes::from(std::mem::take(&mut self.buffer)))))
     }
 }
 
+impl Drop for FileStream {
+    fn drop(&mut self) {
+        if let Some(pool) = self.buffer_pool.as_ref() {
+            pool.give_back(std::mem::take(&mut self.read_buffer));
+        }
+    }
+}
+
 impl From<File> for FileStream {
     fn from(other: File) -> Self {
         FileStream::new(other)
@@ -388,30 +797,498 @@ impl From<File> for FileStream {
 }
 
 impl Responder for FileStream {
-    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+    fn respond_with_builder(mut self, builder: Builder, ctx: &HttpContext) -> Builder {
+        if self.auto_range && !self.ignore_range && self.content_range.is_none() && self.is_rangeable() {
+            if let Some((content_range, (start, end))) = requested_range(ctx, self.get_size()) {
+                if self.content_encoding != Compression::Raw {
+                    error!(
+                        "Range requested on a {} encoded stream of {}: ranges only apply to the identity representation, sending the whole encoded body",
+                        self.content_encoding.to_string(),
+                        self.inner.get_path().display()
+                    );
+                } else {
+                    self.pending_seek = Some(start);
+                    self.range_len = Some(end - start + 1);
+                    self.content_range = Some(content_range);
+                }
+            }
+        }
+
+        // A directory can't be streamed, the source is taken as a plain file
         let mime = if let Some(mime) = &self.inner.get_mime() {
             mime.as_ref().to_string()
         } else {
-            self.inner
-                .get_path()
-                .mime()
-                .unwrap_or_else(|| {
-                    if self.inner.get_path().is_dir() {
-                        mime::TEXT_HTML_UTF_8
-                    } else {
-                        mime::TEXT_PLAIN_UTF_8
-                    }
-                })
-                .as_ref()
-                .to_string()
+            self.inner.get_path().mime().unwrap_or(mime::TEXT_PLAIN_UTF_8).as_ref().to_string()
         };
 
-        let len = self.inner.get_size();
+        let len = self.body_len().filter(|_| !self.is_tailing());
+        let builder = match self.content_range.as_ref() {
+            Some(content_range) => builder
+                .status(http::StatusCode::PARTIAL_CONTENT)
+                .header(http::header::CONTENT_RANGE, content_range.to_string()),
+            None => builder,
+        };
+        let builder = match self.content_encoding {
+            Compression::Raw => builder,
+            encoding => builder
+                .header(http::header::CONTENT_ENCODING, encoding.to_string())
+                .header(http::header::VARY, "Accept-Encoding"),
+        };
+        let accept_ranges = if self.ignore_range || len.is_none() { "none" } else { "bytes" };
+        let builder = builder.header(http::header::ACCEPT_RANGES, accept_ranges);
+        let builder = match len {
+            Some(len) => builder.header(http::header::CONTENT_LENGTH, len),
+            None => builder,
+        };
 
-        builder
-            .file(self)
-            .header(http::header::ACCEPT_RANGES, "bytes")
-            .header(http::header::CONTENT_TYPE, mime)
-            .header(http::header::CONTENT_LENGTH, len)
+        builder.file(self).header(http::header::CONTENT_TYPE, mime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::{waker, ArcWake};
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    /// Source yielding one small chunk at a time, not being ready in between
+    struct SlowFile {
+        chunks: VecDeque<Vec<u8>>,
+        ready: bool,
+        path: PathBuf,
+    }
+
+    impl SlowFile {
+        fn new(chunks: Vec<&[u8]>) -> Self {
+            SlowFile {
+                chunks: chunks.into_iter().map(|c| c.to_vec()).collect(),
+                ready: true,
+                path: PathBuf::from("events"),
+            }
+        }
+    }
+
+    impl FileInfo for SlowFile {
+        fn get_path(&self) -> &PathBuf {
+            &self.path
+        }
+
+        fn get_mime(&self) -> Option<&Mime> {
+            None
+        }
+
+        fn get_size(&self) -> u64 {
+            self.chunks.iter().map(|c| c.len() as u64).sum()
+        }
+    }
+
+    impl AsyncRead for SlowFile {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if !self.ready {
+                self.ready = true;
+                return Poll::Pending;
+            }
+
+            self.ready = false;
+            match self.chunks.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Poll::Ready(Ok(chunk.len()))
+                }
+                None => Poll::Ready(Ok(0)),
+            }
+        }
+    }
+
+    impl AsyncSeek for SlowFile {
+        fn poll_seek(self: Pin<&mut Self>, _cx: &mut Context<'_>, _pos: SeekFrom) -> Poll<io::Result<u64>> {
+            Poll::Ready(Ok(0))
+        }
+    }
+
+    struct WakeCounter(AtomicUsize);
+
+    impl ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn flush_each_chunk_yields_events_promptly() {
+        let counter = Arc::new(WakeCounter(AtomicUsize::new(0)));
+        let waker = waker(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut stream = FileStream::new(SlowFile::new(vec![b"data: 1\n\n", b"data: 2\n\n"])).flush_each_chunk(true);
+
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok(b))) => assert_eq!(b.as_ref(), b"data: 1\n\n"),
+            _ => panic!("first event should be yielded as soon as it is read"),
+        }
+
+        // The stream gives control back to the server so it can flush, and asks to be polled again
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok(b))) => assert_eq!(b.as_ref(), b"data: 2\n\n"),
+            _ => panic!("second event should be yielded as soon as it is read"),
+        }
+    }
+
+    fn collect_chunks(mut stream: FileStream) -> Vec<Bytes> {
+        let waker = waker(Arc::new(WakeCounter(AtomicUsize::new(0))));
+        let mut cx = Context::from_waker(&waker);
+        let mut chunks = Vec::new();
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(chunk)) => chunks.push(chunk.unwrap()),
+                Poll::Ready(None) => return chunks,
+                Poll::Pending => continue,
+            }
+        }
+    }
+
+    fn assert_range_is_exact(range_len: usize) {
+        let content: Vec<u8> = (0..3 * MAX_BUFFER).map(|i| (i % 251) as u8).collect();
+        // Start right before the first buffer boundary so the range spans it
+        let start = MAX_BUFFER - 10;
+        let end = start + range_len - 1;
+
+        let mut stream = FileStream::new(FileCursor::new(content.clone(), None, PathBuf::from("range.bin")));
+        futures::executor::block_on(stream.set_range((start as u64, end as u64))).unwrap();
+        let chunks = collect_chunks(stream);
+
+        assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= MAX_BUFFER));
+        let body: Vec<u8> = chunks.iter().flat_map(|c| c.iter().cloned()).collect();
+        assert_eq!(body.len(), range_len);
+        assert_eq!(&body[..], &content[start..=end]);
+    }
+
+    #[test]
+    fn range_includes_its_end() {
+        let mut stream = FileStream::new(FileCursor::new(b"0123456789".to_vec(), None, PathBuf::from("digits.txt")));
+        futures::executor::block_on(stream.set_range((2, 5))).unwrap();
+        let body: Vec<u8> = collect_chunks(stream).iter().flat_map(|c| c.iter().cloned()).collect();
+        assert_eq!(body, b"2345");
+    }
+
+    #[test]
+    fn range_smaller_than_max_buffer() {
+        assert_range_is_exact(MAX_BUFFER - 1);
+    }
+
+    #[test]
+    fn range_of_max_buffer() {
+        assert_range_is_exact(MAX_BUFFER);
+    }
+
+    #[test]
+    fn range_larger_than_max_buffer() {
+        assert_range_is_exact(MAX_BUFFER + 1);
+    }
+
+    #[test]
+    fn full_file_of_max_buffer_multiple_has_no_empty_chunk() {
+        let content = vec![7u8; 2 * MAX_BUFFER];
+        let chunks = collect_chunks(FileStream::new(FileCursor::new(content, None, PathBuf::from("full.bin"))));
+
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![MAX_BUFFER, MAX_BUFFER]);
+    }
+
+    #[test]
+    fn chunks_are_coalesced_by_default() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut stream = FileStream::new(SlowFile::new(vec![b"data: 1\n\n", b"data: 2\n\n"]));
+
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            if let Poll::Ready(item) = Pin::new(&mut stream).poll_next(&mut cx) {
+                assert_eq!(item.unwrap().unwrap().as_ref(), b"data: 1\n\ndata: 2\n\n");
+                break;
+            }
+        }
+        assert!(polls > 1);
+    }
+
+    #[tokio::test]
+    async fn prefix_and_suffix_surround_the_file() {
+        let stream = || {
+            FileStream::new(FileCursor::new(b"<p>body</p>".to_vec(), None, PathBuf::from("page.html")))
+                .with_prefix(Bytes::from_static(b"<html>"))
+                .with_suffix(Bytes::from_static(b"</html>"))
+        };
+
+        let chunks = collect_chunks(stream());
+        assert_eq!(chunks, vec![&b"<html>"[..], &b"<p>body</p>"[..], &b"</html>"[..]]);
+
+        let req = crate::request::Request::new(http::Request::builder().body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+        let res = stream().respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        assert_eq!(res.headers().get(http::header::CONTENT_LENGTH).unwrap(), "24");
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"<html><p>body</p></html>"));
+    }
+
+    #[tokio::test]
+    async fn open_file_is_streamed_with_the_requested_range() {
+        let path = std::env::temp_dir().join("saphir_into_range_stream.txt");
+        std::fs::File::create(&path).unwrap().write_all(b"0123456789").unwrap();
+        let path = path.to_str().unwrap();
+
+        let ctx = |range: Option<&str>| {
+            let mut req = http::Request::builder();
+            if let Some(range) = range {
+                req = req.header(http::header::RANGE, range);
+            }
+            let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+            HttpContext::new(req, crate::router::Router::builder().build())
+        };
+
+        let respond = |ctx: HttpContext| async move {
+            let stream = File::open(path).await.unwrap().into_range_stream(&ctx).await.unwrap();
+            let res = stream.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+            let status = res.status().as_u16();
+            let content_range = res.headers().get(http::header::CONTENT_RANGE).map(|h| h.to_str().unwrap().to_string());
+            let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+            (status, content_range, body)
+        };
+
+        let (status, content_range, body) = respond(ctx(Some("bytes=2-5"))).await;
+        assert_eq!(status, 206);
+        assert_eq!(content_range.as_deref(), Some("bytes 2-5/10"));
+        assert_eq!(body, Bytes::from_static(b"2345"));
+
+        for range in &[None, Some("bytes=20-30")] {
+            let (status, content_range, body) = respond(ctx(*range)).await;
+            assert_eq!(status, 200);
+            assert_eq!(content_range, None);
+            assert_eq!(body, Bytes::from_static(b"0123456789"));
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_stream_serves_the_requested_range() {
+        async fn page(_req: crate::request::Request<crate::body::Body>) -> FileStream {
+            FileStream::new(FileCursor::new(b"0123456789".to_vec(), None, PathBuf::from("digits.txt"))).auto_range(true)
+        }
+
+        let router = crate::router::Router::builder().route("/digits", http::Method::GET, page).build();
+        let get = |range: &str| {
+            let req = http::Request::builder().uri("/digits").header(http::header::RANGE, range);
+            let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+            let router = router.clone();
+            async move {
+                let mut ctx = router.clone().handle(HttpContext::new(req, router)).await.unwrap();
+                let res = ctx.state.take_response().unwrap();
+                let status = res.status().as_u16();
+                let headers = res.headers().clone();
+                let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+                (status, headers, body)
+            }
+        };
+
+        let (status, headers, body) = get("bytes=-3").await;
+        assert_eq!(status, 206);
+        assert_eq!(headers.get(http::header::CONTENT_RANGE).unwrap(), "bytes 7-9/10");
+        assert_eq!(headers.get(http::header::CONTENT_LENGTH).unwrap(), "3");
+        assert_eq!(body, Bytes::from_static(b"789"));
+
+        let (status, headers, body) = get("bytes=50-").await;
+        assert_eq!(status, 200);
+        assert!(headers.get(http::header::CONTENT_RANGE).is_none());
+        assert_eq!(body, Bytes::from_static(b"0123456789"));
+    }
+
+    #[tokio::test]
+    async fn ignored_range_sends_the_whole_stream() {
+        let req = http::Request::builder().header(http::header::RANGE, "bytes=0-1");
+        let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+        let stream = FileStream::new(FileCursor::new(b"0123456789".to_vec(), None, PathBuf::from("digits.txt")))
+            .auto_range(true)
+            .ignore_range(true);
+
+        let res = stream.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(http::header::ACCEPT_RANGES).unwrap(), "none");
+        assert!(res.headers().get(http::header::CONTENT_RANGE).is_none());
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"0123456789"));
+    }
+
+    #[tokio::test]
+    async fn auto_range_is_ignored_once_the_stream_is_positioned() {
+        let req = http::Request::builder().header(http::header::RANGE, "bytes=0-1");
+        let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+
+        let stream = FileStream::new(FileCursor::new(b"0123456789".to_vec(), None, PathBuf::from("digits.txt")))
+            .with_prefix(Bytes::from_static(b">"))
+            .auto_range(true);
+        let res = stream.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        assert_eq!(res.status(), 200);
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b">0123456789"));
+    }
+
+    #[tokio::test]
+    async fn range_takes_precedence_over_requested_compression() {
+        let path = std::env::temp_dir().join("saphir_into_encoded_stream.txt");
+        std::fs::File::create(&path).unwrap().write_all(b"0123456789").unwrap();
+        let path = path.to_str().unwrap();
+        let ctx = |range: Option<&str>| {
+            let mut req = http::Request::builder();
+            if let Some(range) = range {
+                req = req.header(http::header::RANGE, range);
+            }
+            let req = crate::request::Request::new(req.body(crate::body::Body::empty()).unwrap(), None);
+            HttpContext::new(req, crate::router::Router::builder().build())
+        };
+
+        let ranged = ctx(Some("bytes=2-5"));
+        let stream = File::open(path).await.unwrap().into_encoded_stream(&ranged, Compression::Gzip).await.unwrap();
+        let res = stream.respond_with_builder(Builder::new(), &ranged).build().unwrap();
+        assert_eq!(res.status(), 206);
+        assert!(res.headers().get(http::header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().get(http::header::CONTENT_RANGE).unwrap(), "bytes 2-5/10");
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"2345"));
+
+        let full = ctx(None);
+        let stream = File::open(path).await.unwrap().into_encoded_stream(&full, Compression::Gzip).await.unwrap();
+        let res = stream.respond_with_builder(Builder::new(), &full).build().unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(http::header::CONTENT_ENCODING).unwrap(), "gzip");
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded).unwrap();
+        assert_eq!(decoded, b"0123456789");
+
+        // An already encoded stream can't serve the identity range
+        let encoded = FileStream::new(FileCursor::new(body.to_vec(), None, PathBuf::from("saphir_encoded_digits.txt")))
+            .content_encoding(Compression::Gzip)
+            .auto_range(true);
+        let res = encoded.respond_with_builder(Builder::new(), &ranged).build().unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get(http::header::CONTENT_RANGE).is_none());
+        assert_eq!(res.headers().get(http::header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[tokio::test]
+    async fn tailed_stream_delivers_appended_bytes() {
+        use futures::StreamExt;
+        use std::io::Write;
+        use tokio::time::timeout;
+
+        let path = std::env::temp_dir().join("saphir_tailed.log");
+        std::fs::write(&path, b"line 1\n").unwrap();
+
+        let file = File::open(path.to_str().unwrap()).await.unwrap();
+        let mut stream = FileStream::new(file).tail(true).tail_interval(Duration::from_millis(10));
+        assert_eq!(
+            timeout(Duration::from_secs(5), stream.next()).await.unwrap().unwrap().unwrap(),
+            Bytes::from_static(b"line 1\n")
+        );
+
+        // Nothing new yet, the stream waits instead of ending
+        assert!(timeout(Duration::from_millis(50), stream.next()).await.is_err());
+
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"line 2\n").unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(5), stream.next()).await.unwrap().unwrap().unwrap(),
+            Bytes::from_static(b"line 2\n")
+        );
+
+        // Truncated by a rotation, there is nothing more to follow
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+        assert!(timeout(Duration::from_secs(5), stream.next()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn tailed_stream_ends_when_the_file_is_replaced() {
+        use futures::StreamExt;
+        use tokio::time::timeout;
+
+        let path = std::env::temp_dir().join("saphir_tailed_replaced.log");
+        std::fs::write(&path, b"line 1\n").unwrap();
+
+        let file = File::open(path.to_str().unwrap()).await.unwrap();
+        let mut stream = FileStream::new(file).tail(true).tail_interval(Duration::from_millis(10));
+        assert_eq!(
+            timeout(Duration::from_secs(5), stream.next()).await.unwrap().unwrap().unwrap(),
+            Bytes::from_static(b"line 1\n")
+        );
+
+        // The new file is bigger than what was sent, only its identity tells it apart
+        let rotated = std::env::temp_dir().join("saphir_tailed_replaced.log.new");
+        std::fs::write(&rotated, b"line 1\nline 2\nline 3\n").unwrap();
+        std::fs::rename(&rotated, &path).unwrap();
+        assert!(timeout(Duration::from_secs(5), stream.next()).await.unwrap().is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn files_reporting_no_size_are_read_to_their_end() {
+        use futures::StreamExt;
+
+        let file = File::open("/proc/self/status").await.unwrap();
+        assert_eq!(file.get_size(), 0);
+        let stream = FileStream::new(file);
+        let req = crate::request::Request::new(http::Request::builder().body(crate::body::Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, crate::router::Router::builder().build());
+        let res = stream.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        assert!(res.headers().get(http::header::CONTENT_LENGTH).is_none());
+
+        let mut stream = FileStream::new(File::open("/proc/self/status").await.unwrap());
+        let mut status = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            status.extend_from_slice(&chunk.unwrap());
+        }
+        assert!(String::from_utf8(status).unwrap().contains("Pid:"));
+    }
+
+    #[tokio::test]
+    async fn stream_of_a_truncated_file_is_aborted() {
+        use futures::StreamExt;
+
+        let path = std::env::temp_dir().join("saphir_truncated_stream.bin");
+        std::fs::write(&path, vec![b'x'; MAX_BUFFER * 3]).unwrap();
+        let mut stream = FileStream::new(File::open(path.to_str().unwrap()).await.unwrap());
+        assert_eq!(stream.get_size(), MAX_BUFFER as u64 * 3);
+        let mut sent = stream.next().await.unwrap().unwrap().len();
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(MAX_BUFFER as u64 + 10)
+            .unwrap();
+        let error = loop {
+            match stream.next().await.unwrap() {
+                Ok(chunk) => sent += chunk.len(),
+                Err(e) => break e,
+            }
+        };
+        // What the file still had was sent, then the stream failed instead of ending
+        assert_eq!(sent, MAX_BUFFER + 10);
+        let error = error.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(), format!("file ended after {} of {} bytes", MAX_BUFFER + 10, MAX_BUFFER * 3));
+
+        // The declared length is never exceeded either
+        std::fs::write(&path, b"0123").unwrap();
+        let mut stream = FileStream::new(File::open(path.to_str().unwrap()).await.unwrap());
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"4567").unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from_static(b"0123"));
+        assert!(stream.next().await.is_none());
     }
 }
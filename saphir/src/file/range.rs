@@ -3,8 +3,8 @@
 // license: https://github.com/dekellum/hyperx/blob/master/LICENSE
 // source: https://github.com/dekellum/hyperx/blob/master/src/header/common/range.rs
 
-use crate::error::SaphirError;
-use std::str::FromStr;
+use crate::{error::SaphirError, response::Builder};
+use std::{path::Path, str::FromStr};
 
 /// `Range` header, defined in [RFC7233](https://tools.ietf.org/html/rfc7233#section-3.1)
 ///
@@ -21,6 +21,37 @@ pub enum Range {
     Unregistered(String, String),
 }
 
+impl Range {
+    /// The range unit, `bytes` or the unregistered one
+    pub fn unit(&self) -> &str {
+        match self {
+            Range::Bytes(_) => "bytes",
+            Range::Unregistered(unit, _) => unit.as_str(),
+        }
+    }
+}
+
+/// Serves the file ranges of a unit other than `bytes`, see
+/// `FileMiddlewareBuilder::range_unit_handler`.
+///
+/// Handlers run on the blocking executor of the file middleware, so they can
+/// read the file synchronously.
+pub trait RangeUnitHandler: Send + Sync {
+    /// Respond to a request for the `range_set` of the file at `path`,
+    /// `size` bytes long, usually with a `206 Partial Content`. `None` means
+    /// the range is not satisfiable, and gets a `416 Range Not Satisfiable`.
+    fn respond(&self, path: &Path, size: u64, range_set: &str) -> Option<Builder>;
+}
+
+impl<F> RangeUnitHandler for F
+where
+    F: Fn(&Path, u64, &str) -> Option<Builder> + Send + Sync,
+{
+    fn respond(&self, path: &Path, size: u64, range_set: &str) -> Option<Builder> {
+        (self)(path, size, range_set)
+    }
+}
+
 /// Each `Range::Bytes` header can contain one or more `ByteRangeSpecs`.
 /// Each `ByteRangeSpec` defines a range of bytes to fetch
 #[derive(PartialEq, Clone, Debug)]
@@ -141,7 +172,7 @@ impl FromStr for Range {
         let mut iter = s.splitn(2, '=');
 
         match (iter.next(), iter.next()) {
-            (Some("bytes"), Some(ranges)) => {
+            (Some(unit), Some(ranges)) if unit.eq_ignore_ascii_case("bytes") => {
                 let ranges = from_comma_delimited(ranges);
                 if ranges.is_empty() {
                     return Err(SaphirError::Other("Range is empty".to_owned()));
@@ -235,6 +266,12 @@ mod tests {
         let r = Range::from_str("custom=xxx-yyy").unwrap();
         let r2 = Range::Unregistered("custom".to_owned(), "xxx-yyy".to_owned());
         assert_eq!(r, r2);
+        assert_eq!(r.unit(), "custom");
+        assert_eq!(r.to_string(), "custom=xxx-yyy");
+
+        let r = Range::from_str("Bytes=1-100").unwrap();
+        assert_eq!(r, bytes(1, 100));
+        assert_eq!(r.unit(), "bytes");
     }
 
     #[test]
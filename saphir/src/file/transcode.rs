@@ -0,0 +1,332 @@
+//! On the fly transcoding of images to the formats the clients prefer.
+
+use crate::file::blocking::filesystem_call;
+use mime::Mime;
+use std::{
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::UNIX_EPOCH,
+};
+
+const DEFAULT_MAX_SOURCE_SIZE: u64 = 16_777_216;
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+const DEFAULT_MAX_CACHE_SIZE: u64 = 268_435_456;
+
+/// Extension of the files recording the source of the entries of a hash
+const SOURCE_EXTENSION: &str = ".source";
+/// Extension of the entries being written
+const PART_EXTENSION: &str = ".part";
+
+static PARTIAL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Encoder of the transcoded images. Saphir ships none, so this is where an
+/// image library is plugged in. It is implemented by the closures taking the
+/// source image, its type and the type to encode it to.
+pub trait ImageTranscoder: Send + Sync {
+    /// Encode `image`, of type `from`, to `to`
+    fn transcode(&self, image: &[u8], from: &Mime, to: &Mime) -> io::Result<Vec<u8>>;
+}
+
+impl<F> ImageTranscoder for F
+where
+    F: Fn(&[u8], &Mime, &Mime) -> io::Result<Vec<u8>> + Send + Sync,
+{
+    fn transcode(&self, image: &[u8], from: &Mime, to: &Mime) -> io::Result<Vec<u8>> {
+        (self)(image, from, to)
+    }
+}
+
+/// Transcoding of the JPEG and PNG files served by the file middleware to
+/// the first of `formats` the client accepts, see
+/// `FileMiddlewareBuilder::image_transcoding`.
+///
+/// A file is transcoded on the first request wanting it in a format, and the
+/// result is kept in `cache_dir` until the file is modified or deleted. The
+/// cache holds at most `max_cache_size` bytes, the oldest transcodings being
+/// removed past it. Transcoding is bounded: files larger than
+/// `max_source_size` aren't transcoded, and while `max_concurrent` files are
+/// being transcoded, the others are served as is.
+///
+/// ```rust
+/// # use saphir::file::{middleware::FileMiddlewareBuilder, transcode::ImageTranscoding};
+/// # use mime::Mime;
+/// # fn encode(image: &[u8], to: &Mime) -> std::io::Result<Vec<u8>> { Ok(image.to_vec()) }
+/// let transcoding = ImageTranscoding::new(|image: &[u8], _from: &Mime, to: &Mime| encode(image, to), "/var/cache/images")
+///     .formats(&["image/webp"])
+///     .max_concurrent(4);
+///
+/// let middleware = FileMiddlewareBuilder::new("static", "/var/www").image_transcoding(transcoding).build();
+/// ```
+pub struct ImageTranscoding {
+    transcoder: Arc<dyn ImageTranscoder>,
+    cache_dir: PathBuf,
+    formats: Vec<Mime>,
+    max_source_size: u64,
+    max_concurrent: usize,
+    max_cache_size: u64,
+    running: AtomicUsize,
+}
+
+/// A transcoding slot, given back when dropped, even by a panicking
+/// transcoder
+struct Slot<'a>(&'a AtomicUsize);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ImageTranscoding {
+    /// Transcode with `transcoder` to AVIF or WebP, in this order of
+    /// preference, caching the results in `cache_dir`
+    pub fn new<T: 'static + ImageTranscoder, P: Into<PathBuf>>(transcoder: T, cache_dir: P) -> Self {
+        ImageTranscoding {
+            transcoder: Arc::new(transcoder),
+            cache_dir: cache_dir.into(),
+            formats: vec!["image/avif".parse().expect("valid mime"), "image/webp".parse().expect("valid mime")],
+            max_source_size: DEFAULT_MAX_SOURCE_SIZE,
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            max_cache_size: DEFAULT_MAX_CACHE_SIZE,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    /// Transcode to the media types of `formats`, from the most to the least
+    /// preferred. Invalid media types are skipped
+    pub fn formats(mut self, formats: &[&str]) -> Self {
+        self.formats = formats.iter().filter_map(|f| f.parse().ok()).collect();
+        self
+    }
+
+    /// Serve the files larger than `size` bytes as is
+    pub fn max_source_size(mut self, size: u64) -> Self {
+        self.max_source_size = size;
+        self
+    }
+
+    /// Transcode at most `count` files at once
+    pub fn max_concurrent(mut self, count: usize) -> Self {
+        self.max_concurrent = count;
+        self
+    }
+
+    /// Keep at most `size` bytes of transcodings in the cache directory,
+    /// removing the oldest ones past it
+    pub fn max_cache_size(mut self, size: u64) -> Self {
+        self.max_cache_size = size;
+        self
+    }
+
+    /// The media types to transcode to, by preference
+    pub(crate) fn targets(&self) -> &[Mime] {
+        &self.formats
+    }
+
+    /// Whether files of type `mime` are transcoded
+    pub(crate) fn transcodes(&self, mime: &Mime) -> bool {
+        mime.essence_str() == mime::IMAGE_JPEG.essence_str() || mime.essence_str() == mime::IMAGE_PNG.essence_str()
+    }
+
+    /// The cached transcoding of `source` to `to`, transcoding it on a miss.
+    /// `None` when it is over the limits or the transcoder fails, the source
+    /// then being served as is. Blocks on the filesystem and the transcoder
+    pub(crate) fn cached(&self, source: &Path, from: &Mime, to: &Mime, modified: u64, size: u64) -> Option<PathBuf> {
+        // The entries of a source keep their name across restarts
+        let hash = format!("{:x}", md5::compute(source.to_string_lossy().as_bytes()));
+        let extension = to.subtype().as_str();
+        let cached = self.cache_dir.join(format!("{}-{}-{}.{}", hash, modified, size, extension));
+        filesystem_call();
+        if cached.is_file() {
+            return Some(cached);
+        }
+
+        if size > self.max_source_size {
+            return None;
+        }
+        let max_concurrent = self.max_concurrent;
+        if self
+            .running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| {
+                Some(running + 1).filter(|running| *running <= max_concurrent)
+            })
+            .is_err()
+        {
+            debug!("Too many images being transcoded, serving {} as is", source.display());
+            return None;
+        }
+        let slot = Slot(&self.running);
+        let transcoded = self.transcode(source, from, to, &cached, &hash);
+        drop(slot);
+
+        match transcoded {
+            Ok(()) => Some(cached),
+            Err(e) => {
+                warn!("Unable to transcode {} to {}: {}", source.display(), to, e);
+                None
+            }
+        }
+    }
+
+    /// Write the transcoding of `source` to `cached`, recording the source of
+    /// the entries of `hash`, then prune the cache
+    fn transcode(&self, source: &Path, from: &Mime, to: &Mime, cached: &Path, hash: &str) -> io::Result<()> {
+        let image = fs::read(source)?;
+        let transcoded = panic::catch_unwind(AssertUnwindSafe(|| self.transcoder.transcode(&image, from, to)))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the transcoder panicked"))??;
+
+        // Concurrent misses each write their own file, the last rename wins
+        fs::create_dir_all(&self.cache_dir)?;
+        let mut partial = cached.as_os_str().to_os_string();
+        partial.push(format!(
+            ".{}-{}{}",
+            std::process::id(),
+            PARTIAL_FILES.fetch_add(1, Ordering::Relaxed),
+            PART_EXTENSION
+        ));
+        fs::write(&partial, transcoded)?;
+        fs::rename(&partial, cached)?;
+        fs::write(
+            self.cache_dir.join(format!("{}{}", hash, SOURCE_EXTENSION)),
+            source.to_string_lossy().as_bytes(),
+        )?;
+
+        self.prune(cached, hash)
+    }
+
+    /// Remove the entries of the previous versions of the source of `hash`,
+    /// the entries of the deleted sources, then the oldest entries until the
+    /// cache fits in `max_cache_size`. `cached`, just written, is kept
+    fn prune(&self, cached: &Path, hash: &str) -> io::Result<()> {
+        let extension = cached.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut deleted_sources = Vec::new();
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.cache_dir)?.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(entry_hash) = name.strip_suffix(SOURCE_EXTENSION) {
+                let source = fs::read_to_string(&path).unwrap_or_default();
+                if !Path::new(&source).exists() {
+                    deleted_sources.push(format!("{}-", entry_hash));
+                    let _ = fs::remove_file(&path);
+                }
+            } else if name.ends_with(PART_EXTENSION) || path == cached {
+                continue;
+            } else if name.starts_with(&format!("{}-", hash)) && name.ends_with(&format!(".{}", extension)) {
+                let _ = fs::remove_file(&path);
+            } else if let Ok(metadata) = entry.metadata() {
+                entries.push((name, path, metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH)));
+            }
+        }
+
+        let mut size = fs::metadata(cached)?.len();
+        entries.retain(|(name, path, len, _)| {
+            if deleted_sources.iter().any(|prefix| name.starts_with(prefix)) {
+                let _ = fs::remove_file(path);
+                false
+            } else {
+                size += len;
+                true
+            }
+        });
+
+        entries.sort_by_key(|(.., modified)| *modified);
+        for (_, path, len, _) in entries {
+            if size <= self.max_cache_size {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcodings_are_bounded() {
+        let cache_dir = std::env::temp_dir().join("saphir_transcode_bounded");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let source = std::env::temp_dir().join("saphir_transcode_bounded.png");
+        fs::write(&source, b"png").unwrap();
+        let webp: Mime = "image/webp".parse().unwrap();
+        let transcoding = ImageTranscoding::new(|_: &[u8], _: &Mime, _: &Mime| Ok(b"webp".to_vec()), &cache_dir).max_source_size(8);
+
+        assert!(transcoding.transcodes(&mime::IMAGE_PNG));
+        assert!(!transcoding.transcodes(&mime::IMAGE_GIF));
+        assert!(transcoding.cached(&source, &mime::IMAGE_PNG, &webp, 0, 9).is_none());
+
+        let first = transcoding.cached(&source, &mime::IMAGE_PNG, &webp, 1, 3).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), b"webp");
+        // A new version of the source replaces the entry of the previous one
+        let second = transcoding.cached(&source, &mime::IMAGE_PNG, &webp, 2, 3).unwrap();
+        assert!(second.is_file());
+        assert!(!first.exists());
+
+        let busy = ImageTranscoding::new(|_: &[u8], _: &Mime, _: &Mime| Ok(Vec::new()), &cache_dir).max_concurrent(0);
+        assert!(busy.cached(&source, &mime::IMAGE_PNG, &webp, 3, 3).is_none());
+    }
+
+    #[test]
+    fn panicking_transcoder_gives_its_slot_back() {
+        let cache_dir = std::env::temp_dir().join("saphir_transcode_panicking");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let broken = std::env::temp_dir().join("saphir_transcode_broken.png");
+        let source = std::env::temp_dir().join("saphir_transcode_panicking.png");
+        fs::write(&broken, b"broken").unwrap();
+        fs::write(&source, b"png").unwrap();
+        let webp: Mime = "image/webp".parse().unwrap();
+        let transcoding = ImageTranscoding::new(
+            |image: &[u8], _: &Mime, _: &Mime| {
+                assert_ne!(image, b"broken");
+                Ok(b"webp".to_vec())
+            },
+            &cache_dir,
+        )
+        .max_concurrent(1);
+
+        assert!(transcoding.cached(&broken, &mime::IMAGE_PNG, &webp, 1, 6).is_none());
+        assert!(transcoding.cached(&source, &mime::IMAGE_PNG, &webp, 1, 3).is_some());
+    }
+
+    #[test]
+    fn cache_is_bounded_and_drops_deleted_sources() {
+        let cache_dir = std::env::temp_dir().join("saphir_transcode_pruned");
+        let _ = fs::remove_dir_all(&cache_dir);
+        let sources: Vec<PathBuf> = (0..3)
+            .map(|i| {
+                let source = std::env::temp_dir().join(format!("saphir_transcode_pruned_{}.png", i));
+                fs::write(&source, b"png").unwrap();
+                source
+            })
+            .collect();
+        let webp: Mime = "image/webp".parse().unwrap();
+        let transcoding = ImageTranscoding::new(|_: &[u8], _: &Mime, _: &Mime| Ok(b"webp".to_vec()), &cache_dir).max_cache_size(8);
+
+        // Two entries of 4 bytes fit, the oldest goes with the third
+        let first = transcoding.cached(&sources[0], &mime::IMAGE_PNG, &webp, 1, 3).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let second = transcoding.cached(&sources[1], &mime::IMAGE_PNG, &webp, 1, 3).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let third = transcoding.cached(&sources[2], &mime::IMAGE_PNG, &webp, 1, 3).unwrap();
+        assert!(!first.exists());
+        assert!(second.is_file());
+        assert!(third.is_file());
+
+        // The entries of a deleted source go with the next transcoding
+        fs::remove_file(&sources[1]).unwrap();
+        let first = transcoding.cached(&sources[0], &mime::IMAGE_PNG, &webp, 2, 3).unwrap();
+        assert!(first.is_file());
+        assert!(!second.exists());
+        assert!(third.is_file());
+    }
+}
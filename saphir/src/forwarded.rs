@@ -0,0 +1,275 @@
+//! Parsing of the `Forwarded` header (RFC 7239).
+//!
+//! Each proxy relaying the request appends an element describing the hop it
+//! handled, so the elements are listed from the one closest to the client to
+//! the one added by the proxy connected to the server. Since any client can
+//! send the header, it is only used to find the client address and protocol
+//! when the proxies adding it are trusted, see
+//! `router::Builder::trusted_proxies`.
+
+use crate::request::validate_host;
+use http::{header::FORWARDED, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
+
+/// One element of the `Forwarded` header, describing a hop of the request
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForwardedHop {
+    by: Option<String>,
+    forwarded_for: Option<String>,
+    host: Option<String>,
+    proto: Option<String>,
+}
+
+impl ForwardedHop {
+    /// The `by=` node, the interface of the proxy which received the request
+    pub fn by(&self) -> Option<&str> {
+        self.by.as_deref()
+    }
+
+    /// The `for=` node, the client or proxy which sent the request
+    pub fn forwarded_for(&self) -> Option<&str> {
+        self.forwarded_for.as_deref()
+    }
+
+    /// The `host=` parameter, the `Host` the request was received with
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The `proto=` parameter, the protocol the request was received with
+    pub fn proto(&self) -> Option<&str> {
+        self.proto.as_deref()
+    }
+
+    /// The address of the `for=` node, if it is neither `unknown` nor an
+    /// obfuscated identifier
+    pub fn for_ip(&self) -> Option<IpAddr> {
+        self.forwarded_for.as_deref().and_then(node_ip)
+    }
+}
+
+/// Parse every `Forwarded` header of `headers`, in order. Parameters without
+/// a value and unknown parameters are ignored.
+///
+/// ```rust
+/// # use saphir::forwarded;
+/// let mut headers = http::HeaderMap::new();
+/// headers.insert(http::header::FORWARDED, "for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\"".parse().unwrap());
+///
+/// let hops = forwarded::parse(&headers);
+/// assert_eq!(hops[0].proto(), Some("https"));
+/// assert_eq!(hops[1].for_ip(), Some("2001:db8::1".parse().unwrap()));
+/// ```
+pub fn parse(headers: &HeaderMap) -> Vec<ForwardedHop> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| split_unquoted(h, ','))
+        .map(parse_element)
+        .collect()
+}
+
+fn parse_element(element: &str) -> ForwardedHop {
+    let mut hop = ForwardedHop::default();
+    for pair in split_unquoted(element, ';') {
+        let mut pair = pair.splitn(2, '=');
+        let (name, value) = match (pair.next(), pair.next()) {
+            (Some(name), Some(value)) => (name.trim(), unquote(value.trim())),
+            _ => continue,
+        };
+
+        let param = if name.eq_ignore_ascii_case("by") {
+            &mut hop.by
+        } else if name.eq_ignore_ascii_case("for") {
+            &mut hop.forwarded_for
+        } else if name.eq_ignore_ascii_case("host") {
+            &mut hop.host
+        } else if name.eq_ignore_ascii_case("proto") {
+            &mut hop.proto
+        } else {
+            continue;
+        };
+        *param = Some(value);
+    }
+    hop
+}
+
+/// Split `value` on `separator`, except inside quoted strings
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(value[start..i].trim());
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return value.to_string();
+    }
+
+    let mut unquoted = String::with_capacity(value.len() - 2);
+    let mut escaped = false;
+    for c in value[1..value.len() - 1].chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            unquoted.push(c);
+            escaped = false;
+        }
+    }
+    unquoted
+}
+
+/// Address of a node, which is an IPv4 address or a bracketed IPv6 address,
+/// both with an optional port
+fn node_ip(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// Extension holding the client address found for a request, see
+/// `Request::client_addr`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClientAddr(pub IpAddr);
+
+/// Extension holding the URL reconstructed for a request, see
+/// `Request::full_url`
+#[derive(Clone, Debug)]
+pub(crate) struct FullUrl(pub String);
+
+/// Client address, protocol and host of a request received from `peer`: the
+/// hops are walked back from the server as long as they were added by a
+/// trusted proxy. The walk stops at the first hop without a usable address,
+/// leaving the proxy which added it as the client. The host is only set when
+/// a trusted hop gives a valid one.
+pub(crate) fn effective_client(hops: &[ForwardedHop], peer: Option<IpAddr>, tls: bool, trusted: &[IpAddr]) -> (Option<IpAddr>, String, Option<String>) {
+    let mut addr = peer;
+    let mut proto = if tls { "https" } else { "http" }.to_string();
+    let mut host = None;
+    for hop in hops.iter().rev() {
+        match addr {
+            Some(a) if trusted.contains(&a) => {}
+            _ => break,
+        }
+
+        match hop.for_ip() {
+            Some(ip) => {
+                addr = Some(ip);
+                if let Some(p) = hop.proto() {
+                    proto = p.to_ascii_lowercase();
+                }
+                if let Some(h) = hop.host().filter(|h| validate_host(h).is_some()) {
+                    host = Some(h.to_string());
+                }
+            }
+            None => break,
+        }
+    }
+    (addr, proto, host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, http_context::HttpContext, request::Request, router::Router};
+
+    #[test]
+    fn multi_hop_header_is_parsed() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            FORWARDED,
+            "for=192.0.2.43;proto=https;host=\"example.com\", for=\"[2001:db8:cafe::17]:4711\";by=10.0.0.1"
+                .parse()
+                .unwrap(),
+        );
+        headers.append(FORWARDED, "For=unknown;by=\"_proxy;2\";ext=ignored, proto".parse().unwrap());
+
+        let hops = parse(&headers);
+        assert_eq!(hops.len(), 4);
+        assert_eq!(hops[0].forwarded_for(), Some("192.0.2.43"));
+        assert_eq!(hops[0].proto(), Some("https"));
+        assert_eq!(hops[0].host(), Some("example.com"));
+        assert_eq!(hops[1].for_ip(), Some("2001:db8:cafe::17".parse().unwrap()));
+        assert_eq!(hops[1].by(), Some("10.0.0.1"));
+        assert_eq!(hops[2].forwarded_for(), Some("unknown"));
+        assert_eq!(hops[2].for_ip(), None);
+        assert_eq!(hops[2].by(), Some("_proxy;2"));
+        assert_eq!(hops[3], ForwardedHop::default());
+    }
+
+    #[test]
+    fn client_is_found_through_trusted_proxies() {
+        let router = Router::builder()
+            .trusted_proxies(vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()])
+            .build();
+        let ctx = |peer: &str, forwarded: &str| {
+            let req = http::Request::builder().header(FORWARDED, forwarded).body(Body::empty()).unwrap();
+            HttpContext::new(Request::new(req, Some(peer.parse().unwrap())), router.clone())
+        };
+
+        let chain = "for=203.0.113.9, for=198.51.100.7;proto=https, for=10.0.0.2;proto=http";
+        let trusted = ctx("10.0.0.1:443", chain);
+        assert_eq!(trusted.forwarded().len(), 3);
+        assert_eq!(trusted.client_addr(), Some("198.51.100.7".parse().unwrap()));
+        assert_eq!(trusted.client_proto(), "https");
+
+        let direct = ctx("198.51.100.7:5000", chain);
+        assert_eq!(direct.client_addr(), Some("198.51.100.7".parse().unwrap()));
+        assert_eq!(direct.client_proto(), "http");
+
+        let obfuscated = ctx("10.0.0.1:443", "for=203.0.113.9, for=_hidden;proto=https");
+        assert_eq!(obfuscated.client_addr(), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(obfuscated.client_proto(), "http");
+    }
+
+    #[test]
+    fn full_url_is_reconstructed() {
+        let router = Router::builder().trusted_proxies(vec!["10.0.0.1".parse().unwrap()]).build();
+        let ctx = |peer: &str, tls: bool, headers: &[(http::header::HeaderName, &str)]| {
+            let mut req = http::Request::builder().uri("/search?q=saphir");
+            for (name, value) in headers {
+                req = req.header(name, *value);
+            }
+            let mut req = req.body(Body::empty()).unwrap();
+            if tls {
+                req.extensions_mut().insert(crate::request::TlsConnection);
+            }
+            HttpContext::new(Request::new(req, Some(peer.parse().unwrap())), router.clone())
+        };
+        let host = http::header::HOST;
+
+        let plain = ctx("198.51.100.7:5000", false, &[(host.clone(), "example.com:8080")]);
+        assert_eq!(plain.full_url(), Some("http://example.com:8080/search?q=saphir"));
+        assert_eq!(plain.state.request_unchecked().full_url(), plain.full_url());
+
+        let tls = ctx("198.51.100.7:5000", true, &[(host.clone(), "example.com")]);
+        assert_eq!(tls.full_url(), Some("https://example.com/search?q=saphir"));
+
+        // The host and protocol given by a trusted proxy take precedence
+        let proxied = [
+            (host.clone(), "backend:8000"),
+            (FORWARDED, "for=203.0.113.9;proto=https;host=\"shop.example.com\""),
+        ];
+        assert_eq!(ctx("10.0.0.1:80", false, &proxied).full_url(), Some("https://shop.example.com/search?q=saphir"));
+        assert_eq!(ctx("203.0.113.50:80", false, &proxied).full_url(), Some("http://backend:8000/search?q=saphir"));
+
+        assert_eq!(ctx("198.51.100.7:5000", false, &[(host, "bad host")]).full_url(), None);
+    }
+}
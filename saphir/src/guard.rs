@@ -2,13 +2,18 @@
 //! can modify the request data or stops request processing by returning a
 //! response immediately.
 
+#[cfg(feature = "form")]
+use crate::error::ParameterLocation;
 use crate::{
     body::Body,
+    error::ExtractError,
     request::Request,
     responder::{DynResponder, Responder},
 };
 use futures::{future::BoxFuture, FutureExt};
 use futures_util::future::Future;
+#[cfg(feature = "form")]
+use regex::Regex;
 
 /// Auto trait implementation over every function that match the definition of a
 /// guard.
@@ -116,3 +121,256 @@ where
         false
     }
 }
+
+/// Using Feature `form`
+///
+/// Guard ensuring query parameters are present, and optionally that their
+/// value matches a pattern, before the handler runs. The request is rejected
+/// otherwise, with the `ExtractError` of the first offending parameter.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::guard::RequiredQuery;
+/// # use regex::Regex;
+/// async fn export(_req: Request<Body>) -> u16 {
+///     200
+/// }
+///
+/// let server = Server::builder()
+///     .configure_router(|r| {
+///         r.route_with_guards("/export", Method::GET, export, |g| {
+///             g.apply(RequiredQuery::new().param("token").param_matching("page", Regex::new("^[0-9]+$").unwrap()))
+///         })
+///     })
+///     .build();
+/// ```
+#[cfg(feature = "form")]
+#[derive(Default)]
+pub struct RequiredQuery {
+    params: Vec<(String, Option<Regex>)>,
+}
+
+#[cfg(feature = "form")]
+impl RequiredQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require the `name` query parameter
+    pub fn param(mut self, name: &str) -> Self {
+        self.params.push((name.to_string(), None));
+        self
+    }
+
+    /// Require the `name` query parameter, with a value matching `pattern`
+    pub fn param_matching(mut self, name: &str, pattern: Regex) -> Self {
+        self.params.push((name.to_string(), Some(pattern)));
+        self
+    }
+
+    fn check(&self, query: &[(String, String)]) -> Result<(), ExtractError> {
+        for (name, pattern) in &self.params {
+            match query.iter().find(|(key, _)| key == name) {
+                None => {
+                    return Err(ExtractError::MissingParameter {
+                        name: name.clone(),
+                        location: ParameterLocation::Query,
+                    })
+                }
+                Some((_, value)) if pattern.as_ref().map(|p| !p.is_match(value)).unwrap_or(false) => {
+                    return Err(ExtractError::InvalidParameter {
+                        name: name.clone(),
+                        location: ParameterLocation::Query,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "form")]
+impl Guard for RequiredQuery {
+    type Future = futures::future::Ready<Result<Request<Body>, Self::Responder>>;
+    type Responder = ExtractError;
+
+    fn validate(&'static self, req: Request<Body>) -> Self::Future {
+        let query = match req.uri().query().map(serde_urlencoded::from_str::<Vec<(String, String)>>).transpose() {
+            Ok(query) => query.unwrap_or_default(),
+            Err(e) => return futures::future::ready(Err(ExtractError::InvalidForm(e))),
+        };
+
+        futures::future::ready(self.check(&query).map(|_| req))
+    }
+}
+
+/// Guard accepting request bodies only in the listed media types, and for
+/// the media types it is given charsets, only in those charsets. A body in
+/// any other media type or charset is rejected with a
+/// `415 Unsupported Media Type`, before the handler decodes it wrongly. A
+/// body without a `charset` parameter is accepted, in the default charset of
+/// its media type, and requests without a body are let through.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::guard::RequiredContentType;
+/// async fn import(_req: Request<Body>) -> u16 {
+///     200
+/// }
+///
+/// let server = Server::builder()
+///     .configure_router(|r| {
+///         r.route_with_guards("/import", Method::POST, import, |g| {
+///             g.apply(RequiredContentType::new().allow_charsets("application/json", &["utf-8"]).allow("text/csv"))
+///         })
+///     })
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct RequiredContentType {
+    media_types: Vec<(String, Vec<String>)>,
+}
+
+impl RequiredContentType {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept bodies of `media_type`, in any charset
+    pub fn allow(self, media_type: &str) -> Self {
+        self.allow_charsets(media_type, &[])
+    }
+
+    /// Accept bodies of `media_type` in one of `charsets`, or in every
+    /// charset when `charsets` is empty
+    pub fn allow_charsets(mut self, media_type: &str, charsets: &[&str]) -> Self {
+        self.media_types
+            .push((media_type.to_ascii_lowercase(), charsets.iter().map(|c| c.to_ascii_lowercase()).collect()));
+        self
+    }
+
+    fn check(&self, content_type: &str) -> Result<(), ExtractError> {
+        let mut params = content_type.split(';').map(|p| p.trim());
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let charset = params.find_map(|p| {
+            let (name, value) = p.split_once('=')?;
+            Some(value.trim().trim_matches('"').to_ascii_lowercase()).filter(|_| name.trim().eq_ignore_ascii_case("charset"))
+        });
+
+        let charsets = match self.media_types.iter().find(|(allowed, _)| *allowed == media_type) {
+            Some((_, charsets)) => charsets,
+            None => return Err(ExtractError::UnsupportedMediaType { media_type }),
+        };
+        match charset {
+            Some(charset) if !charsets.is_empty() && !charsets.contains(&charset) => Err(ExtractError::UnsupportedCharset { media_type, charset }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the headers of a request announce a body
+fn has_body(headers: &http::HeaderMap) -> bool {
+    headers.contains_key(http::header::TRANSFER_ENCODING)
+        || headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .map(|len| len.trim() != "0")
+            .unwrap_or(false)
+}
+
+impl Guard for RequiredContentType {
+    type Future = futures::future::Ready<Result<Request<Body>, Self::Responder>>;
+    type Responder = ExtractError;
+
+    fn validate(&'static self, req: Request<Body>) -> Self::Future {
+        if !has_body(req.headers()) {
+            return futures::future::ready(Ok(req));
+        }
+
+        let checked = match req.headers().get(http::header::CONTENT_TYPE).map(|h| h.to_str()) {
+            Some(Ok(content_type)) => self.check(content_type),
+            Some(Err(_)) => Err(ExtractError::MalformedContentType),
+            None => Err(ExtractError::MissingContentType),
+        };
+        futures::future::ready(checked.map(|_| req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http_context::HttpContext, router::Router};
+    use http::Method;
+
+    async fn export(_req: Request<Body>) -> u16 {
+        200
+    }
+
+    async fn call(router: &Router, uri: &str) -> (u16, String) {
+        let req = Request::new(http::Request::builder().method(Method::GET).uri(uri).body(Body::empty()).unwrap(), None);
+        send(router, req).await
+    }
+
+    async fn send(router: &Router, req: Request<Body>) -> (u16, String) {
+        let mut ctx = router.clone().handle(HttpContext::new(req, router.clone())).await.unwrap();
+        let res = ctx.state.take_response().unwrap();
+        let status = res.status().as_u16();
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[cfg(feature = "form")]
+    #[tokio::test]
+    async fn required_query_rejects_missing_params() {
+        let router = Router::builder()
+            .route_with_guards("/export", Method::GET, export, |g| {
+                g.apply(RequiredQuery::new().param("token").param_matching("page", Regex::new("^[0-9]+$").unwrap()))
+            })
+            .build();
+
+        assert_eq!(call(&router, "/export?token=abc&page=2").await, (200, String::new()));
+        assert_eq!(call(&router, "/export").await, (400, "Missing query parameter: token".to_string()));
+        assert_eq!(call(&router, "/export?token=abc").await, (400, "Missing query parameter: page".to_string()));
+        assert_eq!(
+            call(&router, "/export?token=abc&page=two").await,
+            (400, "Invalid query parameter: page".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn required_content_type_checks_the_charset() {
+        let router = Router::builder()
+            .route_with_guards("/import", Method::POST, export, |g| {
+                g.apply(RequiredContentType::new().allow_charsets("application/json", &["utf-8"]).allow("text/csv"))
+            })
+            .build();
+        let post = |content_type: Option<&str>| {
+            let mut req = http::Request::builder()
+                .method(Method::POST)
+                .uri("/import")
+                .header(http::header::CONTENT_LENGTH, "2");
+            if let Some(content_type) = content_type {
+                req = req.header(http::header::CONTENT_TYPE, content_type);
+            }
+            Request::new(req.body(Body::from_raw(hyper::Body::from("{}"))).unwrap(), None)
+        };
+
+        for accepted in &["application/json", "Application/JSON; charset=\"UTF-8\"", "text/csv; charset=iso-8859-1"] {
+            assert_eq!(send(&router, post(Some(accepted))).await.0, 200, "{} was rejected", accepted);
+        }
+        assert_eq!(
+            send(&router, post(Some("application/json; charset=iso-8859-1"))).await,
+            (415, "Unsupported charset for application/json: iso-8859-1".to_string())
+        );
+        assert_eq!(
+            send(&router, post(Some("application/xml"))).await,
+            (415, "Unsupported media type: application/xml".to_string())
+        );
+        assert_eq!(send(&router, post(None)).await, (415, "Missing Content-Type".to_string()));
+        assert_eq!(send(&router, post(Some("text/csv; name=é"))).await, (400, "Malformed Content-Type".to_string()));
+
+        let empty = Request::new(http::Request::builder().method(Method::POST).uri("/import").body(Body::empty()).unwrap(), None);
+        assert_eq!(send(&router, empty).await.0, 200);
+    }
+}
@@ -1,4 +1,14 @@
-use crate::{request::Request, response::Response, router::Router};
+use crate::{
+    body::BodyMetrics,
+    cancellation::CancellationToken,
+    forwarded::{self, ForwardedHop},
+    request::{validate_host, Request},
+    response::Response,
+    router::Router,
+    server_timing::ServerTimings,
+};
+use http::{Method, Version};
+use std::{fmt, net::IpAddr, time::Duration};
 
 #[cfg(feature = "operation")]
 pub static OPERATION_ID_HEADER: &str = "Operation-Id";
@@ -162,15 +172,59 @@ pub struct HttpContext {
     /// Unique Identifier of the current request->response chain
     pub operation_id: crate::http_context::operation::OperationId,
     pub(crate) router: Option<Router>,
+    response_encoding: Option<String>,
+    body_metrics: BodyMetrics,
+    host: Option<String>,
+    range: Option<String>,
+    accept: Option<String>,
+    forwarded: Vec<ForwardedHop>,
+    client_addr: Option<IpAddr>,
+    client_proto: String,
+    full_url: Option<String>,
+    request_line: RequestLine,
+    cancellation_token: CancellationToken,
+    server_timings: ServerTimings,
 }
 
 impl HttpContext {
     pub(crate) fn new(request: Request, router: Router) -> Self {
         #[cfg(not(feature = "operation"))]
         {
+            let mut request = request;
+            let host = request.host().map(|h| h.to_string());
+            let range = unconditional_range(&request);
+            let accept = request.headers().get(http::header::ACCEPT).and_then(|h| h.to_str().ok()).map(|h| h.to_string());
+            let forwarded = forwarded::parse(request.headers());
+            let peer = request.peer_addr().map(|a| a.ip());
+            let (client_addr, client_proto, forwarded_host) = forwarded::effective_client(&forwarded, peer, request.is_tls(), router.trusted_proxies());
+            if let Some(addr) = client_addr {
+                request.extensions_mut().insert(forwarded::ClientAddr(addr));
+            }
+            let full_url = full_url(&request, &client_proto, forwarded_host);
+            let request_line = RequestLine::of(&request);
+            if let Some(url) = full_url.clone() {
+                request.extensions_mut().insert(forwarded::FullUrl(url));
+            }
+            let cancellation_token = request.cancellation_token().clone();
+            let server_timings = request.server_timings().clone();
             let state = State::Before(Box::new(request));
             let router = Some(router);
-            HttpContext { state, router }
+            HttpContext {
+                state,
+                router,
+                host,
+                range,
+                accept,
+                forwarded,
+                client_addr,
+                client_proto,
+                full_url,
+                request_line,
+                cancellation_token,
+                server_timings,
+                response_encoding: None,
+                body_metrics: BodyMetrics::default(),
+            }
         }
 
         #[cfg(feature = "operation")]
@@ -184,9 +238,41 @@ impl HttpContext {
                 .and_then(|op_id_str| operation::OperationId::from_str(op_id_str).ok())
                 .unwrap_or_else(operation::OperationId::new);
             *request.operation_id_mut() = operation_id;
+            let host = request.host().map(|h| h.to_string());
+            let range = unconditional_range(&request);
+            let accept = request.headers().get(http::header::ACCEPT).and_then(|h| h.to_str().ok()).map(|h| h.to_string());
+            let forwarded = forwarded::parse(request.headers());
+            let peer = request.peer_addr().map(|a| a.ip());
+            let (client_addr, client_proto, forwarded_host) = forwarded::effective_client(&forwarded, peer, request.is_tls(), router.trusted_proxies());
+            if let Some(addr) = client_addr {
+                request.extensions_mut().insert(forwarded::ClientAddr(addr));
+            }
+            let full_url = full_url(&request, &client_proto, forwarded_host);
+            let request_line = RequestLine::of(&request);
+            if let Some(url) = full_url.clone() {
+                request.extensions_mut().insert(forwarded::FullUrl(url));
+            }
+            let cancellation_token = request.cancellation_token().clone();
+            let server_timings = request.server_timings().clone();
             let state = State::Before(Box::new(request));
             let router = Some(router);
-            HttpContext { state, router, operation_id }
+            HttpContext {
+                state,
+                router,
+                host,
+                range,
+                accept,
+                forwarded,
+                client_addr,
+                client_proto,
+                full_url,
+                request_line,
+                cancellation_token,
+                server_timings,
+                operation_id,
+                response_encoding: None,
+                body_metrics: BodyMetrics::default(),
+            }
         }
     }
 
@@ -199,6 +285,168 @@ impl HttpContext {
     pub fn after(&mut self, response: Response) {
         self.state = State::After(Box::new(response))
     }
+
+    /// The validated host of the request, without its port, see
+    /// `Request::host`. It stays available after the request is handled
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The token cancelled when the request is abandoned, the same one as
+    /// `Request::cancellation_token`
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
+    /// Record a `Server-Timing` metric for the response, see
+    /// `server_timing::ServerTimingMiddleware`
+    pub fn server_timing<N: Into<String>>(&self, name: N, duration: Duration) {
+        self.server_timings.record(name, duration);
+    }
+
+    /// The `Server-Timing` metrics recorded for the request, by middlewares
+    /// and by the handler
+    pub fn server_timings(&self) -> &ServerTimings {
+        &self.server_timings
+    }
+
+    /// The `Range` header of the request. It is left out when the request
+    /// also carries an `If-Range`, since the validator can't be checked
+    /// without the resource, in which case the whole resource must be sent.
+    /// It stays available after the request is handled
+    pub fn range(&self) -> Option<&str> {
+        self.range.as_deref()
+    }
+
+    /// The `Accept` header of the request, it stays available after the
+    /// request is handled
+    pub fn accept(&self) -> Option<&str> {
+        self.accept.as_deref()
+    }
+
+    /// The elements of the `Forwarded` headers of the request, from the one
+    /// closest to the client to the one added by the last proxy. They can be
+    /// forged by the client, see `client_addr` for the trusted information
+    pub fn forwarded(&self) -> &[ForwardedHop] {
+        &self.forwarded
+    }
+
+    /// Address of the client: the address the request was received from, or
+    /// when it comes from a trusted proxy, the one given by the `Forwarded`
+    /// header, see `router::Builder::trusted_proxies`
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        self.client_addr
+    }
+
+    /// Protocol the client sent the request with, `http` or `https`, found
+    /// like `client_addr`
+    pub fn client_proto(&self) -> &str {
+        &self.client_proto
+    }
+
+    /// The URL the client requested, as `scheme://host/path?query`, for
+    /// handlers building absolute URLs. The scheme is `client_proto`, and
+    /// the host is the one the client sent, port included: the `host=` of
+    /// the `Forwarded` header when it comes from a trusted proxy, the
+    /// validated host of the request otherwise. `None` without a valid host.
+    /// It stays available after the request is handled, see also
+    /// `Request::full_url`
+    pub fn full_url(&self) -> Option<&str> {
+        self.full_url.as_deref()
+    }
+
+    /// The request line as the client sent it, before any middleware or path
+    /// rewrite changed the request, e.g. for audit logs
+    pub fn request_line(&self) -> &RequestLine {
+        &self.request_line
+    }
+
+    /// The `Content-Encoding` a middleware chose to compress the response
+    /// body with, if any
+    pub fn response_encoding(&self) -> Option<&str> {
+        self.response_encoding.as_deref()
+    }
+
+    /// Amount of request and response body bytes transferred for this request
+    pub fn body_metrics(&self) -> &BodyMetrics {
+        &self.body_metrics
+    }
+
+    pub(crate) fn set_body_metrics(&mut self, metrics: BodyMetrics) {
+        self.body_metrics = metrics;
+    }
+
+    /// Record the `Content-Encoding` used for the response body. Meant to be
+    /// called by middlewares compressing the response
+    pub fn set_response_encoding<S: Into<String>>(&mut self, encoding: S) {
+        self.response_encoding = Some(encoding.into());
+    }
+}
+
+/// Method, request target and HTTP version of a request, as received. The
+/// target is the raw one, not percent-decoded nor normalized, and displays as
+/// `GET /path?query HTTP/1.1`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestLine {
+    method: Method,
+    target: String,
+    version: Version,
+}
+
+impl RequestLine {
+    fn of(request: &Request) -> Self {
+        RequestLine {
+            method: request.method().clone(),
+            target: request.uri().to_string(),
+            version: request.version(),
+        }
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The target: a path and query for most requests, an absolute URL for
+    /// requests sent to a proxy, the authority of a `CONNECT` or `*`
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+}
+
+impl fmt::Display for RequestLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {:?}", self.method, self.target, self.version)
+    }
+}
+
+/// `scheme://authority/path?query` of `request`, the authority being
+/// `forwarded_host` when a trusted proxy gave one
+fn full_url(request: &Request, scheme: &str, forwarded_host: Option<String>) -> Option<String> {
+    let authority = match forwarded_host {
+        Some(host) => host,
+        None => {
+            let authority = match request.uri().authority() {
+                Some(authority) => authority.as_str(),
+                None => request.headers().get(http::header::HOST).and_then(|h| h.to_str().ok())?,
+            };
+            validate_host(authority)?;
+            authority.to_string()
+        }
+    };
+    let path = request.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Some(format!("{}://{}{}", scheme, authority, path))
+}
+
+fn unconditional_range(request: &Request) -> Option<String> {
+    if request.headers().contains_key(http::header::IF_RANGE) {
+        return None;
+    }
+
+    request.headers().get(http::header::RANGE).and_then(|h| h.to_str().ok()).map(|h| h.to_string())
 }
 
 #[cfg(feature = "operation")]
@@ -64,18 +64,45 @@
 //! - `json`  : Add the `Json` wrapper type to simplify working with json data
 //! - `form`  : Add the `Form` wrapper type to simplify working with urlencoded
 //!   data
+//! - `anyhow`: Allow handlers to return `anyhow::Error` as a responder
+//! - `decompression`: Add a middleware decoding compressed request bodies
+//! - `digest`: Add a middleware verifying request bodies against their
+//!   `Content-MD5` or `Digest` header
+//! - `chaos` : Add a middleware injecting latency and faults, for resilience
+//!   testing. Left out of `full`, never enable it in production builds
 //!
 //! *_More feature will be added in the future_*
 
 #[macro_use]
 extern crate log;
+// Lets the tests use the code generated by the macros, which refers to `saphir`
+#[cfg(all(test, feature = "macro"))]
+extern crate self as saphir;
 
+/// Logging of the requests handled
+pub mod access_log;
 ///
 pub mod body;
+/// Verification of request bodies against their digest
+#[cfg(feature = "digest")]
+pub mod body_digest;
+/// Cancellation of the work tied to an abandoned request
+pub mod cancellation;
+/// Injection of synthetic latency and faults, for resilience testing
+#[cfg(feature = "chaos")]
+pub mod chaos;
 ///
 pub mod controller;
 ///
 pub mod cookie;
+/// Decoding of compressed request bodies
+#[cfg(feature = "decompression")]
+pub mod decompression;
+/// Echo of the requests received, for development
+#[cfg(feature = "json")]
+pub mod echo;
+/// Redirection or rejection of plaintext requests
+pub mod enforce_https;
 /// Error definitions
 pub mod error;
 ///
@@ -83,6 +110,8 @@ pub mod extension;
 ///
 #[cfg(feature = "file")]
 pub mod file;
+/// Parsing of the `Forwarded` header
+pub mod forwarded;
 ///
 pub mod guard;
 /// Definition of types which can handle an http request
@@ -97,6 +126,8 @@ pub mod middleware;
 /// The async Multipart Form-Data representation
 #[cfg(feature = "multipart")]
 pub mod multipart;
+/// Limiting of the requests each client can send
+pub mod rate_limit;
 ///
 #[cfg(feature = "redirect")]
 pub mod redirect;
@@ -106,10 +137,21 @@ pub mod request;
 pub mod responder;
 /// The Http Response type
 pub mod response;
+/// In-process caching of responses
+pub mod response_cache;
 ///
 pub mod router;
 /// Server implementation and default runtime
 pub mod server;
+/// `Server-Timing` metrics of responses
+pub mod server_timing;
+/// Rendering of responses through a template engine
+#[cfg(feature = "json")]
+pub mod template;
+/// In-process client for testing a server
+pub mod testing;
+/// Scanning of request bodies while they are uploaded
+pub mod upload_scan;
 ///
 pub mod utils;
 ///
@@ -180,8 +222,14 @@ pub mod prelude {
     ///
     pub use crate::request::FromRequest;
     ///
+    pub use crate::request::Headers;
+    ///
     pub use crate::request::Request;
     ///
+    pub use crate::responder::Html;
+    ///
+    pub use crate::responder::NoContent;
+    ///
     pub use crate::responder::Responder;
     ///
     pub use crate::response::Builder;
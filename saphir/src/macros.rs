@@ -27,6 +27,11 @@
 //! We support even custom methods, and for convinience, `#[any(/your/path)]`
 //! will be treated as : _any method_ being accepted.
 //!
+//! The route can be given its own rate limit, counted for each client
+//! address: `#[post("/login", rate_limit = "5/min")]` answers with a `429 Too
+//! Many Requests` past 5 requests a minute. See `saphir::rate_limit` for the
+//! format of the limit.
+//!
 //! ## The `#[cookies] Attribute`
 //! This will ensure cookies are parsed in the request before the endpoint
 //! function is called, cookies can than be accessed with
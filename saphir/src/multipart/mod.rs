@@ -8,6 +8,7 @@ use parking_lot::Mutex;
 
 use crate::{
     body::{Body, Bytes},
+    error::ExtractError,
     http_context::HttpContext,
     multipart::parser::ParseFieldError,
     request::{FromRequest, Request},
@@ -18,10 +19,13 @@ use futures_util::{
     future::Future,
     task::{Context, Poll},
 };
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::macros::support::Pin;
 
 mod parser;
+pub mod spool;
+
+use spool::{RequestSpool, SpoolBudget, SpoolPermit};
 
 #[derive(Debug)]
 pub enum MultipartError {
@@ -34,6 +38,8 @@ pub enum MultipartError {
     Finished,
     Hyper(hyper::error::Error),
     Io(std::io::Error),
+    /// Saving the field would go past the `SpoolBudget` of the server
+    SpoolLimitReached,
     #[cfg(feature = "json")]
     Json(serde_json::error::Error),
     #[cfg(feature = "form")]
@@ -54,8 +60,23 @@ impl Responder for MultipartError {
             }
         };
 
-        debug!("{}Unable to parse multipart data: {:?}", op_id, &self);
-        builder.status(400)
+        if let MultipartError::SpoolLimitReached = self {
+            warn!("{}Unable to save a multipart field: too many uploads are being written to disk", op_id);
+            return builder.status(503);
+        }
+
+        let extract = match self {
+            MultipartError::MissingBoundary => ExtractError::MalformedContentType,
+            #[cfg(feature = "json")]
+            MultipartError::Json(e) => ExtractError::InvalidJson(e),
+            #[cfg(feature = "form")]
+            MultipartError::Form(e) => ExtractError::InvalidForm(e),
+            e => {
+                debug!("{}Unable to parse multipart data: {:?}", op_id, &e);
+                return builder.status(400);
+            }
+        };
+        extract.respond_with_builder(builder, ctx)
     }
 }
 
@@ -135,6 +156,7 @@ pub struct Field {
     content_transfer_encoding: Option<String>,
     boundary: String,
     stream: Option<FieldStream>,
+    spool: Option<RequestSpool>,
 }
 
 impl Field {
@@ -174,6 +196,19 @@ impl Field {
             .map(|b| if b.is_empty() { None } else { Some(b) })
     }
 
+    /// Turns the field into a stream of its data, read from the request body
+    /// as it arrives
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, MultipartError>> + Send {
+        futures::stream::unfold(Some(self), |field| async move {
+            let mut field = field?;
+            match field.next_chunk().await {
+                Ok(Some(chunk)) => Some((Ok(Bytes::from(chunk)), Some(field))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
     /// Loads the entire field into memory and returns it as raw bytes
     pub async fn as_raw(&mut self) -> Result<Vec<u8>, MultipartError> {
         self.read_all().await
@@ -215,28 +250,133 @@ impl Field {
     }
 
     /// Saves the field into a file on disk.
+    ///
+    /// When the server has a `SpoolBudget`, the field counts against it until
+    /// the request is done. Going past it fails with
+    /// `MultipartError::SpoolLimitReached`, and like any other failure,
+    /// removes the partial file.
     pub async fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, MultipartError> {
+        let (bytes_writen, permit) = self.write_to(path.as_ref()).await?;
+        if let (Some(spool), Some(permit)) = (self.spool.as_ref(), permit) {
+            spool.hold(permit);
+        }
+
+        Ok(bytes_writen)
+    }
+
+    /// Saves the field into a temporary file on disk, removed when the
+    /// returned `TemporaryFile` is dropped unless it is persisted.
+    ///
+    /// When the server has a `SpoolBudget`, the field counts against it until
+    /// the file is removed or persisted, failing like `save` otherwise.
+    pub async fn save_temporary<P: AsRef<Path>>(&mut self, path: P) -> Result<TemporaryFile, MultipartError> {
+        let (len, permit) = self.write_to(path.as_ref()).await?;
+        Ok(TemporaryFile {
+            path: Some(path.as_ref().to_path_buf()),
+            len,
+            permit,
+        })
+    }
+
+    /// Write the field to `path`, with the spool permit it counts against.
+    /// The partial file is removed on failure
+    async fn write_to(&mut self, path: &Path) -> Result<(usize, Option<SpoolPermit>), MultipartError> {
         use tokio::io::AsyncWriteExt;
+        let mut permit = match self.spool.as_ref() {
+            Some(spool) => Some(spool.acquire()?),
+            None => None,
+        };
         let mut file = tokio::fs::File::create(path).await.map_err(MultipartError::Io)?;
         let mut bytes_writen = 0;
-        while let Some(bytes) = self.next_chunk().await? {
-            file.write_all(bytes.as_slice()).await.map_err(MultipartError::Io)?;
-            bytes_writen += bytes.len();
+        let written = async {
+            while let Some(bytes) = self.next_chunk().await? {
+                if let Some(permit) = permit.as_mut() {
+                    permit.grow(bytes.len() as u64)?;
+                }
+                file.write_all(bytes.as_slice()).await.map_err(MultipartError::Io)?;
+                bytes_writen += bytes.len();
+            }
+            file.flush().await.map_err(MultipartError::Io)
         }
+        .await;
 
-        Ok(bytes_writen)
+        if let Err(e) = written {
+            drop(file);
+            let _ = tokio::fs::remove_file(path).await;
+            return Err(e);
+        }
+        Ok((bytes_writen, permit))
+    }
+
+    fn with_spool(mut self, spool: Option<RequestSpool>) -> Self {
+        self.spool = spool;
+        self
     }
 
     async fn read_all(&mut self) -> Result<Vec<u8>, MultipartError> {
-        parser::parse_field_data(self.stream.take().ok_or_else(|| MultipartError::AlreadyConsumed)?, self.boundary.as_str())
-            .await
-            .map(|mut b| {
-                if b[(b.len() - 2)..b.len()] == [0x0d, 0x0a] {
-                    b.truncate(b.len() - 2)
+        parser::parse_field_data(self.stream.take().ok_or_else(|| MultipartError::AlreadyConsumed)?, self.boundary.as_str()).await
+    }
+}
+
+/// A field saved to disk by `Field::save_temporary`, removed when dropped
+/// unless it is persisted. It counts against the `SpoolBudget` of the server
+/// until then.
+pub struct TemporaryFile {
+    path: Option<PathBuf>,
+    len: usize,
+    permit: Option<SpoolPermit>,
+}
+
+impl TemporaryFile {
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().expect("SAVED FILE: The path is only taken by remove, persist or drop")
+    }
+
+    /// Number of bytes saved
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keep the file on disk, out of the `SpoolBudget` of the server
+    pub fn persist(mut self) -> PathBuf {
+        self.permit = None;
+        self.path.take().expect("SAVED FILE: The path is only taken by remove, persist or drop")
+    }
+
+    /// Remove the file from disk, giving its share of the `SpoolBudget` back
+    pub async fn remove(mut self) -> Result<(), MultipartError> {
+        let path = self.path.take().expect("SAVED FILE: The path is only taken by remove, persist or drop");
+        tokio::fs::remove_file(path).await.map_err(MultipartError::Io)
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            // The permit is released once the file is gone
+            let permit = self.permit.take();
+            let remove = move || {
+                let _ = std::fs::remove_file(path);
+                drop(permit);
+            };
+            match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn_blocking(remove);
                 }
+                Err(_) => remove(),
+            }
+        }
+    }
+}
 
-                b
-            })
+impl Debug for TemporaryFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TemporaryFile").field("path", &self.path).field("len", &self.len).finish()
     }
 }
 
@@ -255,10 +395,15 @@ impl Debug for Field {
 type NextFieldFuture = Pin<Box<dyn Future<Output = Result<Option<Field>, MultipartError>> + Send + Sync>>;
 
 /// Struct used to parse a multipart body into fields
+///
+/// The body is parsed as it is received: a field is returned as soon as its
+/// headers are read, and its data is then read chunk by chunk, so only a
+/// small part of the request is held in memory at any time.
 pub struct Multipart {
     boundary: String,
     inner: DataStream,
     next_field_fut: Option<NextFieldFuture>,
+    spool: Option<RequestSpool>,
 }
 
 impl FromRequest for Multipart {
@@ -278,8 +423,15 @@ impl FromRequest for Multipart {
             .ok_or_else(|| MultipartError::MissingBoundary);
 
         let stream = req.body_mut().take().into_raw().map_err(MultipartError::Hyper);
+        let spool = req.extensions().get::<SpoolBudget>().cloned();
 
-        futures::future::ready(boundary.map(|boundary| Self::from_part(boundary, stream)))
+        futures::future::ready(boundary.map(|boundary| {
+            let multipart = Self::from_part(boundary, stream);
+            match spool {
+                Some(spool) => multipart.with_spool_budget(spool),
+                None => multipart,
+            }
+        }))
     }
 }
 
@@ -295,16 +447,25 @@ impl Multipart {
             boundary,
             inner: DataStream::new(stream),
             next_field_fut: None,
+            spool: None,
         }
     }
 
+    /// Count the fields saved to disk against `spool`, see `Field::save`.
+    /// It is set from the server configuration when extracted from a
+    /// request
+    pub fn with_spool_budget(mut self, spool: SpoolBudget) -> Self {
+        self.spool = Some(RequestSpool::new(spool));
+        self
+    }
+
     /// Parse the next field inside the body
     /// This will partially load the content, until the field "metadata" is
     /// parsed
     pub async fn next_field(&self) -> Result<Option<Field>, MultipartError> {
         if let Some(s) = self.inner.take() {
             match parser::parse_field(s, self.boundary.as_str()).await {
-                Ok(f) => Ok(Some(f)),
+                Ok(f) => Ok(Some(f.with_spool(self.spool.clone()))),
                 Err(MultipartError::Finished) => Ok(None),
                 Err(e) => Err(e),
             }
@@ -313,9 +474,9 @@ impl Multipart {
         }
     }
 
-    async fn next_field_owned(stream: FieldStream, boundary: String) -> Result<Option<Field>, MultipartError> {
+    async fn next_field_owned(stream: FieldStream, boundary: String, spool: Option<RequestSpool>) -> Result<Option<Field>, MultipartError> {
         match parser::parse_field(stream, boundary.as_str()).await {
-            Ok(f) => Ok(Some(f)),
+            Ok(f) => Ok(Some(f.with_spool(spool))),
             Err(MultipartError::Finished) => Ok(None),
             Err(e) => Err(e),
         }
@@ -337,7 +498,7 @@ impl Stream for Multipart {
             };
 
             let boundary = self.boundary.clone();
-            let mut current = Box::pin(Self::next_field_owned(stream, boundary));
+            let mut current = Box::pin(Self::next_field_owned(stream, boundary, self.spool.clone()));
             let res = current.as_mut().poll(cx);
             current_fut = Some(current);
             res
@@ -352,3 +513,174 @@ impl Stream for Multipart {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Body stream handing out its chunks one per poll, with a pending poll
+    /// between each of them
+    struct Trickle {
+        chunks: VecDeque<Bytes>,
+        ready: bool,
+    }
+
+    impl Trickle {
+        fn new(body: &'static [u8], chunk_size: usize) -> Self {
+            Trickle {
+                chunks: body.chunks(chunk_size).map(Bytes::from_static).collect(),
+                ready: false,
+            }
+        }
+    }
+
+    impl Stream for Trickle {
+        type Item = Result<Bytes, MultipartError>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            self.ready = false;
+            Poll::Ready(self.chunks.pop_front().map(Ok))
+        }
+    }
+
+    const BODY: &[u8] = b"\
+        --AaB03x\r\n\
+        content-disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Quarterly report\r\n\
+        --AaB03x\r\n\
+        content-disposition: form-data; name=\"report\"; filename=\"report.csv\"\r\n\
+        content-type: text/csv\r\n\
+        \r\n\
+        month,total\r\njanuary,12\r\nfebruary,--AaB03\r\n\
+        --AaB03x\r\n\
+        content-disposition: form-data; name=\"empty\"\r\n\
+        \r\n\
+        \r\n\
+        --AaB03x--\r\n";
+
+    async fn parse(chunk_size: usize) -> Vec<(String, Option<String>, Vec<u8>)> {
+        let multipart = Multipart::from_part("AaB03x".to_string(), Trickle::new(BODY, chunk_size));
+        let mut fields = Vec::new();
+        while let Some(field) = multipart.next_field().await.unwrap() {
+            let name = field.name().to_string();
+            let filename = field.filename().map(|f| f.to_string());
+            let data = field.into_stream().map_ok(|b| b.to_vec()).try_concat().await.unwrap();
+            fields.push((name, filename, data));
+        }
+        fields
+    }
+
+    #[tokio::test]
+    async fn fields_are_parsed_from_small_chunks() {
+        for chunk_size in &[1, 3, 7, 64, BODY.len()] {
+            let fields = parse(*chunk_size).await;
+            assert_eq!(fields.len(), 3, "chunk size {}", chunk_size);
+            assert_eq!(fields[0], ("title".to_string(), None, b"Quarterly report".to_vec()));
+            assert_eq!(
+                fields[1],
+                (
+                    "report".to_string(),
+                    Some("report.csv".to_string()),
+                    b"month,total\r\njanuary,12\r\nfebruary,--AaB03".to_vec()
+                )
+            );
+            assert_eq!(fields[2], ("empty".to_string(), None, Vec::new()));
+        }
+    }
+
+    #[tokio::test]
+    async fn text_field_from_small_chunks() {
+        let multipart = Multipart::from_part("AaB03x".to_string(), Trickle::new(BODY, 2));
+        let mut field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.as_text().await.unwrap(), "Quarterly report");
+    }
+
+    #[tokio::test]
+    async fn saturated_spool_budget_rejects_uploads() {
+        let dir = std::env::temp_dir().join(format!("saphir-spool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+
+        // The report field is bigger than the whole budget
+        let budget = SpoolBudget::new(1, 8);
+        let multipart = Multipart::from_part("AaB03x".to_string(), Trickle::new(BODY, 4)).with_spool_budget(budget.clone());
+        multipart.next_field().await.unwrap().unwrap().as_text().await.unwrap();
+        let mut report = multipart.next_field().await.unwrap().unwrap();
+        match report.save(&path).await {
+            Err(MultipartError::SpoolLimitReached) => {}
+            other => panic!("expected the spool limit to be reached, got {:?}", other),
+        }
+        assert!(!path.exists());
+        assert_eq!(budget.files(), 0);
+        assert_eq!(budget.bytes(), 0);
+
+        // Every file slot is taken by another upload
+        let budget = SpoolBudget::new(1, 1024);
+        let held = budget.acquire().unwrap();
+        let multipart = Multipart::from_part("AaB03x".to_string(), Trickle::new(BODY, 4)).with_spool_budget(budget.clone());
+        let mut title = multipart.next_field().await.unwrap().unwrap();
+        let err = title.save(&path).await.unwrap_err();
+        assert!(matches!(err, MultipartError::SpoolLimitReached));
+        assert!(!path.exists());
+        let ctx = HttpContext::new(Request::new(http::Request::new(Body::empty()), None), crate::router::Router::builder().build());
+        assert_eq!(err.respond_with_builder(Builder::new(), &ctx).build().unwrap().status(), 503);
+
+        drop(held);
+        assert_eq!(title.save(&path).await.unwrap(), b"Quarterly report".len());
+        assert_eq!(std::fs::read(&path).unwrap(), b"Quarterly report");
+        // The saved file is kept, and counted until the request is done
+        assert_eq!((budget.files(), budget.bytes()), (1, b"Quarterly report".len() as u64));
+        drop(title);
+        drop(multipart);
+        assert!(path.is_file());
+        assert_eq!((budget.files(), budget.bytes()), (0, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn temporary_files_are_removed_unless_persisted() {
+        let dir = std::env::temp_dir().join(format!("saphir-saved-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let budget = SpoolBudget::new(2, 1024);
+        let multipart = Multipart::from_part("AaB03x".to_string(), Trickle::new(BODY, 4)).with_spool_budget(budget.clone());
+
+        let title = multipart
+            .next_field()
+            .await
+            .unwrap()
+            .unwrap()
+            .save_temporary(dir.join("title.txt"))
+            .await
+            .unwrap();
+        let report = multipart
+            .next_field()
+            .await
+            .unwrap()
+            .unwrap()
+            .save_temporary(dir.join("report.csv"))
+            .await
+            .unwrap();
+        assert_eq!(budget.files(), 2);
+
+        let kept = report.persist();
+        assert!(kept.is_file());
+        assert_eq!((budget.files(), budget.bytes()), (1, b"Quarterly report".len() as u64));
+
+        let title_path = title.path().to_path_buf();
+        drop(title);
+        while budget.files() > 0 {
+            tokio::time::delay_for(std::time::Duration::from_millis(1)).await;
+        }
+        assert!(!title_path.exists());
+        assert_eq!(budget.bytes(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
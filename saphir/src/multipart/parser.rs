@@ -6,8 +6,6 @@ use nom::{
 
 use crate::multipart::{Field, FieldStream, MultipartError, ParseStream};
 
-const BASE_BUFFER_SIZE: usize = 2048;
-
 #[derive(Debug)]
 pub enum ParseFieldError {
     Finished,
@@ -158,30 +156,21 @@ fn field<'a>(input: &'a [u8], bound: &'a str) -> Result<(&'a [u8], FieldHeaders<
     }
 }
 
-async fn buf_data(parse_ctx: &mut ParseStream, additional_size: usize) -> Result<bool, MultipartError> {
-    if parse_ctx.exhausted {
-        return if parse_ctx.buf.is_empty() { Err(MultipartError::Finished) } else { Ok(false) };
-    }
-
-    loop {
-        match parse_ctx.stream.next().await.transpose()? {
-            None => {
-                if !parse_ctx.buf.is_empty() {
-                    parse_ctx.exhausted = true;
-                    break Ok(false);
-                } else {
-                    break Err(MultipartError::Finished);
-                }
-            }
-            Some(b) => {
-                parse_ctx.buf.extend_from_slice(b.as_ref());
-            }
+/// Reads the body until at least `min_len` bytes are buffered, returns false
+/// if the body ended before that
+async fn buf_data(parse_ctx: &mut ParseStream, min_len: usize) -> Result<bool, MultipartError> {
+    while parse_ctx.buf.len() < min_len {
+        if parse_ctx.exhausted {
+            return Ok(false);
         }
 
-        if parse_ctx.buf.len() >= BASE_BUFFER_SIZE + additional_size {
-            break Ok(true);
+        match parse_ctx.stream.next().await.transpose()? {
+            None => parse_ctx.exhausted = true,
+            Some(b) => parse_ctx.buf.extend_from_slice(b.as_ref()),
         }
     }
+
+    Ok(true)
 }
 
 async fn drain_current(stream: &mut FieldStream, boundary: &str) -> Result<(), MultipartError> {
@@ -194,22 +183,15 @@ pub async fn parse_field(mut stream: FieldStream, boundary: &str) -> Result<Fiel
     drain_current(&mut stream, boundary).await?;
 
     let parse_ctx = stream.stream();
-    let mut additional_size = 0usize;
 
     loop {
-        if buf_data(parse_ctx, additional_size).await? {
-            additional_size = 0;
-        }
-
-        let buf = &mut parse_ctx.buf;
-
-        match field(buf.as_slice(), boundary) {
+        match field(parse_ctx.buf.as_slice(), boundary) {
             Ok((i, f)) => {
                 let name = f.content_disposition_name.to_string();
                 let filename = f.content_disposition_filename.map(|s| s.to_string());
                 let content_type = f.content_type.unwrap_or_else(|| mime::TEXT_PLAIN.clone());
                 let content_transfer_encoding = f.content_transfer_encoding.map(|s| s.to_string());
-                *buf = i.to_vec();
+                parse_ctx.buf = i.to_vec();
                 return Ok(Field {
                     name,
                     filename,
@@ -217,85 +199,63 @@ pub async fn parse_field(mut stream: FieldStream, boundary: &str) -> Result<Fiel
                     content_transfer_encoding,
                     boundary: boundary.to_string(),
                     stream: Some(stream),
+                    spool: None,
                 });
             }
-            Err(ParseFieldError::MissingData(size)) if !parse_ctx.exhausted => {
-                additional_size += size;
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
+            Err(ParseFieldError::MissingData(_)) if !parse_ctx.exhausted => {}
+            Err(ParseFieldError::MissingData(_)) if parse_ctx.buf.is_empty() => return Err(MultipartError::Finished),
+            Err(e) => return Err(e.into()),
         }
+
+        // The headers are split across reads, wait for the next chunk of the body
+        let min_len = parse_ctx.buf.len() + 1;
+        buf_data(parse_ctx, min_len).await?;
     }
 }
 
+/// Returns the field data available so far, without its delimiter, or an
+/// empty chunk once the field is completely read
 pub async fn parse_next_field_chunk(stream: &mut FieldStream, boundary: &str) -> Result<Vec<u8>, MultipartError> {
-    let data;
     let parse_ctx = stream.stream();
     let mut boundary = boundary.to_string();
 
     boundary.insert_str(0, "--");
 
-    let boundary_len = boundary.len();
+    // A delimiter and its leading line ending can be split across two reads, so
+    // this much data is kept back until we know it is not part of a delimiter
+    let hold_back = boundary.len() + 2;
 
-    buf_data(parse_ctx, 0).await?;
-
-    let buf = &mut parse_ctx.buf;
-    let res: IResult<&[u8], &[u8]> = take_until!(buf.as_slice(), boundary.as_str());
-    match res {
-        Ok((input, taken)) => {
-            data = taken.to_vec();
-            *buf = input.to_vec();
-        }
-        Err(_) => {
-            if parse_ctx.exhausted {
-                data = buf.drain(0..buf.len()).collect();
-            } else {
-                data = buf[0..(buf.len() - boundary_len)].to_vec();
-                *buf = buf[(buf.len() - boundary_len - 1)..buf.len()].to_vec();
+    loop {
+        let buf = &mut parse_ctx.buf;
+        let res: IResult<&[u8], &[u8]> = take_until!(buf.as_slice(), boundary.as_str());
+        match res {
+            Ok((_, taken)) => {
+                let mut data: Vec<u8> = buf.drain(0..taken.len()).collect();
+                if data.ends_with(b"\r\n") {
+                    data.truncate(data.len() - 2);
+                }
+                return Ok(data);
             }
+            Err(_) if parse_ctx.exhausted => return Ok(buf.drain(0..buf.len()).collect()),
+            Err(_) if buf.len() > hold_back => return Ok(buf.drain(0..(buf.len() - hold_back)).collect()),
+            Err(_) => {}
         }
-    }
 
-    Ok(data)
+        let min_len = parse_ctx.buf.len() + 1;
+        buf_data(parse_ctx, min_len).await?;
+    }
 }
 
 pub async fn parse_field_data(mut stream: FieldStream, boundary: &str) -> Result<Vec<u8>, MultipartError> {
-    let parse_ctx = stream.stream();
-    let mut additional_size = 0usize;
-
     let mut data = Vec::new();
 
-    let mut boundary = boundary.to_string();
-
-    boundary.insert_str(0, "--");
-
-    let boundary_len = boundary.len();
-
     loop {
-        if buf_data(parse_ctx, additional_size).await? {
-            additional_size = 0;
+        let chunk = parse_next_field_chunk(&mut stream, boundary).await?;
+        if chunk.is_empty() {
+            return Ok(data);
         }
 
-        let buf = &mut parse_ctx.buf;
-        let res: IResult<&[u8], &[u8]> = take_until!(buf.as_slice(), boundary.as_str());
-        match res {
-            Ok((input, taken)) => {
-                data.extend_from_slice(taken);
-                *buf = input.to_vec();
-                return Ok(data);
-            }
-            Err(_) => {
-                if parse_ctx.exhausted {
-                    data.extend_from_slice(buf.as_slice());
-                    return Ok(data);
-                } else {
-                    data.extend_from_slice(&buf[0..(buf.len() - boundary_len)]);
-                    *buf = buf[(buf.len() - boundary_len - 1)..buf.len()].to_vec();
-                    additional_size += 1024
-                }
-            }
-        }
+        data.extend_from_slice(chunk.as_slice());
     }
 }
 
@@ -0,0 +1,129 @@
+//! Server-wide limit on the multipart uploads being written to disk.
+
+use crate::multipart::MultipartError;
+use parking_lot::Mutex;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+struct SpoolBudgetInner {
+    max_files: usize,
+    max_bytes: u64,
+    files: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+/// Number and total size of the multipart fields that can be written to disk
+/// at once by `Field::save`, shared by every request of a server, see
+/// `ListenerBuilder::multipart_spool_limit`.
+///
+/// A field counts against the budget from the moment it starts being saved
+/// until its request is done, or for `Field::save_temporary`, until its
+/// `TemporaryFile` is removed or persisted: a field saved when `max_files`
+/// others are, or going past `max_bytes` along with them, fails with
+/// `MultipartError::SpoolLimitReached`, which responds with a
+/// `503 Service Unavailable`, and its partial file is removed.
+#[derive(Clone)]
+pub struct SpoolBudget {
+    inner: Arc<SpoolBudgetInner>,
+}
+
+impl SpoolBudget {
+    pub fn new(max_files: usize, max_bytes: u64) -> Self {
+        SpoolBudget {
+            inner: Arc::new(SpoolBudgetInner {
+                max_files,
+                max_bytes,
+                files: AtomicUsize::new(0),
+                bytes: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.inner.max_files
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.inner.max_bytes
+    }
+
+    /// Number of fields saved or being saved
+    pub fn files(&self) -> usize {
+        self.inner.files.load(Ordering::Acquire)
+    }
+
+    /// Bytes written by the fields saved or being saved
+    pub fn bytes(&self) -> u64 {
+        self.inner.bytes.load(Ordering::Acquire)
+    }
+
+    /// Count a field being saved, until the permit is dropped
+    pub(crate) fn acquire(&self) -> Result<SpoolPermit, MultipartError> {
+        let max_files = self.inner.max_files;
+        self.inner
+            .files
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |files| Some(files + 1).filter(|files| *files <= max_files))
+            .map_err(|_| MultipartError::SpoolLimitReached)?;
+        Ok(SpoolPermit {
+            budget: self.clone(),
+            bytes: 0,
+        })
+    }
+}
+
+/// The `SpoolBudget` of the fields of a request, holding the permits of the
+/// fields saved by `Field::save` until the request and its fields are dropped
+#[derive(Clone)]
+pub(crate) struct RequestSpool {
+    budget: SpoolBudget,
+    saved: Arc<Mutex<Vec<SpoolPermit>>>,
+}
+
+impl RequestSpool {
+    pub(crate) fn new(budget: SpoolBudget) -> Self {
+        RequestSpool {
+            budget,
+            saved: Default::default(),
+        }
+    }
+
+    pub(crate) fn acquire(&self) -> Result<SpoolPermit, MultipartError> {
+        self.budget.acquire()
+    }
+
+    /// Keep counting a saved field until the request is done
+    pub(crate) fn hold(&self, permit: SpoolPermit) {
+        self.saved.lock().push(permit);
+    }
+}
+
+/// A field saved or being saved, released when dropped
+pub(crate) struct SpoolPermit {
+    budget: SpoolBudget,
+    bytes: u64,
+}
+
+impl SpoolPermit {
+    /// Count `bytes` more written by the field
+    pub(crate) fn grow(&mut self, bytes: u64) -> Result<(), MultipartError> {
+        let max_bytes = self.budget.inner.max_bytes;
+        self.budget
+            .inner
+            .bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|used| *used <= max_bytes)
+            })
+            .map_err(|_| MultipartError::SpoolLimitReached)?;
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+impl Drop for SpoolPermit {
+    fn drop(&mut self) {
+        self.budget.inner.bytes.fetch_sub(self.bytes, Ordering::AcqRel);
+        self.budget.inner.files.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,335 @@
+//! Limiting of the requests each client can send.
+//!
+//! A `RateLimit` counts the requests of each client address over a fixed
+//! window. Applied with the `RateLimitMiddleware` it sets the default limit of
+//! the server, and applied as a guard it sets the limit of a single route, so
+//! sensitive routes can be stricter than the default. The controller macro
+//! applies one from the `rate_limit` parameter of the route attribute:
+//!
+//! ```rust
+//! # use saphir::prelude::*;
+//! struct AuthController;
+//!
+//! #[controller]
+//! impl AuthController {
+//!     #[post("/login", rate_limit = "5/min")]
+//!     async fn login(&self) -> u16 {
+//!         200
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! A limit the macro can't parse fails the build:
+//!
+//! ```compile_fail
+//! # use saphir::prelude::*;
+//! struct AuthController;
+//!
+//! #[controller]
+//! impl AuthController {
+//!     #[post("/login", rate_limit = "5/mn")]
+//!     async fn login(&self) -> u16 {
+//!         200
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use crate::{
+    body::Body,
+    error::SaphirError,
+    guard::Guard,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    request::Request,
+    responder::Responder,
+    response::Builder,
+};
+use futures::{future::BoxFuture, FutureExt};
+use http::StatusCode;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// Past this many clients, the ones whose window ended are forgotten
+const PRUNE_THRESHOLD: usize = 1024;
+const DEFAULT_MAX_CLIENTS: usize = 65_536;
+
+/// A limit of `requests` per `window` for each client address. Requests
+/// without a known address are never limited.
+///
+/// At most `max_clients` clients are counted: past it, the clients whose
+/// window started first are forgotten, and start a new window on their next
+/// request.
+pub struct RateLimit {
+    requests: u32,
+    window: Duration,
+    max_clients: usize,
+    clients: Mutex<HashMap<IpAddr, Window>>,
+}
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, window: Duration) -> Self {
+        RateLimit {
+            requests,
+            window,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            clients: Default::default(),
+        }
+    }
+
+    /// Count at most `max` clients at once, 65536 by default
+    pub fn max_clients(mut self, max: usize) -> Self {
+        self.max_clients = max.max(1);
+        self
+    }
+
+    pub fn requests(&self) -> u32 {
+        self.requests
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Count a request from `client`, or give the time left until it can send
+    /// one again when it is over the limit
+    pub fn check(&self, client: IpAddr) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock();
+        if clients.len() >= PRUNE_THRESHOLD.min(self.max_clients) && !clients.contains_key(&client) {
+            let window = self.window;
+            clients.retain(|_, w| now.duration_since(w.started) < window);
+            if clients.len() >= self.max_clients {
+                // Forget a tenth of the clients at once, so a flood of new
+                // clients doesn't scan the map on each request
+                let mut started: Vec<_> = clients.iter().map(|(client, w)| (w.started, *client)).collect();
+                let forgotten = (self.max_clients / 10).max(1).min(started.len());
+                started.select_nth_unstable(forgotten - 1);
+                for (_, client) in &started[..forgotten] {
+                    clients.remove(client);
+                }
+            }
+        }
+
+        let window = clients.entry(client).or_insert(Window { started: now, count: 0 });
+        if now.duration_since(window.started) >= self.window {
+            *window = Window { started: now, count: 0 };
+        }
+
+        if window.count >= self.requests {
+            return Err(RateLimited {
+                retry_after: self.window - now.duration_since(window.started),
+            });
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = SaphirError;
+
+    /// Parse a limit like `5/min`: a number of requests, and a window of
+    /// `s`, `sec`, `min`, `h`, `hour` or `day`, which can be prefixed by a
+    /// count as in `100/10min`. The controller macro checks its `rate_limit`
+    /// parameter against the same format, at compile time
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SaphirError::Other(format!("Invalid rate limit `{}`, expected something like `5/min`", s));
+        let mut parts = s.trim().splitn(2, '/');
+        let requests = parts.next().and_then(|r| r.trim().parse::<u32>().ok()).ok_or_else(invalid)?;
+        let window = parts.next().and_then(parse_window).ok_or_else(invalid)?;
+        Ok(RateLimit::new(requests, window))
+    }
+}
+
+fn parse_window(window: &str) -> Option<Duration> {
+    let window = window.trim();
+    let unit_start = window.find(|c: char| !c.is_ascii_digit()).unwrap_or(window.len());
+    let count = match &window[..unit_start] {
+        "" => 1,
+        count => count.parse::<u64>().ok().filter(|c| *c > 0)?,
+    };
+    let unit = match &window[unit_start..] {
+        "s" | "sec" | "second" => 1,
+        "m" | "min" | "minute" => 60,
+        "h" | "hour" => 3_600,
+        "d" | "day" => 86_400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(count.checked_mul(unit)?))
+}
+
+/// Address the limits are counted for: the client address found by the
+/// context, see `HttpContext::client_addr`, or the peer address
+fn client_of(req: &Request<Body>) -> Option<IpAddr> {
+    req.client_addr().or_else(|| req.peer_addr().map(|a| a.ip()))
+}
+
+impl Guard for RateLimit {
+    type Future = futures::future::Ready<Result<Request<Body>, Self::Responder>>;
+    type Responder = RateLimited;
+
+    fn validate(&'static self, req: Request<Body>) -> Self::Future {
+        let checked = match client_of(&req) {
+            Some(client) => self.check(client),
+            None => Ok(()),
+        };
+        futures::future::ready(checked.map(|_| req))
+    }
+}
+
+/// Response for a client over its limit: a `429 Too Many Requests` with a
+/// `Retry-After` header
+#[derive(Debug)]
+pub struct RateLimited {
+    retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Time left until the client can send a request again
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+impl Responder for RateLimited {
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        // Rounded up, so a client waiting for it is never early
+        let mut seconds = self.retry_after.as_secs();
+        if self.retry_after.subsec_nanos() > 0 || seconds == 0 {
+            seconds += 1;
+        }
+        builder.status(StatusCode::TOO_MANY_REQUESTS).header(http::header::RETRY_AFTER, seconds)
+    }
+}
+
+/// Middleware applying a `RateLimit` to every request it sees, the default
+/// limit of the server. Routes can have their own limit on top of it.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::rate_limit::RateLimitMiddleware;
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(RateLimitMiddleware::new("600/min".parse().unwrap()), vec!["/**"], None))
+///     .build();
+/// ```
+pub struct RateLimitMiddleware {
+    limit: RateLimit,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimitMiddleware { limit }
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        if let Some(client) = ctx.client_addr() {
+            if let Err(limited) = self.limit.check(client) {
+                let res = limited.respond_with_builder(Builder::new(), &ctx).build()?;
+                ctx.after(res);
+                return Ok(ctx);
+            }
+        }
+        chain.next(ctx).await
+    }
+}
+
+impl Middleware for RateLimitMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "macro")]
+    use crate::prelude::*;
+    use crate::router::Router;
+
+    #[test]
+    fn limits_are_parsed() {
+        let limit: RateLimit = "5/min".parse().unwrap();
+        assert_eq!((limit.requests(), limit.window()), (5, Duration::from_secs(60)));
+        let limit: RateLimit = "100 / 10s".parse().unwrap();
+        assert_eq!((limit.requests(), limit.window()), (100, Duration::from_secs(10)));
+        assert!("5".parse::<RateLimit>().is_err());
+        assert!("5/fortnight".parse::<RateLimit>().is_err());
+        assert!("5/0min".parse::<RateLimit>().is_err());
+    }
+
+    #[cfg(feature = "macro")]
+    struct AuthController;
+
+    #[cfg(feature = "macro")]
+    #[controller(name = "auth")]
+    impl AuthController {
+        #[post("/login", rate_limit = "5/min")]
+        async fn login(&self) -> u16 {
+            200
+        }
+
+        #[get("/profile")]
+        async fn profile(&self) -> u16 {
+            200
+        }
+    }
+
+    #[test]
+    fn clients_past_the_maximum_are_forgotten() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).max_clients(10);
+        let client = |i: u8| IpAddr::from([10, 0, 0, i]);
+        assert!(limit.check(client(0)).is_ok());
+        std::thread::sleep(Duration::from_millis(2));
+        for i in 1..10 {
+            assert!(limit.check(client(i)).is_ok());
+        }
+        assert!(limit.check(client(0)).is_err());
+
+        // The first client is forgotten to make room for a new one
+        assert!(limit.check(client(10)).is_ok());
+        assert!(limit.clients.lock().len() <= 10);
+        assert!(limit.check(client(0)).is_ok());
+        assert!(limit.check(client(9)).is_err());
+    }
+
+    #[cfg(feature = "macro")]
+    #[tokio::test]
+    async fn route_limit_is_stricter_than_the_others() {
+        let router = Router::builder().controller(AuthController).build();
+        let call = |method: Method, path: &str, peer: &str| {
+            let req = http::Request::builder().method(method).uri(path).body(Body::empty()).unwrap();
+            let ctx = HttpContext::new(Request::new(req, Some(peer.parse().unwrap())), router.clone());
+            let router = router.clone();
+            async move {
+                let mut ctx = router.handle(ctx).await.unwrap();
+                let res = ctx.state.take_response().unwrap();
+                (res.status().as_u16(), res.headers().get(http::header::RETRY_AFTER).cloned())
+            }
+        };
+
+        for _ in 0..5 {
+            assert_eq!(call(Method::POST, "/auth/login", "10.0.0.1:5000").await, (200, None));
+        }
+        let (status, retry_after) = call(Method::POST, "/auth/login", "10.0.0.1:5001").await;
+        assert_eq!(status, 429);
+        assert_eq!(retry_after.unwrap(), "60");
+
+        // Other clients and other routes are counted apart
+        assert_eq!(call(Method::POST, "/auth/login", "10.0.0.2:5000").await.0, 200);
+        for _ in 0..10 {
+            assert_eq!(call(Method::GET, "/auth/profile", "10.0.0.1:5000").await.0, 200);
+        }
+    }
+}
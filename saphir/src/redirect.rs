@@ -295,6 +295,23 @@ impl Redirect {
         }
     }
 
+    /// `307 Temporary Redirect`: unlike `found`, clients must repeat the
+    /// request with the same method and body at the new location, which makes
+    /// it the redirect to use for a `POST` endpoint. The response carries no
+    /// body.
+    #[inline]
+    pub fn temporary_preserve() -> Builder {
+        Self::temporary_redirect()
+    }
+
+    /// `308 Permanent Redirect`: unlike `moved_permanently`, clients must
+    /// repeat the request with the same method and body at the new location.
+    /// The response carries no body.
+    #[inline]
+    pub fn permanent_preserve() -> Builder {
+        Self::permanent_redirect()
+    }
+
     #[inline]
     pub fn not_modified() -> Builder {
         Builder {
@@ -393,3 +410,71 @@ mod post_redirect {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, error::SaphirError, request::Request, router::Router};
+    use http::Method;
+
+    async fn old_endpoint(_req: Request<Body>) -> Result<Redirect, SaphirError> {
+        Redirect::permanent_preserve()
+            .location("/new")
+            .build()
+            .map_err(|e| SaphirError::Other(format!("{:?}", e)))
+    }
+
+    async fn new_endpoint(mut req: Request<Body>) -> Result<String, SaphirError> {
+        let body = req.body_mut().take_as::<String>().await?;
+        Ok(format!("{} {}", req.method(), body))
+    }
+
+    async fn send(router: &Router, method: Method, uri: &str, body: &'static str) -> crate::response::Response {
+        let req = Request::new(
+            http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from_raw(hyper::Body::from(body)))
+                .unwrap(),
+            None,
+        );
+        let mut ctx = router.clone().handle(HttpContext::new(req, router.clone())).await.unwrap();
+        ctx.state.take_response().unwrap()
+    }
+
+    #[tokio::test]
+    async fn post_follows_a_permanent_preserve_redirect_as_a_post() {
+        let router = Router::builder()
+            .route("/old", Method::POST, old_endpoint)
+            .route("/new", Method::POST, new_endpoint)
+            .build();
+
+        let res = send(&router, Method::POST, "/old", "amount=10").await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert!(res.headers().get(http::header::CONTENT_TYPE).is_none());
+        let location = res.headers().get(http::header::LOCATION).unwrap().to_str().unwrap().to_string();
+        assert_eq!(location, "/new");
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert!(body.is_empty());
+
+        // A client repeats the same method and body on a 308
+        let res = send(&router, Method::POST, &location, "amount=10").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, "POST amount=10");
+    }
+
+    #[test]
+    fn preserving_redirects_use_307_and_308() {
+        let temporary = Redirect::temporary_preserve().location("/new").build().unwrap();
+        assert_eq!(*temporary.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(temporary.location(), Some("/new"));
+        assert_eq!(
+            *Redirect::permanent_preserve().location("/new").build().unwrap().status(),
+            StatusCode::PERMANENT_REDIRECT
+        );
+
+        assert!(Redirect::permanent_preserve().build().is_err());
+        assert!(Redirect::temporary_preserve().location("/new").choices("body").build().is_err());
+    }
+}
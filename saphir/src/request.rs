@@ -1,16 +1,20 @@
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     ops::{Deref, DerefMut},
+    time::Duration,
 };
 
 use futures_util::future::Future;
-use http::Request as RawRequest;
+use http::{HeaderMap, Request as RawRequest};
 use hyper::body::Bytes;
 
 use crate::{
     body::{Body, FromBytes},
+    cancellation::CancellationToken,
     error::SaphirError,
+    forwarded::{ClientAddr, FullUrl},
+    server_timing::ServerTimings,
 };
 
 #[cfg(feature = "operation")]
@@ -27,6 +31,11 @@ pub trait FromRequest: Sized {
     fn from_request(req: &mut Request) -> Self::Fut;
 }
 
+/// Extension marking the requests received over a TLS connection, see
+/// `Request::is_tls`
+#[derive(Clone, Copy, Debug)]
+pub struct TlsConnection;
+
 /// Struct that wraps a hyper request + some magic
 pub struct Request<T = Body<Bytes>> {
     #[doc(hidden)]
@@ -38,6 +47,10 @@ pub struct Request<T = Body<Bytes>> {
     #[doc(hidden)]
     peer_addr: Option<SocketAddr>,
     #[doc(hidden)]
+    cancellation_token: CancellationToken,
+    #[doc(hidden)]
+    server_timings: ServerTimings,
+    #[doc(hidden)]
     #[cfg(feature = "operation")]
     operation_id: OperationId,
 }
@@ -50,6 +63,8 @@ impl<T> Request<T> {
             captures: Default::default(),
             cookies: Default::default(),
             peer_addr,
+            cancellation_token: CancellationToken::new(),
+            server_timings: ServerTimings::default(),
             #[cfg(feature = "operation")]
             operation_id: OperationId::default(),
         }
@@ -62,6 +77,50 @@ impl<T> Request<T> {
         self.peer_addr.as_ref()
     }
 
+    /// Return the memory budget of the request, when the listener has a
+    /// `request_memory_limit`
+    #[inline]
+    pub fn memory_budget(&self) -> Option<&crate::body::MemoryBudget> {
+        self.extensions().get()
+    }
+
+    /// Return the address of the client, the same one as
+    /// `HttpContext::client_addr`. It is only known once the request went
+    /// through its context
+    #[inline]
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        self.inner.extensions().get::<ClientAddr>().map(|a| a.0)
+    }
+
+    /// Return the URL the client requested, the same one as
+    /// `HttpContext::full_url`. It is only known once the request went
+    /// through its context
+    #[inline]
+    pub fn full_url(&self) -> Option<&str> {
+        self.inner.extensions().get::<FullUrl>().map(|u| u.0.as_str())
+    }
+
+    /// Return the token cancelled when the request is abandoned, see
+    /// `CancellationToken`
+    #[inline]
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
+    /// Record a `Server-Timing` metric for the response, see
+    /// `server_timing::ServerTimingMiddleware`
+    #[inline]
+    pub fn server_timing<N: Into<String>>(&self, name: N, duration: Duration) {
+        self.server_timings.record(name, duration);
+    }
+
+    /// The `Server-Timing` metrics recorded for the request, shared with its
+    /// `HttpContext`
+    #[inline]
+    pub fn server_timings(&self) -> &ServerTimings {
+        &self.server_timings
+    }
+
     /// Return the OperationId of the request
     #[inline]
     #[cfg(feature = "operation")]
@@ -76,6 +135,40 @@ impl<T> Request<T> {
         &mut self.operation_id
     }
 
+    /// Whether the request was received over a TLS connection accepted by the
+    /// server itself. Requests forwarded by a TLS-terminating proxy arrive in
+    /// plaintext, see `enforce_https::EnforceHttpsMiddleware` to trust the proxy's
+    /// `X-Forwarded-Proto`.
+    #[inline]
+    pub fn is_tls(&self) -> bool {
+        self.inner.extensions().get::<TlsConnection>().is_some()
+    }
+
+    /// Return the host the request is addressed to, without its port. It is
+    /// taken from the request target when it has an authority (HTTP/2
+    /// `:authority` or an absolute URI), and from the `Host` header otherwise.
+    ///
+    /// Returns `None` if the host is missing or malformed.
+    ///
+    /// ```rust
+    /// # use saphir::prelude::*;
+    /// # use hyper::Request as RawRequest;
+    /// let req = Request::new(RawRequest::builder().uri("/").header("Host", "www.example.com:8080").body(()).unwrap(), None);
+    /// assert_eq!(req.host(), Some("www.example.com"));
+    /// ```
+    #[inline]
+    pub fn host(&self) -> Option<&str> {
+        match self.inner.uri().authority() {
+            Some(authority) => validate_host(authority.as_str()),
+            None => self
+                .inner
+                .headers()
+                .get(http::header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .and_then(validate_host),
+        }
+    }
+
     ///
     #[inline]
     pub fn peer_addr_mut(&mut self) -> Option<&mut SocketAddr> {
@@ -167,6 +260,8 @@ impl<T> Request<T> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         } = self;
@@ -176,6 +271,8 @@ impl<T> Request<T> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         }
@@ -201,6 +298,8 @@ impl<T> Request<T> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         } = self;
@@ -213,6 +312,8 @@ impl<T> Request<T> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         }
@@ -270,6 +371,8 @@ impl<T: FromBytes + Unpin + 'static> Request<Body<T>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         } = self;
@@ -284,10 +387,36 @@ impl<T: FromBytes + Unpin + 'static> Request<Body<T>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         })
     }
+
+    /// Get the trailers sent by the client after the body, e.g. a digest
+    /// computed while uploading. Only meaningful once the body was fully read
+    /// as a stream, loading the body discards them.
+    ///
+    /// Trailers are only received over HTTP/2, HTTP/1 connections drop them.
+    ///
+    /// ```rust
+    /// # use saphir::prelude::*;
+    /// use hyper::body::HttpBody;
+    ///
+    /// async fn upload(mut req: Request<Body>) -> Result<u16, SaphirError> {
+    ///     let mut received = Vec::new();
+    ///     while let Some(chunk) = req.body_mut().data().await {
+    ///         received.extend_from_slice(&chunk?);
+    ///     }
+    ///     let digest = req.trailers().await?.and_then(|t| t.get("x-checksum").cloned());
+    ///     Ok(if digest.is_some() { 201 } else { 400 })
+    /// }
+    /// ```
+    pub async fn trailers(&mut self) -> Result<Option<HeaderMap>, SaphirError> {
+        use hyper::body::HttpBody;
+        self.body_mut().trailers().await
+    }
 }
 
 impl<T, E> Request<Result<T, E>> {
@@ -308,6 +437,8 @@ impl<T, E> Request<Result<T, E>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         } = self;
@@ -318,6 +449,8 @@ impl<T, E> Request<Result<T, E>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         })
@@ -341,6 +474,8 @@ impl<T> Request<Option<T>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         } = self;
@@ -351,6 +486,8 @@ impl<T> Request<Option<T>> {
             captures,
             cookies,
             peer_addr,
+            cancellation_token,
+            server_timings,
             #[cfg(feature = "operation")]
             operation_id,
         })
@@ -393,6 +530,43 @@ mod form {
     }
 }
 
+/// Validate a `host[:port]` authority, returning its host
+pub(crate) fn validate_host(authority: &str) -> Option<&str> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']')?;
+        (&authority[..=end], &authority[(end + 1)..])
+    } else {
+        match authority.rfind(':') {
+            Some(i) => (&authority[..i], &authority[i..]),
+            None => (authority, ""),
+        }
+    };
+
+    if !port.is_empty() {
+        let port = port.strip_prefix(':')?;
+        if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) || port.parse::<u16>().is_err() {
+            return None;
+        }
+    }
+
+    let valid = if host.starts_with('[') {
+        host[1..(host.len() - 1)].parse::<std::net::Ipv6Addr>().is_ok()
+    } else {
+        let name = host.strip_suffix('.').unwrap_or(host);
+        !name.is_empty()
+            && name.len() <= 253
+            && name
+                .split('.')
+                .all(|label| !label.is_empty() && label.len() <= 63 && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'))
+    };
+
+    if valid {
+        Some(host)
+    } else {
+        None
+    }
+}
+
 impl<T> Deref for Request<T> {
     type Target = RawRequest<T>;
 
@@ -406,3 +580,115 @@ impl<T> DerefMut for Request<T> {
         &mut self.inner
     }
 }
+
+/// Copy of the request headers, usable as a handler argument when a handler
+/// only needs the headers
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::request::Headers;
+/// async fn trace(mut req: Request) -> Result<String, SaphirError> {
+///     let headers = Headers::from_request(&mut req).await.map_err(SaphirError::responder)?;
+///     Ok(headers.get("x-trace-id").and_then(|h| h.to_str().ok()).unwrap_or("none").to_string())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Headers(pub HeaderMap);
+
+impl Headers {
+    pub fn into_inner(self) -> HeaderMap {
+        self.0
+    }
+}
+
+impl Deref for Headers {
+    type Target = HeaderMap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Headers {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl FromRequest for Headers {
+    type Err = ();
+    type Fut = futures::future::Ready<Result<Self, Self::Err>>;
+
+    fn from_request(req: &mut Request) -> Self::Fut {
+        futures::future::ready(Ok(Headers(req.headers().clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn headers_are_extracted() {
+        let mut req = Request::new(RawRequest::builder().header("x-trace-id", "4bf92f35").body(Body::empty()).unwrap(), None);
+
+        let headers = Headers::from_request(&mut req).await.unwrap();
+        assert_eq!(headers.get("x-trace-id").unwrap(), "4bf92f35");
+        assert!(headers.get("x-missing").is_none());
+        // The request keeps its headers
+        assert_eq!(req.headers().get("x-trace-id").unwrap(), "4bf92f35");
+    }
+
+    /// Client body sending its chunks, then a trailer
+    struct TrailingBody {
+        chunks: Vec<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    impl hyper::body::HttpBody for TrailingBody {
+        type Data = Bytes;
+        type Error = hyper::Error;
+
+        fn poll_data(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Result<Bytes, hyper::Error>>> {
+            std::task::Poll::Ready(if self.chunks.is_empty() { None } else { Some(Ok(self.chunks.remove(0))) })
+        }
+
+        fn poll_trailers(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<Option<HeaderMap>, hyper::Error>> {
+            std::task::Poll::Ready(Ok(self.trailers.take()))
+        }
+    }
+
+    #[tokio::test]
+    async fn trailers_are_read_after_the_body() {
+        use hyper::{body::HttpBody, server::conn::Http, service::service_fn};
+
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: RawRequest<hyper::Body>| async move {
+                let mut req: Request<Body> = Request::new(req.map(Body::from_raw), None);
+                let mut received = Vec::new();
+                while let Some(chunk) = req.body_mut().data().await {
+                    received.extend_from_slice(&chunk?);
+                }
+                let checksum = req.trailers().await?.and_then(|t| t.get("x-checksum").cloned());
+                let answer = format!("{} {:?}", String::from_utf8(received).unwrap(), checksum);
+                Ok::<_, SaphirError>(http::Response::new(hyper::Body::from(answer)))
+            });
+            let _ = Http::new().http2_only(true).serve_connection(socket, service).await;
+        });
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", "c0ffee".parse().unwrap());
+        let body = TrailingBody {
+            chunks: vec![Bytes::from("first "), Bytes::from("second")],
+            trailers: Some(trailers),
+        };
+        let client = hyper::Client::builder().http2_only(true).build_http::<TrailingBody>();
+        let req = RawRequest::post(format!("http://{}/upload", addr)).body(body).unwrap();
+        let res = client.request(req).await.unwrap();
+        let answer = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(answer, "first second Some(\"c0ffee\")");
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(clippy::let_and_return)]
-use crate::{http_context::HttpContext, response::Builder};
+use crate::{body::ResponseTrailers, http_context::HttpContext, response::Builder};
 use http::StatusCode;
 
 macro_rules! impl_status_responder {
@@ -19,7 +19,7 @@ macro_rules! impl_body_responder {
         $(
             impl Responder for $x {
                 fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
-                    builder.body(self)
+                    builder.expect_default_status().body(self)
                 }
             }
         )+
@@ -31,7 +31,7 @@ macro_rules! impl_plain_body_responder {
         $(
             impl Responder for $x {
                 fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
-                    builder.header(http::header::CONTENT_TYPE, "text/plain").body(self)
+                    builder.expect_default_status().header(http::header::CONTENT_TYPE, "text/plain").body(self)
                 }
             }
         )+
@@ -108,7 +108,7 @@ impl Responder for () {
 impl<T: Responder> Responder for Option<T> {
     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
         if let Some(r) = self {
-            r.respond_with_builder(builder, ctx).status_if_not_set(200)
+            r.respond_with_builder(builder, ctx)
         } else {
             builder.status_if_not_set(404)
         }
@@ -118,7 +118,7 @@ impl<T: Responder> Responder for Option<T> {
 impl<T: Responder, E: Responder> Responder for Result<T, E> {
     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
         match self {
-            Ok(r) => r.respond_with_builder(builder, ctx).status_if_not_set(200),
+            Ok(r) => r.respond_with_builder(builder, ctx),
             Err(r) => r.respond_with_builder(builder, ctx).status_if_not_set(500),
         }
     }
@@ -139,18 +139,30 @@ impl Responder for Builder {
 #[cfg(feature = "json")]
 mod json {
     use super::*;
-    use crate::body::Json;
+    use crate::body::{json::ConfiguredJson, Json, JsonConfig};
     use serde::Serialize;
 
     impl<T: Serialize> Responder for Json<T> {
+        fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+            let default_config = JsonConfig::default();
+            let config = ctx.router.as_ref().map(|r| r.json_config()).unwrap_or(&default_config);
+            respond_json(builder, &self.0, config)
+        }
+    }
+
+    impl<T: Serialize> Responder for ConfiguredJson<T> {
         fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
-            let b = match builder.json(&self.0) {
-                Ok(b) => b,
-                Err((b, _e)) => b.status(500).body("Unable to serialize json data"),
-            };
-            b.header(http::header::CONTENT_TYPE, "application/json")
+            respond_json(builder, &self.value, &self.config)
         }
     }
+
+    fn respond_json<T: Serialize>(builder: Builder, value: &T, config: &JsonConfig) -> Builder {
+        let b = match config.to_vec(value) {
+            Ok(v) => builder.expect_default_status().body(v),
+            Err(_e) => builder.status(500).body("Unable to serialize json data"),
+        };
+        b.header(http::header::CONTENT_TYPE, "application/json")
+    }
 }
 
 #[cfg(feature = "form")]
@@ -161,7 +173,7 @@ mod form {
 
     impl<T: Serialize> Responder for Form<T> {
         fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
-            let b = match builder.form(&self.0) {
+            let b = match builder.expect_default_status().form(&self.0) {
                 Ok(b) => b,
                 Err((b, _e)) => b.status(500).body("Unable to serialize form data"),
             };
@@ -170,6 +182,309 @@ mod form {
     }
 }
 
+/// Html body, sent with a `text/html; charset=utf-8` content type
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// async fn index(_req: Request) -> Html<&'static str> {
+///     Html("<h1>Hello</h1>")
+/// }
+/// ```
+pub struct Html<T>(pub T);
+
+impl<T: 'static + Into<hyper::Body> + Send + Sync> Responder for Html<T> {
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        builder
+            .expect_default_status()
+            .header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(self.0)
+    }
+}
+
+/// Body already computed as bytes, e.g. a generated image or a protobuf
+/// message, sent with the given content type and its `Content-Length`.
+/// Available with the features depending on `mime`: `file`, `multipart` and
+/// `redirect`.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::Raw;
+/// async fn thumbnail(_req: Request) -> Raw {
+///     let png: Vec<u8> = vec![0x89, b'P', b'N', b'G'];
+///     Raw(Bytes::from(png), mime::IMAGE_PNG)
+/// }
+/// ```
+#[cfg(feature = "mime")]
+pub struct Raw(pub hyper::body::Bytes, pub mime::Mime);
+
+#[cfg(feature = "mime")]
+impl Responder for Raw {
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        let Raw(bytes, mime) = self;
+        builder
+            .expect_default_status()
+            .header(http::header::CONTENT_TYPE, mime.as_ref())
+            .header(http::header::CONTENT_LENGTH, bytes.len())
+            .body(bytes)
+    }
+}
+
+/// `204 No Content` response, for operations which succeeded with nothing to
+/// send back. It has no body and no `Content-Length`.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// async fn delete_user(_req: Request) -> NoContent {
+///     NoContent
+/// }
+/// ```
+pub struct NoContent;
+
+impl Responder for NoContent {
+    fn respond_with_builder(self, mut builder: Builder, _ctx: &HttpContext) -> Builder {
+        if let Some(headers) = builder.headers_mut() {
+            headers.remove(http::header::CONTENT_LENGTH);
+        }
+        builder.status(StatusCode::NO_CONTENT)
+    }
+}
+
+/// Wrapper preventing a response from being stored by any cache: it forces
+/// `Cache-Control: no-store` and `Pragma: no-cache`, replacing whatever caching
+/// headers the inner responder set.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::NoStore;
+/// async fn account(_req: Request) -> NoStore<(u16, String)> {
+///     NoStore((200, "account number: 1234".to_string()))
+/// }
+/// ```
+pub struct NoStore<T>(pub T);
+
+impl<T: Responder> Responder for NoStore<T> {
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        let mut builder = self.0.respond_with_builder(builder, ctx);
+        if let Some(headers) = builder.headers_mut() {
+            headers.remove(http::header::EXPIRES);
+            headers.insert(http::header::CACHE_CONTROL, http::HeaderValue::from_static("no-store"));
+            headers.insert(http::header::PRAGMA, http::HeaderValue::from_static("no-cache"));
+        }
+        builder
+    }
+}
+
+/// Name of the trailer carrying the byte count of a `ByteCountTrailer` body
+pub const TOTAL_BYTES_TRAILER: &str = "x-total-bytes";
+
+/// Streamed body followed by an `X-Total-Bytes` trailer holding the amount
+/// of bytes produced, announced with a `Trailer` header. Clients of a large
+/// generated export can use it to make sure they received everything, since
+/// a streamed body has no `Content-Length`.
+///
+/// When a chunk fails, the body is aborted and no trailer is sent. Trailers
+/// are only delivered over HTTP/2, HTTP/1 connections drop them. Progress
+/// can't be reported through chunk extensions, the server doesn't expose
+/// them.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::ByteCountTrailer;
+/// # use futures::stream;
+/// async fn export(_req: Request) -> ByteCountTrailer<stream::Iter<std::vec::IntoIter<Result<Bytes, std::io::Error>>>> {
+///     let rows = vec![Ok(Bytes::from("id,name\n")), Ok(Bytes::from("1,saphir\n"))];
+///     ByteCountTrailer(stream::iter(rows))
+/// }
+/// ```
+pub struct ByteCountTrailer<S>(pub S);
+
+impl<S, E> Responder for ByteCountTrailer<S>
+where
+    S: 'static + futures::Stream<Item = Result<hyper::body::Bytes, E>> + Send + Sync,
+    E: 'static + Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        use futures::{stream, StreamExt, TryStreamExt};
+        use std::sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        };
+
+        let trailers = ResponseTrailers::default();
+        let total = Arc::new(AtomicU64::new(0));
+        let counted = total.clone();
+        let filled = trailers.clone();
+        let body = self
+            .0
+            .inspect_ok(move |chunk| {
+                counted.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+            })
+            // Only reached once the whole stream succeeded, the server stops polling on an error
+            .chain(stream::poll_fn(move |_| {
+                let mut headers = http::HeaderMap::new();
+                headers.insert(TOTAL_BYTES_TRAILER, http::HeaderValue::from(total.load(Ordering::SeqCst)));
+                filled.set(headers);
+                std::task::Poll::Ready(None)
+            }));
+
+        builder
+            .expect_default_status()
+            .header(http::header::TRAILER, TOTAL_BYTES_TRAILER)
+            .extension(trailers)
+            .body(hyper::Body::wrap_stream(body))
+    }
+}
+
+/// Streamed body whose status waits for the first item of the stream, for
+/// streams which can fail right away, e.g. a database query: a first item
+/// failing before anything was sent gets a `500 Internal Server Error`,
+/// otherwise the stream is sent with the default status. Items failing
+/// later abort the body, the status being already sent.
+///
+/// The first item is awaited by `FirstItemStatus::new`, in the handler.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::FirstItemStatus;
+/// # use futures::stream;
+/// async fn export(_req: Request) -> FirstItemStatus<stream::Iter<std::vec::IntoIter<Result<Bytes, std::io::Error>>>, std::io::Error> {
+///     let rows = vec![Ok(Bytes::from("id,name\n")), Ok(Bytes::from("1,saphir\n"))];
+///     FirstItemStatus::new(stream::iter(rows)).await
+/// }
+/// ```
+pub struct FirstItemStatus<S, E> {
+    first: Option<Result<hyper::body::Bytes, E>>,
+    rest: std::pin::Pin<Box<S>>,
+}
+
+impl<S, E> FirstItemStatus<S, E>
+where
+    S: futures::Stream<Item = Result<hyper::body::Bytes, E>>,
+{
+    /// Poll `stream` for its first item
+    pub async fn new(stream: S) -> Self {
+        use futures::StreamExt;
+
+        let mut rest = Box::pin(stream);
+        let first = rest.next().await;
+        FirstItemStatus { first, rest }
+    }
+}
+
+impl<S, E> Responder for FirstItemStatus<S, E>
+where
+    S: 'static + futures::Stream<Item = Result<hyper::body::Bytes, E>> + Send + Sync,
+    E: 'static + Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        use futures::{stream, StreamExt};
+
+        let first = match self.first {
+            Some(Ok(first)) => Some(first),
+            Some(Err(e)) => {
+                error!("The first item of a streamed response failed: {}", e.into());
+                return builder.status(500);
+            }
+            None => None,
+        };
+
+        let body = stream::iter(first).map(Ok::<_, E>).chain(self.rest);
+        builder.expect_default_status().body(hyper::Body::wrap_stream(body))
+    }
+}
+
+/// Body assembled from chunks already in memory, sent one chunk after the
+/// other without concatenating them. The body has no `Content-Length`, so
+/// HTTP/1.1 clients receive it with chunked encoding. Empty chunks are
+/// skipped, an empty chunk would end a chunked body.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::ChunkedBytes;
+/// async fn page(_req: Request) -> ChunkedBytes<Vec<Bytes>> {
+///     let header = Bytes::from_static(b"<html><body>");
+///     let footer = Bytes::from_static(b"</body></html>");
+///     ChunkedBytes(vec![header, Bytes::from("content"), footer])
+/// }
+/// ```
+pub struct ChunkedBytes<I>(pub I);
+
+impl<I> Responder for ChunkedBytes<I>
+where
+    I: IntoIterator<Item = hyper::body::Bytes>,
+    I::IntoIter: 'static + Send + Sync,
+{
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        let chunks = self.0.into_iter().filter(|chunk| !chunk.is_empty()).map(Ok::<_, std::convert::Infallible>);
+        builder.expect_default_status().body(hyper::Body::wrap_stream(futures::stream::iter(chunks)))
+    }
+}
+
+/// Create a body fed from another task or thread: chunks pushed through the
+/// returned `ChannelSender` are streamed to the client, while the
+/// `ChannelBody` is returned by the handler as its responder.
+///
+/// Sending waits until the client made room for the chunk, so a slow client
+/// slows the producer down instead of having the body buffered in memory.
+/// This bridges blocking producers, e.g. a `spawn_blocking` task, into a
+/// streamed response.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::{channel_body, ChannelBody};
+/// async fn report(_req: Request) -> ChannelBody {
+///     let (mut sender, body) = channel_body();
+///     tokio::task::spawn_blocking(move || {
+///         for line in 0..3 {
+///             if sender.send(format!("line {}\n", line)).is_err() {
+///                 break;
+///             }
+///         }
+///     });
+///     body
+/// }
+/// ```
+pub fn channel_body() -> (ChannelSender, ChannelBody) {
+    let (sender, body) = hyper::Body::channel();
+    (ChannelSender(sender), ChannelBody(body))
+}
+
+/// Producing half of a `channel_body`. Dropping it ends the body
+pub struct ChannelSender(hyper::body::Sender);
+
+/// Error returned when the client side of a `channel_body` is gone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyClosed;
+
+impl ChannelSender {
+    /// Send a chunk, blocking the current thread until the client can take
+    /// it. Only meant to be called from a blocking context: use
+    /// `send_async` from async code.
+    pub fn send<B: Into<hyper::body::Bytes>>(&mut self, chunk: B) -> Result<(), BodyClosed> {
+        futures::executor::block_on(self.send_async(chunk))
+    }
+
+    /// Send a chunk, waiting until the client can take it
+    pub async fn send_async<B: Into<hyper::body::Bytes>>(&mut self, chunk: B) -> Result<(), BodyClosed> {
+        self.0.send_data(chunk.into()).await.map_err(|_| BodyClosed)
+    }
+
+    /// Abort the body, the client sees the response fail instead of ending
+    /// normally
+    pub fn abort(self) {
+        self.0.abort()
+    }
+}
+
+/// Responding half of a `channel_body`
+pub struct ChannelBody(hyper::Body);
+
+impl Responder for ChannelBody {
+    fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+        builder.expect_default_status().body(self.0)
+    }
+}
+
 impl_status_responder!(u16, i16, u32, i32, u64, i64, usize, isize);
 impl_plain_body_responder!(String, &'static str);
 impl_body_responder!(Vec<u8>, &'static [u8], hyper::body::Bytes);
@@ -179,6 +494,93 @@ impl_tuple_responder!(0->A, 1->B, 2->C, 3->D);
 impl_tuple_responder!(0->A, 1->B, 2->C, 3->D, 4->E);
 impl_tuple_responder!(0->A, 1->B, 2->C, 3->D, 4->E, 5->F);
 
+/// Representations of the same resource in several media types, the one
+/// sent being picked by the `Accept` q-values of the request. The most
+/// specific media range of `Accept` matching a media type gives its quality,
+/// ties going to the representation registered first. When the client
+/// accepts none of them, the response is a `406 Not Acceptable`.
+///
+/// The picked responder builds the response, with the `Content-Type` of its
+/// media type.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::responder::Representations;
+/// async fn invoice(_req: Request) -> Representations {
+///     Representations::new()
+///         .representation("application/json", r#"{"number":42}"#)
+///         .representation("text/csv", "number\n42\n")
+///         .representation("text/html", Html("<p>Invoice 42</p>"))
+/// }
+/// ```
+#[derive(Default)]
+pub struct Representations {
+    representations: Vec<(String, Box<dyn DynResponder + Send>)>,
+}
+
+impl Representations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer `responder` as the representation in `media_type`
+    pub fn representation<R: 'static + Responder + Send>(mut self, media_type: &str, responder: R) -> Self {
+        self.representations.push((media_type.to_string(), Box::new(Some(responder))));
+        self
+    }
+}
+
+impl Responder for Representations {
+    fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        let builder = builder.header(http::header::VARY, "Accept");
+        let accept = ctx.accept().unwrap_or("*/*");
+
+        let mut picked: Option<(f32, String, Box<dyn DynResponder + Send>)> = None;
+        for (media_type, responder) in self.representations {
+            let quality = media_quality(accept, &media_type);
+            if quality > 0.0 && picked.as_ref().map(|(best, _, _)| quality > *best).unwrap_or(true) {
+                picked = Some((quality, media_type, responder));
+            }
+        }
+
+        match picked {
+            Some((_, media_type, mut responder)) => {
+                let mut builder = responder.dyn_respond(builder, ctx);
+                if let (Some(headers), Ok(content_type)) = (builder.headers_mut(), http::HeaderValue::from_str(&media_type)) {
+                    headers.insert(http::header::CONTENT_TYPE, content_type);
+                }
+                builder
+            }
+            None => builder.status(406),
+        }
+    }
+}
+
+/// Quality given by `accept` to `media_type`, from the most specific media
+/// range matching it. Parameters of `media_type` are ignored
+pub(crate) fn media_quality(accept: &str, media_type: &str) -> f32 {
+    let mut offered = media_type.split(';').next().unwrap_or_default().trim().splitn(2, '/');
+    let (kind, subtype) = (offered.next().unwrap_or_default(), offered.next().unwrap_or_default());
+    accept
+        .split(',')
+        .filter_map(|media_range| {
+            let mut params = media_range.split(';').map(|p| p.trim());
+            let mut range = params.next()?.splitn(2, '/');
+            let (range_kind, range_subtype) = (range.next()?, range.next()?);
+            let specificity = match (range_kind, range_subtype) {
+                ("*", "*") => 0,
+                (k, "*") if k.eq_ignore_ascii_case(kind) => 1,
+                (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => 2,
+                _ => return None,
+            };
+            let q = params.find_map(|p| p.strip_prefix("q=")).and_then(|q| q.parse::<f32>().ok()).unwrap_or(1.0);
+            Some((specificity, q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+        .unwrap_or(0.0)
+}
+
 /// Trait used by the server, not meant for manual implementation
 pub trait DynResponder {
     #[doc(hidden)]
@@ -193,3 +595,251 @@ where
         self.take().ok_or(500).respond_with_builder(builder, ctx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, request::Request, router::Router};
+
+    fn ctx() -> HttpContext {
+        let req = Request::new(http::Request::builder().body(Body::empty()).unwrap(), None);
+        HttpContext::new(req, Router::builder().build())
+    }
+
+    async fn respond<R: Responder>(responder: R) -> (Option<String>, String) {
+        let res = responder.respond_with_builder(Builder::new(), &ctx()).build().unwrap();
+        let content_type = res.headers().get(http::header::CONTENT_TYPE).map(|v| v.to_str().unwrap().to_string());
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (content_type, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn html_str_sets_content_type() {
+        let (content_type, body) = respond(Html("<p>static</p>")).await;
+        assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(body, "<p>static</p>");
+    }
+
+    #[tokio::test]
+    async fn html_string_sets_content_type() {
+        let (content_type, body) = respond(Html(format!("<p>{}</p>", "rendered"))).await;
+        assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(body, "<p>rendered</p>");
+    }
+
+    #[cfg(feature = "mime")]
+    #[tokio::test]
+    async fn raw_bytes_keep_their_content_type() {
+        let message = hyper::body::Bytes::from_static(&[0x08, 0x96, 0x01, 0x12, 0x00, 0xff]);
+        let protobuf: mime::Mime = "application/x-protobuf".parse().unwrap();
+        let res = Raw(message.clone(), protobuf).respond_with_builder(Builder::new(), &ctx()).build().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[http::header::CONTENT_TYPE], "application/x-protobuf");
+        assert_eq!(res.headers()[http::header::CONTENT_LENGTH], "6");
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(bytes, message);
+    }
+
+    #[tokio::test]
+    async fn representations_are_picked_by_quality() {
+        async fn negotiate(accept: &str) -> (u16, Option<String>, String) {
+            let req = http::Request::builder().header(http::header::ACCEPT, accept).body(Body::empty()).unwrap();
+            let ctx = HttpContext::new(Request::new(req, None), Router::builder().build());
+            let representations = Representations::new()
+                .representation("application/json", r#"{"id":1}"#)
+                .representation("text/csv", "id\n1\n")
+                .representation("text/html; charset=utf-8", Html("<p>1</p>"));
+            let res = representations.respond_with_builder(Builder::new(), &ctx).build().unwrap();
+            assert_eq!(res.headers()[http::header::VARY], "Accept");
+            let content_type = res.headers().get(http::header::CONTENT_TYPE).map(|v| v.to_str().unwrap().to_string());
+            let status = res.status().as_u16();
+            let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+            (status, content_type, String::from_utf8(bytes.to_vec()).unwrap())
+        }
+
+        let (status, content_type, body) = negotiate("text/csv;q=0.9, application/json;q=0.5, text/html;q=0.1").await;
+        assert_eq!((status, content_type.as_deref(), body.as_str()), (200, Some("text/csv"), "id\n1\n"));
+
+        let (_, content_type, body) = negotiate("text/*;q=0.3, text/html;q=0.8, application/json;q=0.4").await;
+        assert_eq!((content_type.as_deref(), body.as_str()), (Some("text/html; charset=utf-8"), "<p>1</p>"));
+
+        // Ties go to the first registered representation
+        let (_, content_type, _) = negotiate("*/*").await;
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        let (_, content_type, _) = negotiate("text/*, application/json;q=0").await;
+        assert_eq!(content_type.as_deref(), Some("text/csv"));
+
+        let (status, _, body) = negotiate("image/png, application/*;q=0").await;
+        assert_eq!(status, 406);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_content_is_empty() {
+        let res = NoContent.respond_with_builder(Builder::new(), &ctx()).build().unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(res.headers().get(http::header::CONTENT_LENGTH).is_none());
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn json_is_pretty_or_compact() {
+        use crate::body::{
+            json::{FieldCasing, JsonConfig},
+            Json,
+        };
+
+        let value = || serde_json::json!({ "user_id": 7, "display_name": null });
+        let (content_type, compact) = respond(Json(value())).await;
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(compact, r#"{"display_name":null,"user_id":7}"#);
+
+        let (_, pretty) = respond(Json(value()).with_config(JsonConfig::new().pretty(true))).await;
+        assert_eq!(pretty, "{\n  \"display_name\": null,\n  \"user_id\": 7\n}");
+
+        let config = JsonConfig::new().field_casing(FieldCasing::CamelCase).skip_nulls(true);
+        let (_, styled) = respond(Json(value()).with_config(config)).await;
+        assert_eq!(styled, r#"{"userId":7}"#);
+
+        let ctx = HttpContext::new(
+            Request::new(http::Request::builder().body(Body::empty()).unwrap(), None),
+            Router::builder().json_config(JsonConfig::new().pretty(true)).build(),
+        );
+        let res = Json(value()).respond_with_builder(Builder::new(), &ctx).build().unwrap();
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(String::from_utf8(bytes.to_vec()).unwrap(), pretty);
+    }
+
+    struct Cacheable;
+
+    impl Responder for Cacheable {
+        fn respond_with_builder(self, builder: Builder, _ctx: &HttpContext) -> Builder {
+            builder
+                .header(http::header::CACHE_CONTROL, "public")
+                .header(http::header::CACHE_CONTROL, "max-age=3600")
+                .header(http::header::EXPIRES, "Thu, 01 Dec 2094 16:00:00 GMT")
+                .body("secret")
+        }
+    }
+
+    #[tokio::test]
+    async fn no_store_overrides_cache_headers() {
+        let res = NoStore(Cacheable).respond_with_builder(Builder::new(), &ctx()).build().unwrap();
+        let cache_control: Vec<_> = res.headers().get_all(http::header::CACHE_CONTROL).iter().collect();
+        assert_eq!(cache_control, vec!["no-store"]);
+        assert_eq!(res.headers().get(http::header::PRAGMA).unwrap(), "no-cache");
+        assert!(res.headers().get(http::header::EXPIRES).is_none());
+    }
+
+    #[tokio::test]
+    async fn byte_count_trailer_follows_the_stream() {
+        use crate::body::BodyMetrics;
+        use futures::stream;
+        use hyper::body::HttpBody;
+
+        let chunks: Vec<Result<hyper::body::Bytes, std::io::Error>> = vec![Ok("id,name\n".into()), Ok("1,saphir\n".into())];
+        let res = ByteCountTrailer(stream::iter(chunks))
+            .respond_with_builder(Builder::new(), &ctx())
+            .build()
+            .unwrap();
+        assert_eq!(res.headers().get(http::header::TRAILER).unwrap(), "x-total-bytes");
+
+        let mut res = res.into_raw().unwrap();
+        let trailers = res.extensions_mut().remove::<ResponseTrailers>();
+        let mut body = BodyMetrics::default().count_response(res.into_body().into_raw(), trailers);
+        let mut content = Vec::new();
+        while let Some(chunk) = body.data().await {
+            content.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(content, b"id,name\n1,saphir\n");
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers.get(TOTAL_BYTES_TRAILER).unwrap(), "17");
+    }
+
+    #[tokio::test]
+    async fn first_item_status_streams_or_fails() {
+        use futures::stream;
+
+        let rows: Vec<Result<hyper::body::Bytes, std::io::Error>> = vec![Ok("id,name\n".into()), Ok("1,saphir\n".into())];
+        let res = FirstItemStatus::new(stream::iter(rows))
+            .await
+            .respond_with_builder(Builder::new(), &ctx())
+            .build()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(http::header::CONTENT_LENGTH).is_none());
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(bytes, "id,name\n1,saphir\n");
+
+        let rows: Vec<Result<hyper::body::Bytes, std::io::Error>> = vec![
+            Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused")),
+            Ok("id\n".into()),
+        ];
+        let res = FirstItemStatus::new(stream::iter(rows))
+            .await
+            .respond_with_builder(Builder::new(), &ctx())
+            .build()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn channel_body_streams_from_a_blocking_task() {
+        let (mut sender, body) = channel_body();
+        let producer = tokio::task::spawn_blocking(move || {
+            for i in 0..3 {
+                sender.send(format!("chunk {};", i)).unwrap();
+            }
+        });
+
+        let (_, content) = respond(body).await;
+        producer.await.unwrap();
+        assert_eq!(content, "chunk 0;chunk 1;chunk 2;");
+    }
+
+    #[tokio::test]
+    async fn channel_body_waits_for_the_client() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let (mut sender, body) = channel_body();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        let producer = tokio::task::spawn_blocking(move || {
+            for _ in 0..100 {
+                sender.send("x").unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+        assert!(sent.load(Ordering::SeqCst) < 100);
+
+        let (_, content) = respond(body).await;
+        producer.await.unwrap();
+        assert_eq!(content.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn chunked_bytes_are_sent_in_order() {
+        use hyper::body::{Bytes, HttpBody};
+
+        let chunks = vec![Bytes::from("first;"), Bytes::new(), Bytes::from("second;"), Bytes::from("third")];
+        let res = ChunkedBytes(chunks).respond_with_builder(Builder::new(), &ctx()).build().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(http::header::CONTENT_LENGTH).is_none());
+
+        let mut body = res.into_raw().unwrap().into_body().into_raw();
+        let mut received = Vec::new();
+        while let Some(chunk) = body.data().await {
+            received.push(chunk.unwrap());
+        }
+        assert_eq!(received, vec!["first;", "second;", "third"]);
+    }
+}
@@ -100,6 +100,10 @@ pub struct Builder {
     body: Box<dyn TransmuteBody + Send + Sync>,
     #[doc(hidden)]
     status_set: bool,
+    #[doc(hidden)]
+    default_status: StatusCode,
+    #[doc(hidden)]
+    default_status_expected: bool,
 }
 
 impl Builder {
@@ -120,7 +124,57 @@ impl Builder {
             cookies: None,
             body: Box::new(Option::<String>::None),
             status_set: false,
+            default_status: StatusCode::OK,
+            default_status_expected: false,
+        }
+    }
+
+    /// Set the status sent if the response is built without one, `200` unless
+    /// changed. The router starts the builders of the handlers' responses
+    /// with the status configured through
+    /// `router::Builder::default_status`.
+    /// ```
+    /// # use saphir::prelude::*;
+    ///
+    /// let response = Builder::new()
+    ///     .default_status(202)
+    ///     .body("queued")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), 202);
+    /// ```
+    #[inline]
+    pub fn default_status<T>(mut self, status: T) -> Builder
+    where
+        StatusCode: TryFrom<T>,
+    {
+        if let Ok(status) = StatusCode::try_from(status) {
+            self.default_status = status;
         }
+        self
+    }
+
+    /// Whether a status was explicitly set on this builder
+    #[inline]
+    pub fn is_status_set(&self) -> bool {
+        self.status_set
+    }
+
+    /// Mark the default status as intended: responders producing a body on
+    /// their own, like `String`, rely on it to answer a `200`. Unlike setting
+    /// the status, this lets wrapping responders like `Result` pick theirs.
+    #[inline]
+    pub(crate) fn expect_default_status(mut self) -> Builder {
+        self.default_status_expected = true;
+        self
+    }
+
+    /// Whether the response will go out with the default status although
+    /// nothing asked for it, which usually means a responder forgot to set
+    /// a status
+    #[inline]
+    pub(crate) fn has_implicit_status(&self) -> bool {
+        !self.status_set && !self.default_status_expected
     }
 
     #[inline]
@@ -141,7 +195,7 @@ impl Builder {
     /// This function will configure the HTTP status code of the `Response` that
     /// will be returned from `Builder::build`.
     ///
-    /// By default this is `200`.
+    /// By default this is `200`, see `default_status`.
     /// ```
     /// # use saphir::prelude::*;
     ///
@@ -295,6 +349,51 @@ impl Builder {
         self
     }
 
+    /// Advertise alternative services for the origin with an `Alt-Svc`
+    /// header, letting clients switch transport, e.g. to HTTP/3, on their
+    /// next connections. Use `clear` to withdraw previous advertisements.
+    ///
+    /// ```
+    /// # use saphir::prelude::*;
+    /// let response = Builder::new()
+    ///     .alt_svc("h3=\":443\"; ma=86400")
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(response.headers()["alt-svc"], "h3=\":443\"; ma=86400");
+    /// ```
+    #[inline]
+    pub fn alt_svc(self, value: &str) -> Builder {
+        self.header(http::header::ALT_SVC, value)
+    }
+
+    /// Flag a degraded response with a `Warning` header, e.g. a stale or
+    /// partial one: `code` is the three digit warn-code, `agent` the host of
+    /// the server adding it or `-` when unknown, and `text` is quoted, its
+    /// control characters being left out. Several warnings can be added.
+    ///
+    /// ```
+    /// # use saphir::prelude::*;
+    /// let response = Builder::new()
+    ///     .warning(110, "-", "Response is Stale")
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(response.headers()["warning"], "110 - \"Response is Stale\"");
+    /// ```
+    #[inline]
+    pub fn warning(self, code: u16, agent: &str, text: &str) -> Builder {
+        let mut value = format!("{:03} {} \"", code, agent);
+        for c in text.chars().filter(|c| *c == '\t' || !c.is_control()) {
+            if c == '"' || c == '\\' {
+                value.push('\\');
+            }
+            value.push(c);
+        }
+        value.push('"');
+        self.header(http::header::WARNING, value)
+    }
+
     ///
     #[inline]
     pub fn cookies_mut(&mut self) -> &mut CookieJar {
@@ -314,7 +413,17 @@ impl Builder {
     /// Finish the builder into Response<Body>
     #[inline]
     pub fn build(self) -> Result<Response<Body>, SaphirError> {
-        let Builder { inner, cookies, mut body, .. } = self;
+        let Builder {
+            mut inner,
+            cookies,
+            mut body,
+            status_set,
+            default_status,
+            ..
+        } = self;
+        if !status_set {
+            inner = inner.status(default_status);
+        }
         let b = body.transmute();
         let raw = inner.body(b)?;
 
@@ -331,11 +440,68 @@ impl Default for Builder {
     }
 }
 
+#[cfg(feature = "json")]
+pub use json::JsonEnvelope;
+
 #[cfg(feature = "json")]
 mod json {
     use serde::Serialize;
 
     use super::*;
+    use crate::{
+        body::JsonConfig,
+        http_context::HttpContext,
+        template::{negotiate, Rendering},
+    };
+
+    /// Shape of the JSON envelopes wrapping API payloads, see
+    /// `Builder::json_envelope`. The default one is `{"data": ..., "meta": ...}`.
+    ///
+    /// ```rust
+    /// # use saphir::response::JsonEnvelope;
+    /// // {"result": ..., "pagination": ...}
+    /// const ENVELOPE: JsonEnvelope = JsonEnvelope::new("result", "pagination");
+    /// ```
+    #[derive(Clone, Copy, Debug)]
+    pub struct JsonEnvelope {
+        data_key: &'static str,
+        meta_key: &'static str,
+        keep_null_meta: bool,
+    }
+
+    impl JsonEnvelope {
+        pub const fn new(data_key: &'static str, meta_key: &'static str) -> Self {
+            JsonEnvelope {
+                data_key,
+                meta_key,
+                keep_null_meta: false,
+            }
+        }
+
+        /// Keep the meta key when the meta is `null`, it is left out by
+        /// default
+        pub const fn keep_null_meta(mut self, keep: bool) -> Self {
+            self.keep_null_meta = keep;
+            self
+        }
+
+        /// Wrap `data` and `meta` in the envelope
+        pub fn wrap<D: Serialize, M: Serialize>(&self, data: &D, meta: &M) -> Result<serde_json::Value, serde_json::Error> {
+            let mut envelope = serde_json::Map::new();
+            envelope.insert(self.data_key.to_string(), serde_json::to_value(data)?);
+            let meta = serde_json::to_value(meta)?;
+            if !meta.is_null() || self.keep_null_meta {
+                envelope.insert(self.meta_key.to_string(), meta);
+            }
+            Ok(serde_json::Value::Object(envelope))
+        }
+    }
+
+    impl Default for JsonEnvelope {
+        fn default() -> Self {
+            JsonEnvelope::new("data", "meta")
+        }
+    }
 
     impl Builder {
         pub fn json<T: Serialize>(self, t: &T) -> Result<Builder, (Builder, SaphirError)> {
@@ -344,6 +510,251 @@ mod json {
                 Err(e) => Err((self, e.into())),
             }
         }
+
+        /// Answer with `data` and `meta` wrapped in the default
+        /// `JsonEnvelope`, `{"data": ..., "meta": ...}`. A `null` meta, such as
+        /// `()` or `None`, is left out. The envelope is serialized with the
+        /// `JsonConfig` of the router, like `Json` responders.
+        ///
+        /// ```rust
+        /// # use saphir::prelude::*;
+        /// struct Numbers;
+        ///
+        /// impl Responder for Numbers {
+        ///     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        ///         // {"data":[1,2,3],"meta":{"total":3}}
+        ///         match builder.json_envelope(&vec![1, 2, 3], &serde_json::json!({ "total": 3 }), ctx) {
+        ///             Ok(builder) => builder,
+        ///             Err((builder, _e)) => builder.status(500),
+        ///         }
+        ///     }
+        /// }
+        /// ```
+        pub fn json_envelope<D: Serialize, M: Serialize>(self, data: &D, meta: &M, ctx: &HttpContext) -> Result<Builder, (Builder, SaphirError)> {
+            self.json_envelope_with(&JsonEnvelope::default(), data, meta, ctx)
+        }
+
+        /// Answer with `data` and `meta` wrapped in `envelope`
+        pub fn json_envelope_with<D: Serialize, M: Serialize>(
+            self,
+            envelope: &JsonEnvelope,
+            data: &D,
+            meta: &M,
+            ctx: &HttpContext,
+        ) -> Result<Builder, (Builder, SaphirError)> {
+            let default_config = JsonConfig::default();
+            let config = ctx.router.as_ref().map(|r| r.json_config()).unwrap_or(&default_config);
+            match envelope.wrap(data, meta).and_then(|body| config.to_vec(&body)) {
+                Ok(v) => Ok(self.header(http::header::CONTENT_TYPE, "application/json").body(v)),
+                Err(e) => Err((self, e.into())),
+            }
+        }
+
+        /// Answer with `status` and a JSON error body holding a machine
+        /// readable `code`, a `message` and the `request_id` of the request,
+        /// so clients can reference the failed request when reporting it. The
+        /// request id is the operation id of the context, it is `null` when
+        /// the `operation` feature is disabled.
+        ///
+        /// ```rust
+        /// # use saphir::prelude::*;
+        /// struct InvoiceNotFound;
+        ///
+        /// impl Responder for InvoiceNotFound {
+        ///     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        ///         // {"code":"invoice_not_found","message":"No invoice with this number","request_id":"..."}
+        ///         builder.json_error(404, "invoice_not_found", "No invoice with this number", ctx)
+        ///     }
+        /// }
+        /// ```
+        #[allow(unused_variables)]
+        pub fn json_error(self, status: u16, code: &str, message: &str, ctx: &HttpContext) -> Builder {
+            #[cfg(feature = "operation")]
+            let request_id = Some(ctx.operation_id.to_string());
+            #[cfg(not(feature = "operation"))]
+            let request_id: Option<String> = None;
+
+            let body = serde_json::json!({
+                "code": code,
+                "message": message,
+                "request_id": request_id,
+            });
+
+            self.status(status)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+        }
+
+        /// Answer with `template` rendered with `data` by the router's
+        /// template engine, or with `data` serialized as JSON, whichever the
+        /// `Accept` header of the request prefers. HTML is chosen when both
+        /// are equally accepted, and JSON is always sent when no template
+        /// engine is registered. The response carries `Vary: Accept`, and is
+        /// a `406 Not Acceptable` when the client accepts neither.
+        ///
+        /// ```rust
+        /// # use saphir::prelude::*;
+        /// #[derive(serde_derive::Serialize)]
+        /// struct Order {
+        ///     id: u32,
+        ///     total: f64,
+        /// }
+        ///
+        /// impl Responder for Order {
+        ///     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        ///         builder.render("order.html", &self, ctx)
+        ///     }
+        /// }
+        /// ```
+        pub fn render<T: Serialize>(self, template: &str, data: &T, ctx: &HttpContext) -> Builder {
+            let builder = self.header(http::header::VARY, "Accept");
+            let data = match serde_json::to_value(data) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("Unable to serialize the data of template {}: {}", template, e);
+                    return builder.status(500);
+                }
+            };
+
+            let engine = ctx.router.as_ref().and_then(|router| router.template_engine());
+            match (negotiate(ctx.accept(), engine.is_some()), engine) {
+                (Some(Rendering::Html), Some(engine)) => match engine.render(template, &data) {
+                    Ok(html) => builder
+                        .expect_default_status()
+                        .header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+                        .body(html),
+                    Err(e) => {
+                        error!("Unable to render template {}: {:?}", template, e);
+                        builder.status(500)
+                    }
+                },
+                (Some(_), _) => builder
+                    .expect_default_status()
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(data.to_string()),
+                (None, _) => builder.status(406),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{request::Request, router::Router};
+
+        #[tokio::test]
+        async fn json_error_includes_the_request_id() {
+            let req = http::Request::builder().header("Operation-Id", "b3b856c4-8bca-4c1e-a8a2-3f1e2a7b9d10");
+            let req = Request::new(req.body(Body::empty()).unwrap(), None);
+            let ctx = HttpContext::new(req, Router::builder().build());
+
+            let res = Builder::new()
+                .json_error(404, "invoice_not_found", "No invoice with this number", &ctx)
+                .build()
+                .unwrap();
+            assert_eq!(res.status(), 404);
+            assert_eq!(res.headers().get(http::header::CONTENT_TYPE).unwrap(), "application/json");
+            let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "invoice_not_found");
+            assert_eq!(body["message"], "No invoice with this number");
+            #[cfg(feature = "operation")]
+            assert_eq!(body["request_id"], "b3b856c4-8bca-4c1e-a8a2-3f1e2a7b9d10");
+            #[cfg(not(feature = "operation"))]
+            assert!(body["request_id"].is_null());
+        }
+
+        async fn body_json(builder: Builder) -> serde_json::Value {
+            let res = builder.build().unwrap();
+            assert_eq!(res.headers().get(http::header::CONTENT_TYPE).unwrap(), "application/json");
+            let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        #[tokio::test]
+        async fn json_envelope_wraps_data_and_meta() {
+            let ctx = HttpContext::new(Request::new(http::Request::new(Body::empty()), None), Router::builder().build());
+            let builder = Builder::new().json_envelope(&vec!["a", "b"], &serde_json::json!({ "total": 2 }), &ctx);
+            assert_eq!(
+                body_json(builder.ok().unwrap()).await,
+                serde_json::json!({ "data": ["a", "b"], "meta": { "total": 2 } })
+            );
+
+            let builder = Builder::new().json_envelope(&"a", &(), &ctx);
+            assert_eq!(body_json(builder.ok().unwrap()).await, serde_json::json!({ "data": "a" }));
+
+            let envelope = JsonEnvelope::new("result", "pagination").keep_null_meta(true);
+            let builder = Builder::new().json_envelope_with(&envelope, &1, &None::<u32>, &ctx);
+            assert_eq!(body_json(builder.ok().unwrap()).await, serde_json::json!({ "result": 1, "pagination": null }));
+
+            // Keys that aren't strings can't be serialized
+            let data: std::collections::BTreeMap<Vec<u8>, u8> = vec![(vec![1], 1)].into_iter().collect();
+            assert!(Builder::new().json_envelope(&data, &(), &ctx).is_err());
+        }
+
+        #[tokio::test]
+        async fn json_envelope_follows_the_json_config_of_the_router() {
+            use crate::body::json::FieldCasing;
+            let router = Router::builder().json_config(JsonConfig::new().field_casing(FieldCasing::CamelCase)).build();
+            let ctx = HttpContext::new(Request::new(http::Request::new(Body::empty()), None), router);
+            let builder = Builder::new().json_envelope(&serde_json::json!({ "user_id": 7 }), &serde_json::json!({ "next_page": 2 }), &ctx);
+            assert_eq!(
+                body_json(builder.ok().unwrap()).await,
+                serde_json::json!({ "data": { "userId": 7 }, "meta": { "nextPage": 2 } })
+            );
+        }
+
+        struct Templates;
+
+        impl crate::template::TemplateEngine for Templates {
+            fn render(&self, template: &str, data: &serde_json::Value) -> Result<String, SaphirError> {
+                Ok(format!("<{}>{}</{}>", template, data["name"].as_str().unwrap_or_default(), template))
+            }
+        }
+
+        struct Profile;
+
+        impl crate::responder::Responder for Profile {
+            fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+                builder.render("h1", &serde_json::json!({ "name": "saphir" }), ctx)
+            }
+        }
+
+        async fn get_profile(router: &Router, accept: &str) -> (u16, Option<String>, String) {
+            let req = http::Request::builder().uri("/profile").header(http::header::ACCEPT, accept);
+            let req = Request::new(req.body(Body::empty()).unwrap(), None);
+            let mut ctx = router.clone().handle(HttpContext::new(req, router.clone())).await.unwrap();
+            let res = ctx.state.take_response().unwrap();
+            let content_type = res.headers().get(http::header::CONTENT_TYPE).map(|h| h.to_str().unwrap().to_string());
+            let status = res.status().as_u16();
+            let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+            (status, content_type, String::from_utf8(body.to_vec()).unwrap())
+        }
+
+        #[tokio::test]
+        async fn render_negotiates_html_or_json() {
+            let profile = |_req: Request<Body>| async { Profile };
+            let router = Router::builder()
+                .template_engine(Templates)
+                .route("/profile", http::Method::GET, profile)
+                .build();
+
+            let (status, content_type, body) = get_profile(&router, "text/html,*/*;q=0.8").await;
+            assert_eq!(status, 200);
+            assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+            assert_eq!(body, "<h1>saphir</h1>");
+
+            let (status, content_type, body) = get_profile(&router, "application/json").await;
+            assert_eq!(status, 200);
+            assert_eq!(content_type.as_deref(), Some("application/json"));
+            assert_eq!(body, r#"{"name":"saphir"}"#);
+
+            assert_eq!(get_profile(&router, "image/png").await.0, 406);
+
+            // Without a template engine, JSON is the only representation
+            let router = Router::builder().route("/profile", http::Method::GET, profile).build();
+            assert_eq!(get_profile(&router, "*/*").await.1.as_deref(), Some("application/json"));
+        }
     }
 }
 
@@ -363,18 +774,337 @@ mod form {
     }
 }
 
+#[cfg(feature = "file")]
+pub use file::templated_filename;
+
 #[cfg(feature = "file")]
 mod file {
     use super::*;
-    use crate::{file::FileStream, prelude::Bytes};
+    use crate::{
+        file::{
+            etag::{EntityTag, SystemTimeExt},
+            middleware::PathExt,
+            range::Range,
+            range_requests::{extract_range, is_range_fresh, is_satisfiable_range},
+            requested_range, File, FileInfo, FileStream, StreamedFile,
+        },
+        http_context::HttpContext,
+        prelude::Bytes,
+    };
     use futures::Stream;
+    use http::header;
+    use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+    use std::{collections::HashMap, str::FromStr};
+
+    /// Characters allowed unencoded in an RFC 5987 `ext-value` (`attr-char`)
+    const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'!')
+        .remove(b'#')
+        .remove(b'$')
+        .remove(b'&')
+        .remove(b'+')
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'^')
+        .remove(b'_')
+        .remove(b'`')
+        .remove(b'|')
+        .remove(b'~');
 
     impl Builder {
         pub fn file<F: Into<FileStream>>(self, file: F) -> Builder {
-            self.body(Box::new(file.into())
-                as Box<
-                    dyn Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Sync + Send>>> + 'static + Sync + Send,
-                >)
+            self.expect_default_status().extension(StreamedFile).body(
+                Box::new(file.into()) as Box<dyn Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Sync + Send>>> + 'static + Sync + Send>
+            )
+        }
+
+        /// Stream the file at `path` as an attachment named `filename`.
+        ///
+        /// The `Content-Disposition` header carries both a plain ASCII
+        /// `filename` fallback and the RFC 5987 encoded `filename*`, so non
+        /// ASCII names survive in every browser. The `Range` of the request
+        /// of `ctx` is honored, and so is its `If-Range` while the request is
+        /// still in `ctx`. Once it was handed to the handler, a request carrying
+        /// an `If-Range` gets the whole file, like with `bytes_range`.
+        ///
+        /// ```rust,no_run
+        /// # use saphir::prelude::*;
+        /// async fn monthly_report(mut ctx: HttpContext, _chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        ///     let res = Builder::new()
+        ///         .download_file("/srv/reports/2020-01.pdf", "Rapport de janvier.pdf", &ctx)
+        ///         .await?
+        ///         .build()?;
+        ///     ctx.after(res);
+        ///     Ok(ctx)
+        /// }
+        ///
+        /// let server = Server::builder()
+        ///     .configure_middlewares(|m| m.apply(monthly_report, vec!["/reports/2020-01"], None))
+        ///     .build();
+        /// ```
+        pub async fn download_file(self, path: &str, filename: &str, ctx: &HttpContext) -> Result<Builder, SaphirError> {
+            let file = File::open(path).await?;
+            let file_path = file.get_path().clone();
+            let (last_modified, size) = (file.modified()?, file.get_size());
+            let etag = EntityTag::new(false, format!("{}-{}", last_modified.timestamp(), size).as_str());
+            let mime = file_path.mime().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+            let mut stream = FileStream::new(file);
+            let mut builder = self
+                .header(header::CONTENT_DISPOSITION, attachment_disposition(filename))
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::CONTENT_TYPE, mime.to_string())
+                .header(header::ETAG, etag.get_tag());
+
+            let range = match ctx.state.request() {
+                Some(req) => req
+                    .headers()
+                    .get(header::RANGE)
+                    .and_then(|header| header.to_str().ok())
+                    .filter(|_| is_range_fresh(req, &etag, &last_modified)),
+                None => ctx.range(),
+            };
+            let content_range = range
+                .and_then(|header| Range::from_str(header).ok())
+                .and_then(|range| is_satisfiable_range(&range, size));
+
+            let mut len = size;
+            if let Some(content_range) = content_range {
+                if let Some((start, end)) = extract_range(&content_range) {
+                    stream.set_range((start, end)).await?;
+                    len = end - start + 1;
+                    builder = builder
+                        .header(header::CONTENT_RANGE, content_range.to_string())
+                        .status(StatusCode::PARTIAL_CONTENT);
+                }
+            }
+
+            Ok(builder.header(header::CONTENT_LENGTH, len).file(stream))
+        }
+
+        /// Send `data`, or the byte range of it requested in `ctx` with a
+        /// `206 Partial Content`, for content held in memory. An unsatisfiable
+        /// range, or a `Range` not applying since the request carries an
+        /// `If-Range`, gets the whole content.
+        ///
+        /// ```rust
+        /// # use saphir::prelude::*;
+        /// struct Thumbnail(Bytes);
+        ///
+        /// impl Responder for Thumbnail {
+        ///     fn respond_with_builder(self, builder: Builder, ctx: &HttpContext) -> Builder {
+        ///         builder.header("Content-Type", "image/png").bytes_range(self.0, ctx)
+        ///     }
+        /// }
+        /// ```
+        pub fn bytes_range(self, data: Bytes, ctx: &HttpContext) -> Builder {
+            let builder = self.header(header::ACCEPT_RANGES, "bytes");
+            match requested_range(ctx, data.len() as u64) {
+                Some((content_range, (start, end))) => builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_RANGE, content_range.to_string())
+                    .body(data.slice(start as usize..=end as usize)),
+                None => builder.expect_default_status().body(data),
+            }
+        }
+    }
+
+    /// Filename for a download, from `template` with its `{name}` placeholders
+    /// replaced by the matching `params`, e.g. the captures of the route.
+    /// Unknown placeholders are left out, and `{{` and `}}` stand for literal
+    /// braces.
+    ///
+    /// The name is made safe whatever the params hold: path separators become
+    /// `_`, control characters are removed, and leading dots and surrounding
+    /// spaces are trimmed. An empty name becomes `download`. Encoding it for
+    /// the `Content-Disposition` header is left to `Builder::download_file`.
+    ///
+    /// ```rust
+    /// # use saphir::response::templated_filename;
+    /// # use std::collections::HashMap;
+    /// // The captures of GET /reports/<month>
+    /// let mut captures = HashMap::new();
+    /// captures.insert("month".to_string(), "2020-01".to_string());
+    /// assert_eq!(templated_filename("report-{month}.csv", &captures), "report-2020-01.csv");
+    /// ```
+    pub fn templated_filename(template: &str, params: &HashMap<String, String>) -> String {
+        let mut filename = String::with_capacity(template.len());
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    filename.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    filename.push('}');
+                }
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    if let Some(value) = params.get(name.trim()) {
+                        filename.push_str(value);
+                    }
+                }
+                c => filename.push(c),
+            }
+        }
+
+        let filename: String = filename
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+            .collect();
+        let filename = filename.trim().trim_start_matches('.').trim_start();
+        if filename.is_empty() {
+            "download".to_string()
+        } else {
+            filename.to_string()
+        }
+    }
+
+    fn attachment_disposition(filename: &str) -> String {
+        let fallback: String = filename
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            utf8_percent_encode(filename, ATTR_CHAR)
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{body::Body, request::Request};
+        use std::io::Write;
+
+        fn write_tmp_file(name: &str, content: &[u8]) -> String {
+            let path = std::env::temp_dir().join(name);
+            std::fs::File::create(&path).unwrap().write_all(content).unwrap();
+            path.to_str().unwrap().to_string()
+        }
+
+        fn request(range: Option<&str>) -> Request<Body> {
+            let mut builder = http::Request::builder().uri("/download");
+            if let Some(range) = range {
+                builder = builder.header(header::RANGE, range);
+            }
+            Request::new(builder.body(Body::empty()).unwrap(), None)
+        }
+
+        fn download_ctx(range: Option<&str>) -> HttpContext {
+            HttpContext::new(request(range), crate::router::Router::builder().build())
+        }
+
+        async fn body_bytes(res: Response<Body>) -> Bytes {
+            hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap()
+        }
+
+        #[tokio::test]
+        async fn bytes_range_serves_a_slice_of_in_memory_bytes() {
+            let data = Bytes::from_static(b"0123456789");
+            let respond = |range: Option<&str>| {
+                let ctx = HttpContext::new(request(range), crate::router::Router::builder().build());
+                Builder::new().bytes_range(data.clone(), &ctx).build().unwrap()
+            };
+
+            let res = respond(None);
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[header::ACCEPT_RANGES], "bytes");
+            assert!(res.headers().get(header::CONTENT_RANGE).is_none());
+            assert_eq!(body_bytes(res).await, data);
+
+            let res = respond(Some("bytes=2-5"));
+            assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"2345"));
+
+            let res = respond(Some("bytes=-3"));
+            assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 7-9/10");
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"789"));
+
+            let res = respond(Some("bytes=20-30"));
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(body_bytes(res).await, data);
+        }
+
+        #[test]
+        fn attachment_disposition_encodes_non_ascii() {
+            assert_eq!(
+                attachment_disposition("résumé \"final\".pdf"),
+                "attachment; filename=\"r_sum_ _final_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%22final%22.pdf"
+            );
+            assert_eq!(
+                attachment_disposition("report.pdf"),
+                "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+            );
+        }
+
+        #[test]
+        fn templated_filename_is_sanitized() {
+            let mut params = HashMap::new();
+            params.insert("date".to_string(), "2020-01-31".to_string());
+            params.insert("client".to_string(), "../../etc/\u{7}Société\\x".to_string());
+            assert_eq!(templated_filename("report-{date}.csv", &params), "report-2020-01-31.csv");
+            assert_eq!(templated_filename("{{{date}}}-{missing}.txt", &params), "{2020-01-31}-.txt");
+
+            let filename = templated_filename("{client}-{date}.csv", &params);
+            assert_eq!(filename, "_.._etc_Société_x-2020-01-31.csv");
+            assert_eq!(
+                attachment_disposition(&filename),
+                "attachment; filename=\"_.._etc_Soci_t__x-2020-01-31.csv\"; filename*=UTF-8''_.._etc_Soci%C3%A9t%C3%A9_x-2020-01-31.csv"
+            );
+
+            assert_eq!(templated_filename("..{missing}", &params), "download");
+            assert_eq!(templated_filename(" .hidden\r\n", &params), "hidden");
+        }
+
+        #[tokio::test]
+        async fn download_file_sets_disposition_and_streams() {
+            let path = write_tmp_file("saphir_download_file_full.txt", b"0123456789");
+
+            let res = Builder::new()
+                .download_file(&path, "données.txt", &download_ctx(None))
+                .await
+                .unwrap()
+                .build()
+                .unwrap();
+
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+                "attachment; filename=\"donn_es.txt\"; filename*=UTF-8''donn%C3%A9es.txt"
+            );
+            assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "10");
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"0123456789"));
+        }
+
+        #[tokio::test]
+        async fn download_file_applies_range() {
+            let path = write_tmp_file("saphir_download_file_range.txt", b"0123456789");
+
+            let res = Builder::new()
+                .download_file(&path, "digits.txt", &download_ctx(Some("bytes=2-5")))
+                .await
+                .unwrap()
+                .build()
+                .unwrap();
+
+            assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 2-5/10");
+            assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "4");
+            assert!(res.headers().get(header::CONTENT_DISPOSITION).is_some());
+            assert_eq!(body_bytes(res).await, Bytes::from_static(b"2345"));
         }
     }
 }
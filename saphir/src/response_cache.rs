@@ -0,0 +1,797 @@
+//! In-process caching of whole responses.
+//!
+//! Responses are stored in memory, keyed by method, host, path and the
+//! values of the request headers they vary on, and replayed without invoking the
+//! handler until they expire. An expired response can still be replayed for a
+//! while as the handler is invoked in the background to refresh it, see
+//! `ResponseCacheMiddleware::stale_while_revalidate`. Concurrent misses on
+//! the same response can be coalesced into a single invocation of the
+//! handler, see `ResponseCacheMiddleware::coalesce_requests`.
+
+use crate::{
+    body::{Body, Bytes, SharedBody},
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    request::Request,
+    response::{Builder, Response},
+};
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use http_body::Body as HttpBody;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_CAPACITY: u64 = 67_108_864;
+const DEFAULT_MAX_ENTRY_SIZE: u64 = 1_048_576;
+
+/// Status codes cacheable by default, RFC 7231 section 6.1
+const CACHEABLE_STATUS: [u16; 10] = [200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct CacheKey {
+    method: Method,
+    host: Option<String>,
+    path: String,
+    vary: Vec<Option<HeaderValue>>,
+}
+
+struct CacheEntry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    last_used: u64,
+    /// How long the entry is replayed past its ttl while it is refreshed
+    stale_for: Duration,
+    revalidating: bool,
+}
+
+impl CacheEntry {
+    fn size(&self) -> u64 {
+        let headers: usize = self.headers.iter().map(|(name, value)| name.as_str().len() + value.len()).sum();
+        (self.body.len() + headers) as u64
+    }
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, CacheEntry>,
+    size: u64,
+    clock: u64,
+    /// Keys being computed by a request other requests wait for, resolving
+    /// once it is done. It resolves to `Ok` when the response isn't
+    /// storable, and is canceled otherwise
+    in_flight: HashMap<CacheKey, Shared<oneshot::Receiver<()>>>,
+}
+
+/// How a miss is handled when requests are coalesced
+enum Flight {
+    /// Invoke the handler, the others waiting until the guard is dropped
+    Leader(FlightGuard),
+    /// Wait for the leader, then look the cache up again
+    Follower(Shared<oneshot::Receiver<()>>),
+}
+
+/// Ends the flight of a key when dropped, however the leader finished
+struct FlightGuard {
+    inner: Arc<Mutex<CacheInner>>,
+    key: CacheKey,
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl FlightGuard {
+    /// Send the followers to the handler on their own, rather than have one
+    /// of them take over, the response not being storable
+    fn release_followers(mut self) {
+        if let Some(done) = self.done.take() {
+            let _ = done.send(());
+        }
+    }
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        // The sender is dropped right after this, waking the followers for
+        // one of them to take over if nothing was stored
+        self.inner.lock().in_flight.remove(&self.key);
+    }
+}
+
+impl CacheInner {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.size();
+        }
+    }
+
+    /// Evict the least recently used entries until `needed` more bytes fit
+    /// in `max_capacity`
+    fn make_room(&mut self, needed: u64, max_capacity: u64) {
+        while self.size + needed > max_capacity {
+            let lru = match self.entries.iter().min_by_key(|(_, e)| e.last_used) {
+                Some((key, _)) => key.clone(),
+                None => return,
+            };
+            self.remove(&lru);
+        }
+    }
+}
+
+/// Middleware caching the responses of the routes it is applied to.
+///
+/// Only `GET` and `HEAD` requests are cached, and only when the response has
+/// a cacheable status, a body of a known size of at most `max_entry_size`,
+/// no `Set-Cookie`, no `Cache-Control: no-store` or `private`, and no
+/// `Vary` on a request header other than the ones given to `vary_on`. Requests
+/// with an `Authorization` header or a `Cache-Control: no-store` always go
+/// to the handler. Once the cache grows past `max_capacity` bytes, the least
+/// recently used responses are evicted. Replayed responses carry an `Age`
+/// header.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::response_cache::ResponseCacheMiddleware;
+/// # use std::time::Duration;
+/// let cache = ResponseCacheMiddleware::new()
+///     .ttl(Duration::from_secs(30))
+///     .vary_on(header::ACCEPT_LANGUAGE);
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(cache, vec!["/catalog/**"], None))
+///     .build();
+/// ```
+pub struct ResponseCacheMiddleware {
+    inner: Arc<Mutex<CacheInner>>,
+    ttl: Duration,
+    max_capacity: u64,
+    max_entry_size: u64,
+    stale_while_revalidate: Duration,
+    vary: Vec<header::HeaderName>,
+    coalesce_requests: bool,
+}
+
+impl Default for ResponseCacheMiddleware {
+    fn default() -> Self {
+        ResponseCacheMiddleware {
+            inner: Arc::new(Mutex::new(CacheInner::default())),
+            ttl: DEFAULT_TTL,
+            max_capacity: DEFAULT_MAX_CAPACITY,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            stale_while_revalidate: Duration::from_secs(0),
+            vary: Vec::new(),
+            coalesce_requests: false,
+        }
+    }
+}
+
+impl ResponseCacheMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a response is served from the cache, 60 seconds by default
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Total size of the cached responses, 64 MiB by default
+    pub fn max_capacity(mut self, size: u64) -> Self {
+        self.max_capacity = size;
+        self
+    }
+
+    /// Size of the biggest body stored, 1 MiB by default
+    pub fn max_entry_size(mut self, size: u64) -> Self {
+        self.max_entry_size = size;
+        self
+    }
+
+    /// How long an expired response is still replayed while the handler is
+    /// invoked in the background to refresh it, each entry being refreshed
+    /// once at a time. A `stale-while-revalidate` directive in the
+    /// `Cache-Control` of the response takes precedence. Replayed expired
+    /// responses carry a `Warning: 110 - "Response is Stale"`. Disabled by
+    /// default
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = window;
+        self
+    }
+
+    /// Cache a different response for each value of the `name` request
+    /// header. These headers are announced with `Vary` on every response.
+    /// Responses varying on a header not given here aren't stored
+    pub fn vary_on(mut self, name: header::HeaderName) -> Self {
+        self.vary.push(name);
+        self
+    }
+
+    /// Let a single request invoke the handler when concurrent requests miss
+    /// the same response, the others waiting to be served what it cached.
+    /// This keeps a burst of requests for a response that isn't cached yet,
+    /// or just expired, from invoking the handler for each of them. When the
+    /// response turns out not to be storable, the waiting requests go to the
+    /// handler on their own, and when the request fails or is dropped before
+    /// its response is stored, one of them takes over. Disabled by default
+    pub fn coalesce_requests(mut self, enabled: bool) -> Self {
+        self.coalesce_requests = enabled;
+        self
+    }
+
+    /// Number of cached responses
+    pub fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached response for `key`, and whether it is stale and must be
+    /// refreshed by the caller
+    fn lookup(&self, key: &CacheKey) -> Option<(Response, bool)> {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let ttl = self.ttl;
+        let expired = match inner.entries.get_mut(key) {
+            Some(entry) if entry.stored_at.elapsed() < ttl + entry.stale_for => {
+                entry.last_used = clock;
+                let stale = entry.stored_at.elapsed() >= ttl;
+                let revalidate = stale && !entry.revalidating;
+                if revalidate {
+                    entry.revalidating = true;
+                }
+                let mut builder = Builder::new().status(entry.status);
+                for (name, value) in entry.headers.iter() {
+                    builder = builder.header(name, value.clone());
+                }
+                if stale {
+                    builder = builder.warning(110, "-", "Response is Stale");
+                }
+                return builder
+                    .header(header::AGE, entry.stored_at.elapsed().as_secs())
+                    .extension(SharedBody)
+                    .body(entry.body.clone())
+                    .build()
+                    .ok()
+                    .map(|res| (res, revalidate));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            inner.remove(key);
+        }
+        None
+    }
+
+    fn store(&self, key: CacheKey, status: StatusCode, headers: HeaderMap, body: Bytes) {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let stale_for = directive_secs(&headers, "stale-while-revalidate")
+            .map(Duration::from_secs)
+            .unwrap_or(self.stale_while_revalidate);
+        let entry = CacheEntry {
+            status,
+            headers,
+            body,
+            stored_at: Instant::now(),
+            last_used: inner.clock,
+            stale_for,
+            revalidating: false,
+        };
+        let size = entry.size();
+        if size > self.max_capacity {
+            return;
+        }
+
+        inner.remove(&key);
+        inner.make_room(size, self.max_capacity);
+        inner.size += size;
+        inner.entries.insert(key, entry);
+    }
+
+    async fn next_inner(&'static self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let req = ctx.state.request_unchecked();
+        let cacheable_request = (req.method() == Method::GET || req.method() == Method::HEAD)
+            && !req.headers().contains_key(header::AUTHORIZATION)
+            && !has_directive(req.headers(), &["no-store"]);
+        if !cacheable_request {
+            return chain.next(ctx).await;
+        }
+
+        let host = match req.uri().authority() {
+            Some(authority) => Some(authority.as_str()),
+            None => req.headers().get(header::HOST).and_then(|h| h.to_str().ok()),
+        };
+        let key = CacheKey {
+            method: req.method().clone(),
+            host: host.map(|h| h.to_ascii_lowercase()),
+            path: req.uri().path_and_query().map(|p| p.as_str()).unwrap_or_else(|| req.uri().path()).to_string(),
+            vary: self.vary.iter().map(|name| req.headers().get(name).cloned()).collect(),
+        };
+        // Held until the response is stored, so followers find it once woken
+        let mut flight = None;
+        loop {
+            if let Some((res, revalidate)) = self.lookup(&key) {
+                if revalidate {
+                    self.revalidate(key, &ctx, chain);
+                }
+                ctx.after(res);
+                return Ok(ctx);
+            }
+            if !self.coalesce_requests {
+                break;
+            }
+
+            match self.join_flight(&key) {
+                Flight::Leader(guard) => {
+                    flight = Some(guard);
+                    break;
+                }
+                Flight::Follower(done) => {
+                    // A canceled flight stored nothing, retried to find the
+                    // response or take over
+                    if done.await.is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let ctx = chain.next(ctx).await?;
+        self.store_response(key, ctx, flight).await
+    }
+
+    /// Wait for the request already computing `key`, or become the one
+    fn join_flight(&self, key: &CacheKey) -> Flight {
+        let mut inner = self.inner.lock();
+        if let Some(done) = inner.in_flight.get(key) {
+            return Flight::Follower(done.clone());
+        }
+
+        let (done, waiting) = oneshot::channel();
+        inner.in_flight.insert(key.clone(), waiting.shared());
+        Flight::Leader(FlightGuard {
+            inner: self.inner.clone(),
+            key: key.clone(),
+            done: Some(done),
+        })
+    }
+
+    /// Invoke the handler in the background for a copy of the request of
+    /// `ctx`, to refresh the stale entry of `key`
+    fn revalidate(&'static self, key: CacheKey, ctx: &HttpContext, chain: &dyn MiddlewareChain) {
+        let req = ctx.state.request_unchecked();
+        let copy = http::Request::builder()
+            .method(req.method().clone())
+            .uri(req.uri().clone())
+            .version(req.version())
+            .body(Body::empty());
+        let (mut copy, router) = match (copy, ctx.router.clone()) {
+            (Ok(copy), Some(router)) => (copy, router),
+            _ => return self.end_revalidation(&key),
+        };
+        *copy.headers_mut() = req.headers().clone();
+        let refresh = chain.next(HttpContext::new(Request::new(copy, req.peer_addr().copied()), router));
+
+        tokio::spawn(async move {
+            let refreshed = match refresh.await {
+                Ok(ctx) => self.store_response(key.clone(), ctx, None).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = refreshed {
+                debug!("Unable to refresh a stale cached response: {:?}", e);
+            }
+            // The stale entry stays when nothing replaced it, to be refreshed
+            // by a later request
+            self.end_revalidation(&key);
+        });
+    }
+
+    fn end_revalidation(&self, key: &CacheKey) {
+        if let Some(entry) = self.inner.lock().entries.get_mut(key) {
+            entry.revalidating = false;
+        }
+    }
+
+    /// Store the response of `ctx` under `key` when it can be cached,
+    /// dropping the previous response otherwise. The requests waiting on
+    /// `flight` are released once it is done
+    async fn store_response(&self, key: CacheKey, mut ctx: HttpContext, flight: Option<FlightGuard>) -> Result<HttpContext, SaphirError> {
+        let res = match ctx.state.take_response() {
+            Some(res) => res,
+            None => return Ok(ctx),
+        };
+        let res = self.add_vary(res)?;
+        if !self.is_storable(&res) {
+            self.inner.lock().remove(&key);
+            if let Some(flight) = flight {
+                flight.release_followers();
+            }
+            ctx.after(res);
+            return Ok(ctx);
+        }
+
+        let (parts, body) = res.into_raw()?.into_parts();
+        let body = hyper::body::to_bytes(body.into_raw()).await?;
+        self.store(key, parts.status, parts.headers.clone(), body.clone());
+        let mut builder = Builder::new().status(parts.status);
+        for (name, value) in parts.headers.iter() {
+            builder = builder.header(name, value.clone());
+        }
+        // The body is the one stored
+        ctx.after(builder.extension(SharedBody).body(body).build()?);
+        Ok(ctx)
+    }
+
+    fn add_vary(&self, mut res: Response) -> Result<Response, SaphirError> {
+        if !self.vary.is_empty() {
+            let vary = self.vary.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ");
+            res.headers_mut().append(header::VARY, HeaderValue::from_str(&vary)?);
+        }
+        Ok(res)
+    }
+
+    fn is_storable(&self, res: &Response) -> bool {
+        CACHEABLE_STATUS.contains(&res.status().as_u16())
+            && res.cookies().iter().next().is_none()
+            && !res.headers().contains_key(header::SET_COOKIE)
+            && !has_directive(res.headers(), &["no-store", "private"])
+            && self.varies_on_the_key(res.headers())
+            && res.body().size_hint().exact().filter(|size| *size <= self.max_entry_size).is_some()
+    }
+
+    /// Whether the `Vary` headers of a response only name request headers
+    /// the key is made of, a `*` never being
+    fn varies_on_the_key(&self, headers: &HeaderMap) -> bool {
+        headers.get_all(header::VARY).iter().all(|h| match h.to_str() {
+            Ok(h) => h
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .all(|field| self.vary.iter().any(|name| name.as_str().eq_ignore_ascii_case(field))),
+            Err(_) => false,
+        })
+    }
+}
+
+impl Middleware for ResponseCacheMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+/// Whether the `Cache-Control` headers hold one of `directives`
+fn has_directive(headers: &HeaderMap, directives: &[&str]) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|d| d.split('=').next().unwrap_or_default().trim())
+        .any(|d| directives.iter().any(|directive| d.eq_ignore_ascii_case(directive)))
+}
+
+/// Value in seconds of the `directive=<seconds>` of the `Cache-Control`
+/// headers
+fn directive_secs(headers: &HeaderMap, directive: &str) -> Option<u64> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|d| {
+            let mut d = d.splitn(2, '=');
+            match (d.next(), d.next()) {
+                (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case(directive) => value.trim().trim_matches('"').parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, request::Request, router::Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Served {
+        calls: Arc<AtomicUsize>,
+        router: Router,
+    }
+
+    fn router(cache_control: &'static str) -> Served {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handler = move |req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            let lang = req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .map(|l| l.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            async move {
+                Builder::new()
+                    .status(200)
+                    .header(header::CACHE_CONTROL, cache_control)
+                    .body(format!("catalog {} #{}", lang, call))
+            }
+        };
+        Served {
+            calls,
+            router: Router::builder().route("/catalog", Method::GET, handler).build(),
+        }
+    }
+
+    async fn get(middleware: &'static ResponseCacheMiddleware, router: &Router, lang: &str) -> (String, Option<HeaderValue>) {
+        let (body, headers) = get_with_headers(middleware, router, lang).await;
+        (body, headers.get(header::AGE).cloned())
+    }
+
+    async fn get_with_headers(middleware: &'static ResponseCacheMiddleware, router: &Router, lang: &str) -> (String, HeaderMap) {
+        let req = http::Request::builder()
+            .uri("/catalog")
+            .header(header::ACCEPT_LANGUAGE, lang)
+            .body(Body::empty())
+            .unwrap();
+        let mut ctx = middleware
+            .next_inner(HttpContext::new(Request::new(req, None), router.clone()), &MiddleChainEnd)
+            .await
+            .unwrap();
+        let res = ctx.state.take_response().unwrap();
+        let headers = res.headers().clone();
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), headers)
+    }
+
+    #[tokio::test]
+    async fn second_request_is_served_from_the_cache() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().vary_on(header::ACCEPT_LANGUAGE)));
+        let served = router("public");
+
+        assert_eq!(get(middleware, &served.router, "fr").await, ("catalog fr #1".to_string(), None));
+        let (body, age) = get(middleware, &served.router, "fr").await;
+        assert_eq!(body, "catalog fr #1");
+        assert!(age.is_some());
+        assert_eq!(served.calls.load(Ordering::SeqCst), 1);
+
+        // Another value of a header the responses vary on is another entry
+        assert_eq!(get(middleware, &served.router, "en").await.0, "catalog en #2");
+        assert_eq!(served.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(middleware.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_store_responses_are_not_cached() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new()));
+        let served = router("no-store");
+
+        assert_eq!(get(middleware, &served.router, "fr").await.0, "catalog fr #1");
+        assert_eq!(get(middleware, &served.router, "fr").await.0, "catalog fr #2");
+        assert!(middleware.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_responses_go_back_to_the_handler() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().ttl(Duration::from_millis(20))));
+        let served = router("public");
+
+        assert_eq!(get(middleware, &served.router, "fr").await.0, "catalog fr #1");
+        tokio::time::delay_for(Duration::from_millis(40)).await;
+        assert_eq!(get(middleware, &served.router, "fr").await.0, "catalog fr #2");
+    }
+
+    #[tokio::test]
+    async fn stale_response_is_served_while_it_is_refreshed() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().ttl(Duration::from_millis(20))));
+        let served = router("public, stale-while-revalidate=60");
+
+        let (body, headers) = get_with_headers(middleware, &served.router, "fr").await;
+        assert_eq!(body, "catalog fr #1");
+        assert!(headers.get(header::WARNING).is_none());
+        tokio::time::delay_for(Duration::from_millis(40)).await;
+
+        // The stale hit doesn't wait for the handler, which runs in the background
+        let (body, headers) = get_with_headers(middleware, &served.router, "fr").await;
+        assert_eq!(body, "catalog fr #1");
+        assert_eq!(headers[header::WARNING], "110 - \"Response is Stale\"");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while served.calls.load(Ordering::SeqCst) < 2 || middleware.inner.lock().entries.values().any(|e| e.revalidating) {
+                tokio::time::delay_for(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(get(middleware, &served.router, "fr").await.0, "catalog fr #2");
+        assert_eq!(served.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_invoke_the_handler_once() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().coalesce_requests(true)));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handler = move |_req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                tokio::time::delay_for(Duration::from_millis(50)).await;
+                format!("catalog #{}", call)
+            }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+
+        let responses = futures::future::join_all((0..8).map(|_| get(middleware, &router, "fr"))).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(responses.iter().all(|(body, _)| body == "catalog #1"));
+        assert!(middleware.inner.lock().in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn responses_are_cached_per_host() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new()));
+        let handler = |req: Request<Body>| {
+            let host = req.headers()[header::HOST].to_str().unwrap().to_string();
+            async move { format!("catalog of {}", host) }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+        let get = |host: &'static str| {
+            let router = router.clone();
+            async move {
+                let req = http::Request::builder().uri("/catalog").header(header::HOST, host).body(Body::empty()).unwrap();
+                let mut ctx = middleware
+                    .next_inner(HttpContext::new(Request::new(req, None), router), &MiddleChainEnd)
+                    .await
+                    .unwrap();
+                let res = ctx.state.take_response().unwrap();
+                hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap()
+            }
+        };
+
+        assert_eq!(get("a.example.com").await, "catalog of a.example.com");
+        assert_eq!(get("b.example.com").await, "catalog of b.example.com");
+        assert_eq!(get("A.example.com").await, "catalog of a.example.com");
+        assert_eq!(middleware.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn responses_varying_on_other_headers_are_not_cached() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().vary_on(header::ACCEPT_LANGUAGE)));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handler = move |_req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Builder::new().header(header::VARY, "Accept-Encoding").body(format!("catalog #{}", call)) }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+
+        assert_eq!(get(middleware, &router, "fr").await.0, "catalog #1");
+        assert_eq!(get(middleware, &router, "fr").await.0, "catalog #2");
+        assert!(middleware.is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VARY, HeaderValue::from_static("accept-language"));
+        assert!(middleware.varies_on_the_key(&headers));
+        headers.insert(header::VARY, HeaderValue::from_static("*"));
+        assert!(!middleware.varies_on_the_key(&headers));
+    }
+
+    #[tokio::test]
+    async fn a_waiting_request_takes_over_a_dropped_one() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(ResponseCacheMiddleware::new().coalesce_requests(true)));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handler = move |_req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                let delay = if call == 1 { 10_000 } else { 20 };
+                tokio::time::delay_for(Duration::from_millis(delay)).await;
+                format!("catalog #{}", call)
+            }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+
+        let dropped = tokio::time::timeout(Duration::from_millis(50), get(middleware, &router, "fr"));
+        let waiting = futures::future::join_all((0..4).map(|_| async {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            get(middleware, &router, "fr").await
+        }));
+        let (dropped, responses) = futures::future::join(dropped, waiting).await;
+        assert!(dropped.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(responses.iter().all(|(body, _)| body == "catalog #2"));
+    }
+
+    #[tokio::test]
+    async fn stale_responses_found_by_waiting_requests_are_refreshed() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(
+            ResponseCacheMiddleware::new()
+                .ttl(Duration::from_secs(0))
+                .stale_while_revalidate(Duration::from_secs(60))
+                .coalesce_requests(true),
+        ));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let handler = move |_req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+                format!("catalog #{}", call)
+            }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+
+        // Every request waiting on the first finds its response already stale
+        let responses = futures::future::join_all((0..4).map(|_| get(middleware, &router, "fr"))).await;
+        assert!(responses.iter().all(|(body, _)| body == "catalog #1"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while calls.load(Ordering::SeqCst) < 2 || middleware.inner.lock().entries.values().any(|e| e.revalidating) {
+                tokio::time::delay_for(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refreshes_not_stored_can_be_retried() {
+        let middleware: &'static ResponseCacheMiddleware = Box::leak(Box::new(
+            ResponseCacheMiddleware::new()
+                .ttl(Duration::from_millis(20))
+                .stale_while_revalidate(Duration::from_secs(60))
+                .max_capacity(64),
+        ));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        // The refreshed responses are too big for the cache
+        let handler = move |_req: Request<Body>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { format!("catalog #{}{}", call, if call == 1 { String::new() } else { " ".repeat(64) }) }
+        };
+        let router = Router::builder().route("/catalog", Method::GET, handler).build();
+
+        assert_eq!(get(middleware, &router, "fr").await.0, "catalog #1");
+        for refreshes in 2..4 {
+            tokio::time::delay_for(Duration::from_millis(40)).await;
+            assert_eq!(get(middleware, &router, "fr").await.0, "catalog #1");
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while calls.load(Ordering::SeqCst) < refreshes || middleware.inner.lock().entries.values().any(|e| e.revalidating) {
+                    tokio::time::delay_for(Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let middleware = ResponseCacheMiddleware::new().max_capacity(10);
+        let key = |path: &str| CacheKey {
+            method: Method::GET,
+            host: None,
+            path: path.to_string(),
+            vary: Vec::new(),
+        };
+        middleware.store(key("/a"), StatusCode::OK, HeaderMap::new(), Bytes::from("aaaa"));
+        middleware.store(key("/b"), StatusCode::OK, HeaderMap::new(), Bytes::from("bbbb"));
+        assert!(middleware.lookup(&key("/a")).is_some());
+
+        middleware.store(key("/c"), StatusCode::OK, HeaderMap::new(), Bytes::from("cccc"));
+        assert!(middleware.lookup(&key("/a")).is_some());
+        assert!(middleware.lookup(&key("/b")).is_none());
+        assert!(middleware.lookup(&key("/c")).is_some());
+    }
+}
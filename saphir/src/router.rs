@@ -21,25 +21,208 @@ use crate::{
     utils::{EndpointResolver, EndpointResolverResult},
 };
 use futures::{future::BoxFuture, FutureExt};
-use http::Method;
-use std::{collections::HashMap, sync::Arc};
+use http::{Method, StatusCode, Uri};
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+#[cfg(feature = "json")]
+use crate::{body::JsonConfig, template::TemplateEngine};
+
+type PathRewrite = Box<dyn Fn(&mut Request<Body>) -> Option<String> + Send + Sync>;
 
 /// Builder type for the router
 pub struct Builder<Chain: RouterChain + Send + Unpin + 'static + Sync> {
     resolver: HashMap<String, EndpointResolver>,
+    host_resolvers: HashMap<String, HashMap<String, EndpointResolver>>,
+    current_host: Option<String>,
     chain: Chain,
+    max_path_segments: Option<usize>,
+    path_rewrites: Vec<PathRewrite>,
+    duplicate_routes: Vec<(Method, String)>,
+    allowed_hosts: Vec<String>,
+    default_status: StatusCode,
+    trusted_proxies: Vec<IpAddr>,
+    #[cfg(feature = "json")]
+    template_engine: Option<Arc<dyn TemplateEngine>>,
+    #[cfg(feature = "json")]
+    json_config: JsonConfig,
 }
 
 impl Default for Builder<RouterChainEnd> {
     fn default() -> Self {
         Self {
             resolver: Default::default(),
+            host_resolvers: Default::default(),
+            current_host: None,
             chain: RouterChainEnd { handlers: Default::default() },
+            max_path_segments: None,
+            path_rewrites: Vec::new(),
+            duplicate_routes: Vec::new(),
+            allowed_hosts: Vec::new(),
+            default_status: StatusCode::OK,
+            trusted_proxies: Vec::new(),
+            #[cfg(feature = "json")]
+            template_engine: None,
+            #[cfg(feature = "json")]
+            json_config: JsonConfig::default(),
         }
     }
 }
 
 impl<Controllers: 'static + RouterChain + Unpin + Send + Sync> Builder<Controllers> {
+    /// Set the maximum number of path segments the router will process.
+    /// Requests with a deeper path are rejected with a `414 URI Too Long`
+    /// without being matched against any route. Unlimited by default.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// builder.max_path_segments(32);
+    /// ```
+    pub fn max_path_segments<I: Into<Option<usize>>>(mut self, max: I) -> Self {
+        self.max_path_segments = max.into();
+        self
+    }
+
+    /// Only serve requests addressed to `host`, compared without its port and
+    /// ignoring case. Once a host is allowed, requests for any other host are
+    /// rejected with a `421 Misdirected Request`, and requests without a valid
+    /// host with a `400 Bad Request`. All hosts are served by default.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// builder.allow_host("example.com").allow_host("www.example.com");
+    /// ```
+    pub fn allow_host(mut self, host: &str) -> Self {
+        self.allowed_hosts.push(host.to_string());
+        self
+    }
+
+    /// Status sent by handlers whose responder doesn't set one, `200 OK` by
+    /// default. In debug builds, responses relying on it without a built-in
+    /// body responder asking for it are logged as a warning, since it usually
+    /// means a custom `Responder` forgot to set its status.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// builder.default_status(StatusCode::NO_CONTENT);
+    /// ```
+    pub fn default_status(mut self, status: StatusCode) -> Self {
+        self.default_status = status;
+        self
+    }
+
+    /// Addresses of the proxies trusted to add the `Forwarded` header. The
+    /// client address and protocol of requests coming through them are taken
+    /// from the header, see `HttpContext::client_addr`. No proxy is trusted
+    /// by default.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// builder.trusted_proxies(vec!["10.0.0.1".parse().unwrap()]);
+    /// ```
+    pub fn trusted_proxies<I: IntoIterator<Item = IpAddr>>(mut self, proxies: I) -> Self {
+        self.trusted_proxies.extend(proxies);
+        self
+    }
+
+    /// Template engine used by `Builder::render` to produce HTML responses,
+    /// see `template::TemplateEngine`
+    #[cfg(feature = "json")]
+    pub fn template_engine<E: 'static + TemplateEngine>(mut self, engine: E) -> Self {
+        self.template_engine = Some(Arc::new(engine));
+        self
+    }
+
+    /// How `Json` responders serialize their value, unless they carry their
+    /// own config. Compact, with fields named as serialized, by default
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// # use saphir::body::json::{FieldCasing, JsonConfig};
+    /// #
+    /// # let builder = RBuilder::default();
+    /// builder.json_config(JsonConfig::new().field_casing(FieldCasing::CamelCase).skip_nulls(true));
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn json_config(mut self, config: JsonConfig) -> Self {
+        self.json_config = config;
+        self
+    }
+
+    /// Register routes and controllers served only for requests addressed to
+    /// `host`, so a single server can serve several sites with different
+    /// route tables. Requests for a host without routes of its own are matched
+    /// against the routes registered outside of any host.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// async fn api_users(req: Request<Body>) -> impl Responder { 200 }
+    /// async fn home(req: Request<Body>) -> impl Responder { 200 }
+    ///
+    /// builder
+    ///     .host("api.example.com", |r| r.route("/users", Method::GET, api_users))
+    ///     .route("/", Method::GET, home);
+    /// ```
+    pub fn host<F, NewChain>(mut self, host: &str, routes: F) -> Builder<NewChain>
+    where
+        F: FnOnce(Self) -> Builder<NewChain>,
+        NewChain: RouterChain + Send + Unpin + Sync + 'static,
+    {
+        let previous = self.current_host.replace(host.to_ascii_lowercase());
+        let mut builder = routes(self);
+        builder.current_host = previous;
+        builder
+    }
+
+    /// Rewrite the request path before it is matched against the routes.
+    ///
+    /// The closure receives the request and returns the new path, or `None`
+    /// to leave it untouched. The query string is kept as is. Rewrites run in
+    /// the order they were added, after the `max_path_segments` check.
+    ///
+    /// ```rust
+    /// # use saphir::router::Builder as RBuilder;
+    /// # use saphir::prelude::*;
+    /// #
+    /// # let builder = RBuilder::default();
+    /// struct Locale(String);
+    ///
+    /// // Serve `/en/foo` and `/fr/foo` with the handler of `/foo`
+    /// builder.rewrite_path(|req| {
+    ///     let path = req.uri().path().to_string();
+    ///     let mut segments = path.splitn(3, '/').skip(1);
+    ///     match (segments.next(), segments.next()) {
+    ///         (Some(locale), rest) if locale == "en" || locale == "fr" => {
+    ///             req.extensions_mut().insert(Locale(locale.to_string()));
+    ///             Some(format!("/{}", rest.unwrap_or_default()))
+    ///         }
+    ///         _ => None,
+    ///     }
+    /// });
+    /// ```
+    pub fn rewrite_path<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&mut Request<Body>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.path_rewrites.push(Box::new(rewrite));
+        self
+    }
+
     /// Add a simple request handle to a given path
     ///
     /// ```rust
@@ -59,15 +242,7 @@ impl<Controllers: 'static + RouterChain + Unpin + Send + Sync> Builder<Controlle
     where
         H: 'static + DynHandler<Body> + Send + Sync,
     {
-        let endpoint_id = if let Some(er) = self.resolver.get_mut(route) {
-            er.add_method(method.clone());
-            er.id()
-        } else {
-            let er = EndpointResolver::new(route, method.clone()).expect("Unable to construct endpoint resolver");
-            let er_id = er.id();
-            self.resolver.insert(route.to_string(), er);
-            er_id
-        };
+        let endpoint_id = self.endpoint_id(route, &method);
 
         self.chain
             .add_handler(endpoint_id, method, Box::new(handler), crate::guard::Builder::default().build());
@@ -104,15 +279,7 @@ impl<Controllers: 'static + RouterChain + Unpin + Send + Sync> Builder<Controlle
         F: FnOnce(GuardBuilder<GuardChainEnd>) -> GuardBuilder<Chain>,
         Chain: GuardChain + 'static,
     {
-        let endpoint_id = if let Some(er) = self.resolver.get_mut(route) {
-            er.add_method(method.clone());
-            er.id()
-        } else {
-            let er = EndpointResolver::new(route, method.clone()).expect("Unable to construct endpoint resolver");
-            let er_id = er.id();
-            self.resolver.insert(route.to_string(), er);
-            er_id
-        };
+        let endpoint_id = self.endpoint_id(route, &method);
 
         self.chain
             .add_handler(endpoint_id, method, Box::new(handler), guards(GuardBuilder::default()).build());
@@ -142,36 +309,110 @@ impl<Controllers: 'static + RouterChain + Unpin + Send + Sync> Builder<Controlle
         let mut handlers = HashMap::new();
         for (method, subroute, handler, guard_chain) in controller.handlers() {
             let route = format!("{}{}", C::BASE_PATH, subroute);
-            let endpoint_id = if let Some(er) = self.resolver.get_mut(&route) {
-                er.add_method(method.clone());
-                er.id()
-            } else {
-                let er = EndpointResolver::new(&route, method.clone()).expect("Unable to construct endpoint resolver");
-                let er_id = er.id();
-                self.resolver.insert(route, er);
-                er_id
-            };
-
+            let endpoint_id = self.endpoint_id(&route, &method);
             handlers.insert((endpoint_id, method), (handler, guard_chain));
         }
 
         Builder {
             resolver: self.resolver,
+            host_resolvers: self.host_resolvers,
+            current_host: self.current_host,
             chain: RouterChainLink {
                 controller,
                 handlers,
                 rest: self.chain,
             },
+            max_path_segments: self.max_path_segments,
+            path_rewrites: self.path_rewrites,
+            duplicate_routes: self.duplicate_routes,
+            allowed_hosts: self.allowed_hosts,
+            default_status: self.default_status,
+            trusted_proxies: self.trusted_proxies,
+            #[cfg(feature = "json")]
+            template_engine: self.template_engine,
+            #[cfg(feature = "json")]
+            json_config: self.json_config,
         }
     }
 
+    /// Get the id of the endpoint matching `route`, creating it if needed.
+    /// Registering the same method twice on a route is remembered so the
+    /// server can refuse to start.
+    fn endpoint_id(&mut self, route: &str, method: &Method) -> u64 {
+        let resolver = match &self.current_host {
+            Some(host) => self.host_resolvers.entry(host.clone()).or_default(),
+            None => &mut self.resolver,
+        };
+
+        if let Some(er) = resolver.get_mut(route) {
+            if er.has_method(method) {
+                let route = match &self.current_host {
+                    Some(host) => format!("{} (host {})", route, host),
+                    None => route.to_string(),
+                };
+                self.duplicate_routes.push((method.clone(), route));
+            }
+            er.add_method(method.clone());
+            er.id()
+        } else {
+            let er = EndpointResolver::new(route, method.clone()).expect("Unable to construct endpoint resolver");
+            let er_id = er.id();
+            resolver.insert(route.to_string(), er);
+            er_id
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), SaphirError> {
+        if self.duplicate_routes.is_empty() {
+            return Ok(());
+        }
+
+        // A route registered more than twice is reported once
+        let mut routes = Vec::new();
+        for (method, route) in &self.duplicate_routes {
+            let route = format!("{} {}", method, route);
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+        let routes = routes.join(", ");
+        Err(SaphirError::Other(format!("Duplicate route registration: {}", routes)))
+    }
+
     pub(crate) fn build(self) -> Router {
-        let Builder { resolver, chain: controllers } = self;
+        let Builder {
+            resolver,
+            host_resolvers,
+            chain: controllers,
+            max_path_segments,
+            path_rewrites,
+            allowed_hosts,
+            default_status,
+            trusted_proxies,
+            #[cfg(feature = "json")]
+            template_engine,
+            #[cfg(feature = "json")]
+            json_config,
+            ..
+        } = self;
 
         Router {
             inner: Arc::new(RouterInner {
                 resolvers: resolver.into_iter().map(|(_, e)| e).collect(),
+                host_resolvers: host_resolvers
+                    .into_iter()
+                    .map(|(host, resolver)| (host, resolver.into_values().collect()))
+                    .collect(),
                 chain: Box::new(controllers),
+                max_path_segments,
+                path_rewrites,
+                allowed_hosts,
+                default_status,
+                trusted_proxies,
+                #[cfg(feature = "json")]
+                template_engine,
+                #[cfg(feature = "json")]
+                json_config,
             }),
         }
     }
@@ -179,7 +420,17 @@ impl<Controllers: 'static + RouterChain + Unpin + Send + Sync> Builder<Controlle
 
 struct RouterInner {
     resolvers: Vec<EndpointResolver>,
+    host_resolvers: HashMap<String, Vec<EndpointResolver>>,
     chain: Box<dyn RouterChain + Send + Unpin + Sync>,
+    max_path_segments: Option<usize>,
+    path_rewrites: Vec<PathRewrite>,
+    allowed_hosts: Vec<String>,
+    default_status: StatusCode,
+    trusted_proxies: Vec<IpAddr>,
+    #[cfg(feature = "json")]
+    template_engine: Option<Arc<dyn TemplateEngine>>,
+    #[cfg(feature = "json")]
+    json_config: JsonConfig,
 }
 
 #[doc(hidden)]
@@ -193,9 +444,48 @@ impl Router {
         Builder::default()
     }
 
+    pub(crate) fn trusted_proxies(&self) -> &[IpAddr] {
+        &self.inner.trusted_proxies
+    }
+
+    #[cfg(feature = "json")]
+    pub(crate) fn json_config(&self) -> &JsonConfig {
+        &self.inner.json_config
+    }
+
+    #[cfg(feature = "json")]
+    pub(crate) fn template_engine(&self) -> Option<&dyn TemplateEngine> {
+        self.inner.template_engine.as_deref()
+    }
+
     pub fn resolve(&self, req: &mut Request<Body>) -> Result<u64, u16> {
+        if !self.inner.allowed_hosts.is_empty() {
+            let host = req.host().ok_or(400u16)?;
+            if !self.inner.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+                return Err(421);
+            }
+        }
+
+        if let Some(max) = self.inner.max_path_segments {
+            // Stop counting as soon as the limit is exceeded to keep the work bounded
+            if req.uri().path().split('/').filter(|s| !s.is_empty()).nth(max).is_some() {
+                return Err(414);
+            }
+        }
+
+        for rewrite in &self.inner.path_rewrites {
+            if let Some(path) = rewrite(req) {
+                *req.uri_mut() = rewrite_uri_path(req.uri(), &path).ok_or(400u16)?;
+            }
+        }
+
+        let resolvers = req
+            .host()
+            .and_then(|host| self.inner.host_resolvers.get(&host.to_ascii_lowercase()))
+            .unwrap_or(&self.inner.resolvers);
+
         let mut method_not_allowed = false;
-        for endpoint_resolver in &self.inner.resolvers {
+        for endpoint_resolver in resolvers {
             match endpoint_resolver.resolve(req) {
                 EndpointResolverResult::InvalidPath => continue,
                 EndpointResolverResult::MethodNotAllowed => method_not_allowed = true,
@@ -225,9 +515,16 @@ impl Router {
         // # SAFETY #
         // The router is initialized in static memory when calling run on Server.
         let static_self = unsafe { std::mem::transmute::<&'_ Self, &'static Self>(self) };
-        let b = crate::response::Builder::new();
+        let b = crate::response::Builder::new().default_status(self.inner.default_status);
         let res = if let Some(responder) = static_self.inner.chain.dispatch(resolver_id, req) {
-            responder.await.dyn_respond(b, &ctx)
+            let b = responder.await.dyn_respond(b, &ctx);
+            if cfg!(debug_assertions) && b.has_implicit_status() {
+                warn!(
+                    "A handler response was built without a status, sending the default {}. Set it in the responder",
+                    self.inner.default_status
+                );
+            }
+            b
         } else {
             404.respond_with_builder(b, &ctx)
         }
@@ -240,6 +537,18 @@ impl Router {
     }
 }
 
+/// Replace the path of an uri, keeping its query
+fn rewrite_uri_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
 #[doc(hidden)]
 pub trait RouterChain {
     fn dispatch(&'static self, resolver_id: u64, req: Request<Body>) -> Option<BoxFuture<'static, Box<dyn DynResponder + Send>>>;
@@ -309,3 +618,239 @@ impl<C: Sync + Send, Rest: RouterChain + Sync + Send> RouterChain for RouterChai
         self.rest.add_handler(endpoint_id, method, handler, guards);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{ControllerEndpoint, EndpointsBuilder};
+
+    async fn handler(_req: Request<Body>) -> u16 {
+        200
+    }
+
+    fn request(path: &str) -> Request<Body> {
+        Request::new(http::Request::builder().method(Method::GET).uri(path).body(Body::empty()).unwrap(), None)
+    }
+
+    fn request_for_host(host: &str) -> Request<Body> {
+        Request::new(
+            http::Request::builder()
+                .method(Method::GET)
+                .uri("/foo")
+                .header(http::header::HOST, host)
+                .body(Body::empty())
+                .unwrap(),
+            None,
+        )
+    }
+
+    #[test]
+    fn host_is_validated() {
+        assert_eq!(request_for_host("www.example.com").host(), Some("www.example.com"));
+        assert_eq!(request_for_host("www.example.com:8080").host(), Some("www.example.com"));
+        assert_eq!(request_for_host("[::1]:8080").host(), Some("[::1]"));
+        assert_eq!(request("http://api.example.com/foo").host(), Some("api.example.com"));
+
+        for malformed in &[
+            "",
+            "www.example.com:",
+            "www.example.com:99999",
+            "user@example.com",
+            "exa mple.com",
+            "evil.com/x",
+            "[::zz]",
+            "a..b",
+        ] {
+            assert_eq!(request_for_host(malformed).host(), None, "{:?}", malformed);
+        }
+        assert_eq!(request("/foo").host(), None);
+    }
+
+    #[tokio::test]
+    async fn routes_are_resolved_by_host() {
+        async fn api(_req: Request<Body>) -> u16 {
+            201
+        }
+
+        async fn www(_req: Request<Body>) -> u16 {
+            202
+        }
+
+        let router = Router::builder()
+            .host("api.example.com", |r| r.route("/users", Method::GET, api))
+            .host("www.example.com", |r| r.route("/users", Method::GET, www).route("/about", Method::GET, www))
+            .route("/users", Method::GET, handler)
+            .build();
+
+        let status = |host: &str, path: &str| {
+            let req = Request::new(
+                http::Request::builder()
+                    .method(Method::GET)
+                    .uri(path)
+                    .header(http::header::HOST, host)
+                    .body(Body::empty())
+                    .unwrap(),
+                None,
+            );
+            let router = router.clone();
+            async move {
+                let mut ctx = router.clone().handle(HttpContext::new(req, router)).await.unwrap();
+                ctx.state.take_response().unwrap().status().as_u16()
+            }
+        };
+
+        assert_eq!(status("api.example.com", "/users").await, 201);
+        assert_eq!(status("WWW.example.com:8080", "/users").await, 202);
+        assert_eq!(status("www.example.com", "/about").await, 202);
+        assert_eq!(status("api.example.com", "/about").await, 404);
+        assert_eq!(status("other.example.com", "/users").await, 200);
+        assert_eq!(status("other.example.com", "/about").await, 404);
+    }
+
+    #[test]
+    fn allowed_hosts_reject_unknown_hosts() {
+        let router = Router::builder().allow_host("Example.com").route("/foo", Method::GET, handler).build();
+
+        assert!(router.resolve(&mut request_for_host("example.com:443")).is_ok());
+        assert_eq!(router.resolve(&mut request_for_host("exa mple.com")).err(), Some(400));
+        assert_eq!(router.resolve(&mut request("/foo")).err(), Some(400));
+        assert_eq!(router.resolve(&mut request_for_host("attacker.com")).err(), Some(421));
+    }
+
+    #[test]
+    fn max_path_segments_rejects_deep_path() {
+        let router = Router::builder().max_path_segments(8).route("/**", Method::GET, handler).build();
+
+        let deep_path = "/a".repeat(10_000);
+        assert_eq!(router.resolve(&mut request(&deep_path)).err(), Some(414));
+        assert_eq!(router.resolve(&mut request("/a/b/c/d/e/f/g/h/i")).err(), Some(414));
+        assert!(router.resolve(&mut request("/a/b/c/d/e/f/g/h")).is_ok());
+    }
+
+    #[test]
+    fn rewrite_path_routes_to_base_handler() {
+        struct Locale(String);
+
+        let router = Router::builder()
+            .rewrite_path(|req| {
+                let rest = req.uri().path().strip_prefix("/en/")?.to_string();
+                req.extensions_mut().insert(Locale("en".to_string()));
+                Some(format!("/{}", rest))
+            })
+            .route("/foo", Method::GET, handler)
+            .build();
+
+        let mut req = request("/en/foo?page=2");
+        assert!(router.resolve(&mut req).is_ok());
+        assert_eq!(req.uri().path(), "/foo");
+        assert_eq!(req.uri().query(), Some("page=2"));
+        assert_eq!(req.extensions().get::<Locale>().map(|l| l.0.as_str()), Some("en"));
+
+        assert!(router.resolve(&mut request("/foo")).is_ok());
+        assert_eq!(router.resolve(&mut request("/fr/foo")).err(), Some(404));
+    }
+
+    #[tokio::test]
+    async fn request_line_keeps_the_raw_target() {
+        let router = Router::builder()
+            .rewrite_path(|req| Some(format!("/{}", req.uri().path().strip_prefix("/en/")?)))
+            .route("/foo", Method::GET, handler)
+            .build();
+
+        let target = "/en/foo?page=2&next=%2E%2E%2Fadmin";
+        let ctx = HttpContext::new(request(target), router.clone());
+        let mut ctx = router.handle(ctx).await.unwrap();
+        assert_eq!(ctx.state.take_response().unwrap().status(), 200);
+        let line = ctx.request_line();
+        assert_eq!((line.method(), line.target(), line.version()), (&Method::GET, target, http::Version::HTTP_11));
+        assert_eq!(line.to_string(), "GET /en/foo?page=2&next=%2E%2E%2Fadmin HTTP/1.1");
+    }
+
+    #[test]
+    fn duplicate_route_fails_server_build() {
+        let res = crate::server::Server::builder()
+            .configure_router(|r| {
+                r.route("/users/{id}", Method::GET, handler)
+                    .route("/users/{id}", Method::POST, handler)
+                    .route("/users/{id}", Method::GET, handler)
+                    .route("/users/{id}", Method::GET, handler)
+            })
+            .try_build();
+
+        match res {
+            Err(SaphirError::Other(msg)) => assert_eq!(msg, "Duplicate route registration: GET /users/{id}"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("a duplicate route was accepted"),
+        }
+    }
+
+    struct UsersController;
+
+    impl UsersController {
+        async fn user(&self, _req: Request<Body>) -> u16 {
+            200
+        }
+    }
+
+    impl Controller for UsersController {
+        const BASE_PATH: &'static str = "/users";
+
+        fn handlers(&self) -> Vec<ControllerEndpoint<Self>> {
+            EndpointsBuilder::new()
+                .add(Method::GET, "/{id}", UsersController::user)
+                .add(Method::GET, "/{id}", UsersController::user)
+                .build()
+        }
+    }
+
+    #[test]
+    fn duplicate_controller_endpoint_is_reported_once() {
+        let res = crate::server::Server::builder().configure_router(|r| r.controller(UsersController)).try_build();
+
+        match res {
+            Err(SaphirError::Other(msg)) => assert_eq!(msg, "Duplicate route registration: GET /users/{id}"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("a duplicate endpoint was accepted"),
+        }
+    }
+
+    #[test]
+    fn max_path_segments_unlimited_by_default() {
+        let router = Router::builder().route("/**", Method::GET, handler).build();
+
+        let deep_path = "/a".repeat(10_000);
+        assert!(router.resolve(&mut request(&deep_path)).is_ok());
+    }
+
+    struct Unstatused;
+
+    impl Responder for Unstatused {
+        fn respond_with_builder(self, builder: crate::response::Builder, _ctx: &HttpContext) -> crate::response::Builder {
+            builder.body("no status")
+        }
+    }
+
+    async fn status_of(router: Router) -> u16 {
+        let mut ctx = router.clone().handle(HttpContext::new(request("/foo"), router)).await.unwrap();
+        ctx.state.take_response().unwrap().status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn responses_without_status_get_the_default_one() {
+        let unstatused = |_req: Request<Body>| async { Unstatused };
+        assert_eq!(status_of(Router::builder().route("/foo", Method::GET, unstatused).build()).await, 200);
+
+        let router = Router::builder()
+            .default_status(StatusCode::ACCEPTED)
+            .route("/foo", Method::GET, unstatused)
+            .build();
+        assert_eq!(status_of(router).await, 202);
+
+        let explicit = |_req: Request<Body>| async { (Unstatused, 201) };
+        let router = Router::builder()
+            .default_status(StatusCode::ACCEPTED)
+            .route("/foo", Method::GET, explicit)
+            .build();
+        assert_eq!(status_of(router).await, 201);
+    }
+}
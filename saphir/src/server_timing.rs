@@ -0,0 +1,124 @@
+//! `Server-Timing` metrics of a response.
+//!
+//! Handlers record timings with `Request::server_timing` and middlewares
+//! with `HttpContext::server_timing`. The `ServerTimingMiddleware` then sends
+//! them to the browser, which shows them in its developer tools next to the
+//! network timings of the request.
+
+use crate::{
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+};
+use futures::{future::BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use std::{sync::Arc, time::Duration};
+
+const SERVER_TIMING: &str = "server-timing";
+
+/// Timings recorded for a request, shared by the request and its context
+#[derive(Clone, Default)]
+pub struct ServerTimings {
+    timings: Arc<Mutex<Vec<(String, Duration)>>>,
+}
+
+impl ServerTimings {
+    /// Record that `name` took `duration`. The name must be a valid header
+    /// token, e.g. `db` or `cache-lookup`
+    pub fn record<N: Into<String>>(&self, name: N, duration: Duration) {
+        self.timings.lock().push((name.into(), duration));
+    }
+
+    /// The recorded timings, in the order they were recorded
+    pub fn timings(&self) -> Vec<(String, Duration)> {
+        self.timings.lock().clone()
+    }
+
+    /// Value of the `Server-Timing` header holding the timings, `None` when
+    /// nothing was recorded
+    pub fn header_value(&self) -> Option<String> {
+        let timings = self.timings.lock();
+        if timings.is_empty() {
+            return None;
+        }
+
+        let metrics: Vec<String> = timings.iter().map(|(name, duration)| format!("{};dur={}", name, millis(*duration))).collect();
+        Some(metrics.join(", "))
+    }
+}
+
+/// Milliseconds of `duration`, with at most 3 decimals and no trailing zeros
+fn millis(duration: Duration) -> String {
+    let millis = format!("{:.3}", duration.as_secs_f64() * 1000.0);
+    millis.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Middleware adding the timings recorded for a request to its response, as
+/// a `Server-Timing` header.
+///
+/// Timings reveal how the server spends its time, only expose them to
+/// trusted clients, e.g. by applying it to development servers.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::server_timing::ServerTimingMiddleware;
+/// # use std::time::Instant;
+/// async fn users(req: Request<Body>) -> u16 {
+///     let started = Instant::now();
+///     // query the database...
+///     req.server_timing("db", started.elapsed());
+///     200
+/// }
+///
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(ServerTimingMiddleware, vec!["/**"], None))
+///     .configure_router(|r| r.route("/users", Method::GET, users))
+///     .build();
+/// ```
+pub struct ServerTimingMiddleware;
+
+impl ServerTimingMiddleware {
+    async fn next_inner(&self, ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let mut ctx = chain.next(ctx).await?;
+        if let (Some(value), Some(res)) = (ctx.server_timings().header_value(), ctx.state.response_mut()) {
+            if let Ok(value) = http::HeaderValue::from_str(&value) {
+                res.headers_mut().append(SERVER_TIMING, value);
+            }
+        }
+        Ok(ctx)
+    }
+}
+
+impl Middleware for ServerTimingMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, middleware::MiddleChainEnd, request::Request, router::Router};
+
+    #[tokio::test]
+    async fn recorded_timings_are_sent() {
+        let handler = |req: Request<Body>| async move {
+            req.server_timing("db", Duration::from_millis(53));
+            req.server_timing("render", Duration::from_micros(12_500));
+            200
+        };
+        let router = Router::builder().route("/", http::Method::GET, handler).build();
+        let req = Request::new(http::Request::builder().uri("/").body(Body::empty()).unwrap(), None);
+        let ctx = HttpContext::new(req, router);
+        ctx.server_timing("auth", Duration::from_secs(1));
+
+        let mut ctx = ServerTimingMiddleware.next_inner(ctx, &MiddleChainEnd).await.unwrap();
+        let res = ctx.state.take_response().unwrap();
+        assert_eq!(res.headers()[SERVER_TIMING], "auth;dur=1000, db;dur=53, render;dur=12.5");
+    }
+
+    #[test]
+    fn nothing_recorded_sends_nothing() {
+        assert_eq!(ServerTimings::default().header_value(), None);
+    }
+}
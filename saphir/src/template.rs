@@ -0,0 +1,73 @@
+//! Rendering of responses through a template engine.
+//!
+//! Saphir doesn't ship a template engine: any engine can be plugged in by
+//! implementing `TemplateEngine` and registering it on the router. Responses
+//! are then built with `Builder::render`, which renders the template for
+//! browsers and serializes the same data as JSON for API clients.
+
+use crate::{error::SaphirError, responder::media_quality};
+
+/// A template engine, rendering named templates with JSON data
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::template::TemplateEngine;
+/// struct Greeting;
+///
+/// impl TemplateEngine for Greeting {
+///     fn render(&self, template: &str, data: &serde_json::Value) -> Result<String, SaphirError> {
+///         match template {
+///             "hello" => Ok(format!("<h1>Hello {}</h1>", data["name"].as_str().unwrap_or("stranger"))),
+///             _ => Err(SaphirError::Other(format!("Unknown template {}", template))),
+///         }
+///     }
+/// }
+///
+/// let server = Server::builder()
+///     .configure_router(|r| r.template_engine(Greeting))
+///     .build();
+/// ```
+pub trait TemplateEngine: Send + Sync {
+    /// Render `template` with `data`
+    fn render(&self, template: &str, data: &serde_json::Value) -> Result<String, SaphirError>;
+}
+
+/// Representation picked for a rendered response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rendering {
+    Html,
+    Json,
+}
+
+/// Pick between HTML and JSON according to `accept`. HTML wins ties, and is
+/// only offered when a template engine can render it. Returns `None` when
+/// the client accepts neither.
+pub(crate) fn negotiate(accept: Option<&str>, html_available: bool) -> Option<Rendering> {
+    let accept = accept.unwrap_or("*/*");
+    let html = if html_available { media_quality(accept, "text/html") } else { 0.0 };
+    let json = media_quality(accept, "application/json");
+
+    if html == 0.0 && json == 0.0 {
+        None
+    } else if html >= json {
+        Some(Rendering::Html)
+    } else {
+        Some(Rendering::Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_and_json_are_negotiated() {
+        assert_eq!(negotiate(None, true), Some(Rendering::Html));
+        assert_eq!(negotiate(Some("text/html,application/xhtml+xml,*/*;q=0.8"), true), Some(Rendering::Html));
+        assert_eq!(negotiate(Some("application/json"), true), Some(Rendering::Json));
+        assert_eq!(negotiate(Some("text/html;q=0.5, application/json"), true), Some(Rendering::Json));
+        assert_eq!(negotiate(Some("*/*"), false), Some(Rendering::Json));
+        assert_eq!(negotiate(Some("text/html"), false), None);
+        assert_eq!(negotiate(Some("image/png"), true), None);
+    }
+}
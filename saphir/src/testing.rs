@@ -0,0 +1,295 @@
+//! In-process client for testing a server.
+//!
+//! `Server::test_client` turns a built server into a `TestClient`, which
+//! sends requests through the whole stack of the server — middlewares,
+//! router, guards and handlers, then the headers added by the listener —
+//! without binding a socket.
+//!
+//! ```rust
+//! # use saphir::prelude::*;
+//! async fn greet(req: Request<Body>) -> (u16, String) {
+//!     let name = req.headers().get("x-name").and_then(|n| n.to_str().ok()).unwrap_or("world");
+//!     (200, format!("hello {}", name))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let client = Server::builder()
+//!     .configure_router(|r| r.route("/greet", Method::GET, greet))
+//!     .build()
+//!     .test_client();
+//!
+//! let res = client.get("/greet").header("x-name", "saphir").send().await;
+//! assert_eq!(res.status(), 200);
+//! assert_eq!(res.text(), "hello saphir");
+//! # }
+//! ```
+
+use crate::{error::SaphirError, server::StackHandler};
+use http::{header::HeaderName, HeaderMap, HeaderValue, Method, StatusCode, Version};
+use hyper::{body::Bytes, service::Service};
+use std::{convert::TryFrom, net::SocketAddr};
+
+#[cfg(feature = "json")]
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Client sending requests to a server in-process, see `Server::test_client`
+#[derive(Clone)]
+pub struct TestClient {
+    handler: StackHandler,
+}
+
+impl TestClient {
+    pub(crate) fn new(handler: StackHandler) -> Self {
+        TestClient { handler }
+    }
+
+    /// Start a request of `method` to `uri`, a path with an optional query
+    pub fn request(&self, method: Method, uri: &str) -> TestRequest<'_> {
+        TestRequest {
+            client: self,
+            method,
+            uri: uri.to_string(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            peer_addr: None,
+            error: None,
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn post(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::POST, uri)
+    }
+
+    pub fn put(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::PUT, uri)
+    }
+
+    pub fn patch(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::PATCH, uri)
+    }
+
+    pub fn delete(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::DELETE, uri)
+    }
+}
+
+/// A request being built for a `TestClient`. Building errors, like an
+/// invalid header, are reported when the request is sent
+pub struct TestRequest<'c> {
+    client: &'c TestClient,
+    method: Method,
+    uri: String,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+    peer_addr: Option<SocketAddr>,
+    error: Option<String>,
+}
+
+impl<'c> TestRequest<'c> {
+    /// Append a header to the request
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        HeaderValue: TryFrom<V>,
+    {
+        match (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            (Ok(name), Ok(value)) => {
+                self.headers.append(name, value);
+            }
+            _ => self.error = Some("invalid header".to_string()),
+        }
+        self
+    }
+
+    /// Using Feature `form`
+    ///
+    /// Append `query` to the query of the uri, urlencoded
+    #[cfg(feature = "form")]
+    pub fn query<T: serde::Serialize + ?Sized>(mut self, query: &T) -> Self {
+        match serde_urlencoded::to_string(query) {
+            Ok(query) if query.is_empty() => {}
+            Ok(query) => {
+                let separator = if self.uri.contains('?') { '&' } else { '?' };
+                self.uri.push(separator);
+                self.uri.push_str(&query);
+            }
+            Err(e) => self.error = Some(format!("invalid query: {}", e)),
+        }
+        self
+    }
+
+    /// Using Feature `json`
+    ///
+    /// Send `body` serialized as JSON, with a `Content-Type:
+    /// application/json`
+    #[cfg(feature = "json")]
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        match serde_json::to_vec(body) {
+            Ok(body) => {
+                self.body = body.into();
+                self.headers.insert(http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            Err(e) => self.error = Some(format!("invalid json body: {}", e)),
+        }
+        self
+    }
+
+    /// Send `body` as is
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Address the request appears to come from, none by default
+    pub fn peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.peer_addr = Some(addr);
+        self
+    }
+
+    /// Send the request and read the whole response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request couldn't be built, or if the stack failed in a
+    /// way that would have closed the connection of a real client.
+    pub async fn send(self) -> TestResponse {
+        match self.try_send().await {
+            Ok(res) => res,
+            Err(e) => panic!("Test request failed: {:?}", e),
+        }
+    }
+
+    async fn try_send(self) -> Result<TestResponse, SaphirError> {
+        if let Some(error) = self.error {
+            return Err(SaphirError::Other(error));
+        }
+
+        let mut req = http::Request::builder()
+            .method(self.method)
+            .uri(self.uri)
+            .version(self.version)
+            .body(hyper::Body::from(self.body))?;
+        *req.headers_mut() = self.headers;
+
+        let mut handler = self.client.handler.clone().with_peer_addr(self.peer_addr);
+        let res = handler.call(req).await?;
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok(TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+}
+
+/// A response read by a `TestClient`
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The first value of the `name` header, if it is valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|h| h.to_str().ok())
+    }
+
+    /// All the values of the `name` header
+    pub fn header_all(&self, name: &str) -> Vec<&HeaderValue> {
+        self.headers.get_all(name).iter().collect()
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// The body as text, invalid UTF-8 being replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Using Feature `json`
+    ///
+    /// The body deserialized from JSON
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, SaphirError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn client() -> super::TestClient {
+        Server::builder()
+            .configure_listener(|l| l.server_name("TestSaphir"))
+            .configure_router(|r| {
+                r.route("/echo", Method::POST, |mut req: Request<Body>| async move {
+                    let tag = req.headers().get("x-tag").cloned();
+                    let body = req.body_mut().take_as::<Bytes>().await.unwrap_or_default();
+                    let mut builder = Builder::new().status(201).body(body);
+                    if let Some(tag) = tag {
+                        builder = builder.header("x-tag", tag);
+                    }
+                    builder
+                })
+            })
+            .build()
+            .test_client()
+    }
+
+    #[tokio::test]
+    async fn headers_go_through_the_stack() {
+        let res = client().post("/echo").header("x-tag", "t1").body("payload").send().await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.header("x-tag"), Some("t1"));
+        assert_eq!(res.header("server"), Some("TestSaphir"));
+        assert_eq!(res.text(), "payload");
+
+        assert_eq!(client().get("/echo").send().await.status(), 405);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn json_round_trip() {
+        let sent = serde_json::json!({"name": "saphir", "tags": [1, 2]});
+        let res = client().post("/echo").json(&sent).send().await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.json::<serde_json::Value>().unwrap(), sent);
+    }
+
+    #[cfg(feature = "form")]
+    #[tokio::test]
+    async fn query_is_appended() {
+        let client = Server::builder()
+            .configure_router(|r| r.route("/q", Method::GET, |req: Request<Body>| async move { (200, req.uri().to_string()) }))
+            .build()
+            .test_client();
+        let res = client.get("/q?a=1").query(&[("b", "x y")]).send().await;
+        assert_eq!(res.text(), "/q?a=1&b=x+y");
+    }
+}
@@ -0,0 +1,419 @@
+//! Scanning of request bodies while they are uploaded.
+//!
+//! Each chunk of the body goes through a scanner, e.g. a client streaming the
+//! upload to an antivirus, before the handler gets to see it. The body is
+//! never buffered: a chunk is handed to the handler once the scanner accepted
+//! it, and the upload is aborted as soon as the scanner flags one.
+
+use crate::{
+    body::{Body, Bytes},
+    error::SaphirError,
+    http_context::HttpContext,
+    middleware::{Middleware, MiddlewareChain},
+    response::{Builder, Response},
+};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use http::HeaderMap;
+use http_body::{Body as HttpBody, SizeHint};
+use parking_lot::Mutex;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Reason given by a scanner refusing an upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanRejected(pub String);
+
+/// Scanning state of a single upload. A new session is started for every
+/// request, and is handed the chunks of the body in order.
+pub trait ScanSession: Send {
+    /// Scan the next chunk of the body, an error aborts the upload
+    fn scan_chunk(&mut self, chunk: &Bytes) -> BoxFuture<'_, Result<(), ScanRejected>>;
+
+    /// Called once the whole body went through `scan_chunk`, for scanners
+    /// which can only give a verdict at the end. Accepts by default
+    fn finish(&mut self) -> BoxFuture<'_, Result<(), ScanRejected>> {
+        future::ready(Ok(())).boxed()
+    }
+}
+
+/// Middleware passing request bodies through a `ScanSession` before the
+/// handlers read them.
+///
+/// When the scanner rejects a chunk, the body seen by the handler fails
+/// before that chunk, and the request is answered with a
+/// `422 Unprocessable Entity` whatever the handler responded. So is a
+/// request whose body the handler didn't read to its end, since it wasn't
+/// all scanned, unless the handler answered with an error, e.g. a `401` sent
+/// before reading the body, which tells the client the real reason.
+///
+/// ```rust
+/// # use saphir::prelude::*;
+/// # use saphir::upload_scan::{ScanRejected, ScanSession, UploadScanMiddleware};
+/// # use futures::{future::BoxFuture, FutureExt};
+/// struct NoExecutables;
+///
+/// impl ScanSession for NoExecutables {
+///     fn scan_chunk(&mut self, chunk: &Bytes) -> BoxFuture<'_, Result<(), ScanRejected>> {
+///         let flagged = chunk.starts_with(b"MZ");
+///         async move { if flagged { Err(ScanRejected("executable".to_string())) } else { Ok(()) } }.boxed()
+///     }
+/// }
+///
+/// let server = Server::builder()
+///     .configure_middlewares(|m| m.apply(UploadScanMiddleware::new(|| NoExecutables), vec!["/upload/**"], None))
+///     .build();
+/// ```
+pub struct UploadScanMiddleware {
+    new_session: Box<dyn Fn() -> Box<dyn ScanSession> + Send + Sync>,
+}
+
+impl UploadScanMiddleware {
+    /// Scan uploads with the sessions produced by `new_session`
+    pub fn new<F, S>(new_session: F) -> Self
+    where
+        F: 'static + Fn() -> S + Send + Sync,
+        S: 'static + ScanSession,
+    {
+        UploadScanMiddleware {
+            new_session: Box::new(move || Box::new(new_session()) as Box<dyn ScanSession>),
+        }
+    }
+
+    async fn next_inner(&self, mut ctx: HttpContext, chain: &dyn MiddlewareChain) -> Result<HttpContext, SaphirError> {
+        let verdict = match scan_request_body(&mut ctx, (self.new_session)()).await {
+            Some(verdict) => verdict,
+            None => {
+                ctx.after(Builder::new().status(422).build()?);
+                return Ok(ctx);
+            }
+        };
+
+        let mut ctx = chain.next(ctx).await?;
+        if verdict.rejects(ctx.state.response()) {
+            ctx.after(Builder::new().status(422).build()?);
+        }
+        Ok(ctx)
+    }
+}
+
+impl Middleware for UploadScanMiddleware {
+    fn next(&'static self, ctx: HttpContext, chain: &'static dyn MiddlewareChain) -> BoxFuture<'static, Result<HttpContext, SaphirError>> {
+        self.next_inner(ctx, chain).boxed()
+    }
+}
+
+/// Outcome of the scan of a request body, shared by the scanned body and
+/// the middleware acting on it
+#[derive(Clone, Default)]
+pub(crate) struct ScanVerdict(Arc<Mutex<Option<bool>>>);
+
+impl ScanVerdict {
+    /// Whether `res`, the response of the handler, is to be replaced by a
+    /// rejection: the body was rejected, or the handler succeeded without
+    /// reading it to its end, so it wasn't all scanned
+    pub(crate) fn rejects(&self, res: Option<&Response>) -> bool {
+        match *self.0.lock() {
+            Some(accepted) => !accepted,
+            None => {
+                let succeeded = res.map(|res| res.status().is_success()).unwrap_or(true);
+                if succeeded {
+                    debug!("Upload not read to its end by the handler, rejecting it since it wasn't all scanned");
+                }
+                succeeded
+            }
+        }
+    }
+
+    fn set(&self, accepted: bool) {
+        *self.0.lock() = Some(accepted);
+    }
+}
+
+/// Pass the body of the request of `ctx` through `session`. An empty body
+/// is judged right away, `None` meaning it was rejected
+pub(crate) async fn scan_request_body(ctx: &mut HttpContext, mut session: Box<dyn ScanSession>) -> Option<ScanVerdict> {
+    let verdict = ScanVerdict::default();
+    let req = ctx.state.request_unchecked_mut();
+    if req.body().size_hint().exact() == Some(0) {
+        return match session.finish().await {
+            Ok(()) => {
+                verdict.set(true);
+                Some(verdict)
+            }
+            Err(ScanRejected(reason)) => {
+                info!("Upload rejected by the scanner: {}", reason);
+                None
+            }
+        };
+    }
+
+    let body = std::mem::take(req.body_mut());
+    let scanned = verdict.clone();
+    *req.body_mut() = body.wrapped(move |body| ScannedBody {
+        inner: body,
+        state: Mutex::new(ScanState::Idle(session)),
+        verdict: scanned,
+    });
+    Some(verdict)
+}
+
+type ChunkScan = BoxFuture<'static, (Box<dyn ScanSession>, Bytes, Result<(), ScanRejected>)>;
+
+enum ScanState {
+    /// Waiting for the next chunk of the body
+    Idle(Box<dyn ScanSession>),
+    /// Scanning a chunk, handed over once accepted
+    Scanning(ChunkScan),
+    /// Waiting for the verdict on the whole body
+    Finishing(BoxFuture<'static, Result<(), ScanRejected>>),
+    Done,
+}
+
+/// Body forwarding the chunks of `inner` accepted by the scanner, along with
+/// its size hint and trailers, and failing on the first refused one
+struct ScannedBody {
+    inner: Body,
+    // Only locked by `&self` methods, polling goes through `get_mut`
+    state: Mutex<ScanState>,
+    verdict: ScanVerdict,
+}
+
+impl ScannedBody {
+    fn reject(&mut self, reason: String) -> Poll<Option<Result<Bytes, SaphirError>>> {
+        info!("Upload rejected by the scanner: {}", reason);
+        self.verdict.set(false);
+        Poll::Ready(Some(Err(SaphirError::Other(format!("Upload rejected by the scanner: {}", reason)))))
+    }
+}
+
+impl HttpBody for ScannedBody {
+    type Data = Bytes;
+    type Error = SaphirError;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            match std::mem::replace(this.state.get_mut(), ScanState::Done) {
+                ScanState::Idle(mut session) => match Pin::new(&mut this.inner).poll_data(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        *this.state.get_mut() = ScanState::Scanning(
+                            async move {
+                                let scanned = session.scan_chunk(&chunk).await;
+                                (session, chunk, scanned)
+                            }
+                            .boxed(),
+                        );
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        debug!("Unable to read an upload being scanned: {:?}", e);
+                        this.verdict.set(false);
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Ready(None) => *this.state.get_mut() = ScanState::Finishing(async move { session.finish().await }.boxed()),
+                    Poll::Pending => {
+                        *this.state.get_mut() = ScanState::Idle(session);
+                        return Poll::Pending;
+                    }
+                },
+                ScanState::Scanning(mut scan) => match scan.as_mut().poll(cx) {
+                    Poll::Ready((session, chunk, Ok(()))) => {
+                        *this.state.get_mut() = ScanState::Idle(session);
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Poll::Ready((_, _, Err(ScanRejected(reason)))) => return this.reject(reason),
+                    Poll::Pending => {
+                        *this.state.get_mut() = ScanState::Scanning(scan);
+                        return Poll::Pending;
+                    }
+                },
+                ScanState::Finishing(mut finish) => match finish.as_mut().poll(cx) {
+                    Poll::Ready(Ok(())) => {
+                        this.verdict.set(true);
+                        return Poll::Ready(None);
+                    }
+                    Poll::Ready(Err(ScanRejected(reason))) => return this.reject(reason),
+                    Poll::Pending => {
+                        *this.state.get_mut() = ScanState::Finishing(finish);
+                        return Poll::Pending;
+                    }
+                },
+                ScanState::Done => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(*self.state.lock(), ScanState::Done)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::MiddleChainEnd, request::Request, router::Router};
+    use hyper::body::Body as RawBody;
+    use std::collections::VecDeque;
+
+    /// Rejects a body containing `EICAR`, even across chunks
+    struct PatternScanner {
+        tail: Vec<u8>,
+    }
+
+    impl ScanSession for PatternScanner {
+        fn scan_chunk(&mut self, chunk: &Bytes) -> BoxFuture<'_, Result<(), ScanRejected>> {
+            let mut window = std::mem::take(&mut self.tail);
+            window.extend_from_slice(chunk);
+            let flagged = window.windows(5).any(|w| w == b"EICAR");
+            self.tail = window[window.len().saturating_sub(4)..].to_vec();
+            future::ready(if flagged {
+                Err(ScanRejected("EICAR test signature".to_string()))
+            } else {
+                Ok(())
+            })
+            .boxed()
+        }
+    }
+
+    async fn upload(chunks: Vec<&'static str>) -> (u16, Vec<Result<Bytes, ()>>) {
+        let middleware: &'static UploadScanMiddleware = Box::leak(Box::new(UploadScanMiddleware::new(|| PatternScanner { tail: Vec::new() })));
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler_received = received.clone();
+        let handler = move |mut req: Request<Body>| {
+            let received = handler_received.clone();
+            async move {
+                while let Some(chunk) = req.body_mut().data().await {
+                    let failed = chunk.is_err();
+                    received.lock().unwrap().push(chunk.map_err(|_| ()));
+                    if failed {
+                        return 500;
+                    }
+                }
+                201
+            }
+        };
+
+        let router = Router::builder().route("/upload", http::Method::POST, handler).build();
+        let body = RawBody::wrap_stream(futures::stream::iter(chunks.into_iter().map(|c| Ok::<_, std::io::Error>(Bytes::from(c)))));
+        let req = http::Request::builder()
+            .method(http::Method::POST)
+            .uri("/upload")
+            .body(Body::from_raw(body))
+            .unwrap();
+
+        let mut ctx = middleware
+            .next_inner(HttpContext::new(Request::new(req, None), router), &MiddleChainEnd)
+            .await
+            .unwrap();
+        let status = ctx.state.take_response().unwrap().status().as_u16();
+        let received = std::mem::take(&mut *received.lock().unwrap());
+        (status, received)
+    }
+
+    #[tokio::test]
+    async fn clean_upload_reaches_the_handler() {
+        let (status, received) = upload(vec!["first ", "second ", "third"]).await;
+        assert_eq!(status, 201);
+        assert_eq!(received, vec![Ok(Bytes::from("first ")), Ok(Bytes::from("second ")), Ok(Bytes::from("third"))]);
+    }
+
+    #[tokio::test]
+    async fn flagged_upload_is_aborted_mid_stream() {
+        let (status, received) = upload(vec!["first ", "with EI", "CAR inside ", "never sent"]).await;
+        assert_eq!(status, 422);
+        assert_eq!(received, vec![Ok(Bytes::from("first ")), Ok(Bytes::from("with EI")), Err(())]);
+    }
+
+    /// Body of a known size sending trailers after its chunks
+    struct TrailingBody(VecDeque<Bytes>);
+
+    impl HttpBody for TrailingBody {
+        type Data = Bytes;
+        type Error = SaphirError;
+
+        fn poll_data(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, SaphirError>>> {
+            Poll::Ready(self.get_mut().0.pop_front().map(Ok))
+        }
+
+        fn poll_trailers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, SaphirError>> {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", http::HeaderValue::from_static("abc"));
+            Poll::Ready(Ok(Some(trailers)))
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            SizeHint::with_exact(self.0.iter().map(|c| c.len() as u64).sum())
+        }
+    }
+
+    #[tokio::test]
+    async fn scanned_body_keeps_its_size_and_trailers() {
+        let middleware: &'static UploadScanMiddleware = Box::leak(Box::new(UploadScanMiddleware::new(|| PatternScanner { tail: Vec::new() })));
+        let handler = |mut req: Request<Body>| async move {
+            let size = req.body().size_hint().exact();
+            let mut body = Vec::new();
+            while let Some(chunk) = req.body_mut().data().await {
+                body.extend_from_slice(&chunk.unwrap());
+            }
+            let trailers = req.body_mut().trailers().await.unwrap().unwrap();
+            format!("{:?} {} {:?}", size, String::from_utf8(body).unwrap(), trailers["x-checksum"])
+        };
+        let router = Router::builder().route("/upload", http::Method::POST, handler).build();
+        let body = TrailingBody(vec![Bytes::from("first "), Bytes::from("second")].into());
+        let req = http::Request::builder()
+            .method(http::Method::POST)
+            .uri("/upload")
+            .body(Body::from_http_body(body))
+            .unwrap();
+
+        let mut ctx = middleware
+            .next_inner(HttpContext::new(Request::new(req, None), router), &MiddleChainEnd)
+            .await
+            .unwrap();
+        let res = ctx.state.take_response().unwrap();
+        assert_eq!(res.status(), 200);
+        let body = hyper::body::to_bytes(res.into_raw().unwrap().into_body().into_raw()).await.unwrap();
+        assert_eq!(body, "Some(12) first second \"abc\"");
+    }
+
+    #[tokio::test]
+    async fn unread_upload_is_rejected() {
+        let middleware: &'static UploadScanMiddleware = Box::leak(Box::new(UploadScanMiddleware::new(|| PatternScanner { tail: Vec::new() })));
+        let router = Router::builder()
+            .route("/upload", http::Method::POST, |_req: Request<Body>| async { 201 })
+            .route("/private", http::Method::POST, |_req: Request<Body>| async { 401 })
+            .build();
+        let upload = |uri: &'static str, body: Body| {
+            let req = http::Request::builder().method(http::Method::POST).uri(uri).body(body).unwrap();
+            let ctx = HttpContext::new(Request::new(req, None), router.clone());
+            async move {
+                let mut ctx = middleware.next_inner(ctx, &MiddleChainEnd).await.unwrap();
+                ctx.state.take_response().unwrap().status().as_u16()
+            }
+        };
+
+        let unread = || {
+            Body::from_raw(RawBody::wrap_stream(futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(
+                "unscanned",
+            ))])))
+        };
+        assert_eq!(upload("/upload", unread()).await, 422);
+        // There is nothing to read from an empty body
+        assert_eq!(upload("/upload", Body::empty()).await, 201);
+        // The handler rejecting the request before reading it is answered
+        assert_eq!(upload("/private", unread()).await, 401);
+    }
+}
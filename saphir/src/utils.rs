@@ -46,6 +46,14 @@ impl EndpointResolver {
         })
     }
 
+    pub fn has_method(&self, m: &Method) -> bool {
+        if m.is_any() {
+            self.allow_any_method
+        } else {
+            self.methods.contains(m)
+        }
+    }
+
     pub fn add_method(&mut self, m: Method) {
         if !self.allow_any_method && m.is_any() {
             self.allow_any_method = true;
@@ -1,4 +1,4 @@
-use crate::controller::handler::{HandlerAttrs, HandlerRepr};
+use crate::controller::handler::{HandlerAttrs, HandlerRepr, RateLimitDef};
 use proc_macro2::{Ident, TokenStream};
 use syn::{AttributeArgs, Error, ItemImpl, Lit, Meta, MetaNameValue, NestedMeta, Result};
 
@@ -109,9 +109,9 @@ fn gen_controller_handlers_fn(attr: &ControllerAttr, handlers: &[HandlerRepr]) -
         let HandlerAttrs { methods_paths, guards, .. } = &handler.attrs;
         let handler_ident = handler.original_method.sig.ident.clone();
 
-        for (method, path) in methods_paths {
+        for (method, path, rate_limit) in methods_paths {
             let method = method.as_str();
-            if guards.is_empty() {
+            if guards.is_empty() && rate_limit.is_none() {
                 let handler_e = quote! {
                     let b = b.add(Method::from_str(#method).expect("Method was validated by the macro expansion"), #path, #ctrl_ident::#handler_ident);
                 };
@@ -119,6 +119,14 @@ fn gen_controller_handlers_fn(attr: &ControllerAttr, handlers: &[HandlerRepr]) -
             } else {
                 let mut guard_stream = TokenStream::new();
 
+                // Each route gets its own limit, so its clients are counted apart
+                if let Some(RateLimitDef { requests, window_secs }) = rate_limit {
+                    (quote! {
+                        let g = g.apply(saphir::rate_limit::RateLimit::new(#requests, std::time::Duration::from_secs(#window_secs)));
+                    })
+                    .to_tokens(&mut guard_stream);
+                }
+
                 for guard_def in guards {
                     guard_def.to_tokens(&mut guard_stream);
                     (quote! {
@@ -4,8 +4,8 @@ use http::Method;
 use proc_macro2::{Ident, Span, TokenStream};
 use quote::quote;
 use syn::{
-    export::ToTokens, Attribute, Error, Expr, FnArg, GenericArgument, ImplItem, ImplItemMethod, ItemImpl, Lit, Meta, MetaNameValue, NestedMeta, Pat, PatIdent,
-    PatType, Path, PathArguments, PathSegment, Result, ReturnType, Type, TypePath,
+    export::ToTokens, Attribute, Error, Expr, FnArg, GenericArgument, ImplItem, ImplItemMethod, ItemImpl, Lit, LitStr, Meta, MetaNameValue, NestedMeta, Pat,
+    PatIdent, PatType, Path, PathArguments, PathSegment, Result, ReturnType, Type, TypePath,
 };
 
 #[derive(Clone, Debug)]
@@ -35,6 +35,7 @@ pub enum ArgsReprType {
     Cookie,
     Ext,
     Extensions,
+    Headers,
     Option(Box<ArgsReprType>),
 }
 
@@ -49,6 +50,7 @@ impl ArgsReprType {
             "Multipart" => Ok(ArgsReprType::Multipart),
             "Ext" => Ok(ArgsReprType::Ext),
             "Extensions" => Ok(ArgsReprType::Extensions),
+            "Headers" => Ok(ArgsReprType::Headers),
             "Option" => {
                 if let PathArguments::AngleBracketed(a) = &p.arguments {
                     let a = a.args.first().ok_or_else(|| Error::new_spanned(a, "Option types need an type argument"))?;
@@ -64,7 +66,7 @@ impl ArgsReprType {
                 Err(Error::new_spanned(p, "Invalid option type"))
             }
             _params => Ok(ArgsReprType::Params {
-                is_query_param: !attrs.methods_paths.iter().any(|(_, path)| path.contains(name)),
+                is_query_param: !attrs.methods_paths.iter().any(|(_, path, _)| path.contains(name)),
                 is_string: typ_ident_str.eq("String"),
             }),
         }
@@ -305,9 +307,43 @@ impl ToTokens for GuardDef {
     }
 }
 
+/// Limit of `requests` per `window_secs` set by the `rate_limit` parameter of
+/// a route attribute
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitDef {
+    pub requests: u32,
+    pub window_secs: u64,
+}
+
+impl RateLimitDef {
+    /// Parse a limit like `5/min` or `100/10s`, the format of
+    /// `saphir::rate_limit::RateLimit`
+    fn parse(lit: &LitStr) -> Result<Self> {
+        let invalid = || Error::new_spanned(lit, "Invalid rate limit, expected something like `5/min`");
+        let value = lit.value();
+        let mut parts = value.trim().splitn(2, '/');
+        let requests = parts.next().and_then(|r| r.trim().parse::<u32>().ok()).ok_or_else(invalid)?;
+        let window = parts.next().ok_or_else(invalid)?.trim();
+        let unit_start = window.find(|c: char| !c.is_ascii_digit()).unwrap_or(window.len());
+        let count = match &window[..unit_start] {
+            "" => 1,
+            count => count.parse::<u64>().ok().filter(|c| *c > 0).ok_or_else(invalid)?,
+        };
+        let unit = match &window[unit_start..] {
+            "s" | "sec" | "second" => 1,
+            "m" | "min" | "minute" => 60,
+            "h" | "hour" => 3_600,
+            "d" | "day" => 86_400,
+            _ => return Err(invalid()),
+        };
+        let window_secs = count.checked_mul(unit).ok_or_else(invalid)?;
+        Ok(RateLimitDef { requests, window_secs })
+    }
+}
+
 #[derive(Clone)]
 pub struct HandlerAttrs {
-    pub methods_paths: Vec<(Method, String)>,
+    pub methods_paths: Vec<(Method, String, Option<RateLimitDef>)>,
     pub guards: Vec<GuardDef>,
     pub cookie: bool,
 }
@@ -415,7 +451,24 @@ impl HandlerAttrs {
                                     return Err(Error::new_spanned(str, "Path must start with '/'"));
                                 }
 
-                                methods_paths.push((method, path));
+                                let mut rate_limit = None;
+                                for n in l.nested.iter().skip(1) {
+                                    match n {
+                                        NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                                            path, lit: Lit::Str(limit), ..
+                                        })) if path.is_ident("rate_limit") => {
+                                            rate_limit = Some(RateLimitDef::parse(limit)?);
+                                        }
+                                        _ => {
+                                            return Err(Error::new_spanned(
+                                                n,
+                                                "Unexpected parameter for method, expected `rate_limit = \"<requests>/<window>\"`",
+                                            ))
+                                        }
+                                    }
+                                }
+
+                                methods_paths.push((method, path, rate_limit));
                             } else {
                                 return Err(Error::new_spanned(l, "Missing path for method"));
                             }
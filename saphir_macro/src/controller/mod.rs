@@ -116,7 +116,7 @@ fn gen_query_load(stream: &mut TokenStream, opts: &HandlerWrapperOpt) {
     if opts.parse_query {
         (quote! {
 
-        let mut query = req.uri().query().map(|query_str| serde_urlencoded::from_str::<HashMap<String, String>>(query_str)).transpose()?.unwrap_or_default();
+        let mut query = req.uri().query().map(|query_str| serde_urlencoded::from_str::<HashMap<String, String>>(query_str)).transpose().map_err(saphir::error::ExtractError::InvalidForm)?.unwrap_or_default();
         })
         .to_tokens(stream);
     }
@@ -239,6 +239,7 @@ impl ArgsRepr {
             ArgsReprType::Cookie => self_flatten.gen_cookie_param(stream),
             ArgsReprType::Ext => self_flatten.gen_ext_param(stream, optional),
             ArgsReprType::Extensions => self_flatten.gen_extensions_param(stream),
+            ArgsReprType::Headers => self_flatten.gen_headers_param(stream),
             ArgsReprType::Params { is_query_param, .. } => {
                 if *is_query_param {
                     self_flatten.gen_query_param(stream, optional);
@@ -289,18 +290,18 @@ impl ArgsRepr {
 
         (quote! {
 
-            let #id = if let Some(form) = req.uri().query().map(|query_str| serde_urlencoded::from_str::<#typ>(query_str).map_err(SaphirError::SerdeUrlDe)) {
-                Some(form.map(|x| Form(x)))
+            let #id = if let Some(form) = req.uri().query().map(|query_str| serde_urlencoded::from_str::<#typ>(query_str).map_err(|e| SaphirError::from(saphir::error::ExtractError::InvalidForm(e)))) {
+                form.map(|x| Form(x))
             } else {
-                Some(req.body_mut().take_as::<#typ_raw>().await.map(|x| Form(x)))
-            }.transpose()
+                req.body_mut().take_as::<#typ_raw>().await.map(|x| Form(x))
+            }
         })
         .to_tokens(stream);
 
         if optional {
-            (quote! {.ok().flatten()}).to_tokens(stream);
+            (quote! {.ok()}).to_tokens(stream);
         } else {
-            (quote! {?.ok_or_else(|| SaphirError::MissingParameter("form body".to_string(), false))?}).to_tokens(stream);
+            (quote! {?}).to_tokens(stream);
         }
 
         (quote! {;}).to_tokens(stream);
@@ -360,6 +361,15 @@ impl ArgsRepr {
         .to_tokens(stream);
     }
 
+    fn gen_headers_param(&self, stream: &mut TokenStream) {
+        let id = Ident::new(self.name.as_str(), Span::call_site());
+        (quote! {
+
+            let #id = Headers::from_request(&mut req).await.map_err(|e| SaphirError::responder(e))?;
+        })
+        .to_tokens(stream);
+    }
+
     fn gen_path_param(&self, stream: &mut TokenStream, optional: bool) {
         let name = self.name.as_str();
         let id = Ident::new(self.name.as_str(), Span::call_site());
@@ -369,7 +379,7 @@ impl ArgsRepr {
         })
         .to_tokens(stream);
 
-        self.gen_param_str_parsing(stream, name, optional);
+        self.gen_param_str_parsing(stream, name, quote! {saphir::error::ParameterLocation::Path}, optional);
 
         (quote! {;}).to_tokens(stream);
     }
@@ -383,19 +393,20 @@ impl ArgsRepr {
         })
         .to_tokens(stream);
 
-        self.gen_param_str_parsing(stream, name, optional);
+        self.gen_param_str_parsing(stream, name, quote! {saphir::error::ParameterLocation::Query}, optional);
 
         (quote! {;}).to_tokens(stream);
     }
 
-    fn gen_param_str_parsing(&self, stream: &mut TokenStream, name: &str, optional: bool) {
+    fn gen_param_str_parsing(&self, stream: &mut TokenStream, name: &str, location: TokenStream, optional: bool) {
         if !self.is_string() && self.typ.is_some() {
             let typ = self.typ.as_ref().unwrap();
-            (quote! {.map(|p| p.parse::<#typ>()).transpose().map_err(|_| SaphirError::InvalidParameter(#name.to_string(), false))?}).to_tokens(stream);
+            (quote! {.map(|p| p.parse::<#typ>()).transpose().map_err(|_| saphir::error::ExtractError::InvalidParameter { name: #name.to_string(), location: #location })?})
+                .to_tokens(stream);
         }
 
         if !optional {
-            (quote! {.ok_or_else(|| SaphirError::MissingParameter(#name.to_string(), false))?}).to_tokens(stream);
+            (quote! {.ok_or_else(|| saphir::error::ExtractError::MissingParameter { name: #name.to_string(), location: #location })?}).to_tokens(stream);
         }
     }
 }